@@ -0,0 +1,137 @@
+//! Captures of the packets written to the devices and of their replies, recorded with `--record` and sent again by
+//! the `replay` command.
+//!
+//! A capture has one JSON object per packet, the time is in seconds since the recording started:
+//! ```json
+//! {"time":1.250,"device":"AK620 DIGITAL","direction":"out","data":"10 13 05 00 04 05 00"}
+//! {"time":1.251,"device":"AK620 DIGITAL","direction":"in","data":"10 55"}
+//! ```
+
+use crate::{devices::hex, json, log::warning};
+use std::{
+    fs::{read_to_string, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+/// Direction of a packet.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Direction {
+    /// Written to the device
+    Out,
+    /// Reply read from the device
+    In,
+}
+
+/// Capture being recorded, shared by the devices.
+pub struct Capture {
+    file: Mutex<File>,
+    path: PathBuf,
+    start: Instant,
+    /// The last write failed, only the first error in a row is reported
+    failing: AtomicBool,
+}
+
+/// Packet read from a capture.
+pub struct Frame {
+    /// Time since the recording started
+    pub time: Duration,
+    /// Product name of the device
+    pub device: String,
+    pub direction: Direction,
+    pub data: Vec<u8>,
+}
+
+impl Capture {
+    /// Starts a new capture, the file is replaced if it exists.
+    pub fn create(path: &Path) -> io::Result<Self> {
+        Ok(Capture {
+            file: Mutex::new(File::create(path)?),
+            path: path.to_owned(),
+            start: Instant::now(),
+            failing: AtomicBool::new(false),
+        })
+    }
+
+    /// Appends a packet to the capture.
+    pub fn record(&self, device: &str, direction: Direction, data: &[u8]) {
+        let line = format!(
+            "{{\"time\":{:.3},\"device\":\"{}\",\"direction\":\"{}\",\"data\":\"{}\"}}\n",
+            self.start.elapsed().as_secs_f64(),
+            json::escape(device),
+            if direction == Direction::Out { "out" } else { "in" },
+            hex(data)
+        );
+        match self.file.lock().unwrap().write_all(line.as_bytes()) {
+            Ok(()) => self.failing.store(false, Ordering::Relaxed),
+            Err(err) if !self.failing.swap(true, Ordering::Relaxed) => {
+                warning!("Failed to write to {}: {err}", self.path.display());
+            }
+            Err(_) => (),
+        }
+    }
+}
+
+/// Reads the packets of a capture.
+pub fn load(path: &Path) -> Result<Vec<Frame>, String> {
+    let text = read_to_string(path).map_err(|err| format!("Failed to read {}: {err}", path.display()))?;
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| parse_frame(line).ok_or(format!("{}:{}: invalid packet", path.display(), index + 1)))
+        .collect()
+}
+
+/// Parses a line of a capture.
+fn parse_frame(line: &str) -> Option<Frame> {
+    let time: f64 = value(line, "time")?.parse().ok()?;
+    let direction = match value(line, "direction")?.as_str() {
+        "out" => Direction::Out,
+        "in" => Direction::In,
+        _ => return None,
+    };
+    let data = value(line, "data")?
+        .split_whitespace()
+        .map(|byte| u8::from_str_radix(byte, 16).ok())
+        .collect::<Option<_>>()?;
+
+    Some(Frame {
+        time: Duration::try_from_secs_f64(time).ok()?,
+        device: value(line, "device")?,
+        direction,
+        data,
+    })
+}
+
+/// Returns the value of a key of a JSON object written by `Capture::record`, a string or a number.
+fn value(line: &str, key: &str) -> Option<String> {
+    let rest = &line[line.find(&format!("\"{key}\":"))? + key.len() + 3..];
+    let Some(rest) = rest.strip_prefix('"') else {
+        let end = rest.find([',', '}']).unwrap_or(rest.len());
+        return Some(rest[..end].trim().to_string());
+    };
+
+    let mut value = String::new();
+    let mut chars = rest.chars();
+    loop {
+        match chars.next()? {
+            '"' => return Some(value),
+            '\\' => match chars.next()? {
+                'n' => value.push('\n'),
+                'r' => value.push('\r'),
+                't' => value.push('\t'),
+                'u' => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    value.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                }
+                c => value.push(c),
+            },
+            c => value.push(c),
+        }
+    }
+}
@@ -0,0 +1,384 @@
+//! Command line arguments, and the display settings they select with the settings file.
+
+use crate::{
+    completions,
+    config::{self, Config},
+    control, devices,
+    monitor::{
+        self,
+        gpu::{self, Card, PciAddress},
+        metric_name,
+    },
+    service, udev,
+};
+use clap::{Parser, Subcommand};
+use std::{ffi::CString, path::PathBuf, time::Duration};
+
+#[derive(Parser, Debug, Clone)]
+#[command(version, about, long_about = None)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Commands>,
+
+    /// Change the display mode between "temp, usage, auto", or cycle through a list with optional seconds for each
+    /// mode (e.g. "temp:10,usage:3")
+    #[arg(short, long, env = "DEEPCOOL_MODE", default_value_t = String::from("temp"))]
+    pub mode: String,
+
+    /// Change temperature unit to Fahrenheit
+    #[arg(short, long, env = "DEEPCOOL_FAHRENHEIT")]
+    pub fahrenheit: bool,
+
+    /// Read the CPU temperature from this hwmon file (e.g. "/sys/class/hwmon/hwmon2/temp1_input") instead of looking
+    /// up the sensor
+    #[arg(long, env = "DEEPCOOL_SENSOR", value_name = "PATH")]
+    pub sensor: Option<PathBuf>,
+
+    /// Time between two updates, e.g. "500ms", "1s" or "2.5s", a plain number is in milliseconds [default: depends on
+    /// the device]
+    #[arg(long, env = "DEEPCOOL_POLL", value_name = "DURATION", value_parser = config::parse_poll)]
+    pub poll: Option<Duration>,
+
+    /// Slow the updates down to up to 4 seconds while the CPU is idle and the readings are stable, they speed up again
+    /// as soon as the readings change
+    #[arg(long, env = "DEEPCOOL_ADAPTIVE_POLL")]
+    pub adaptive_poll: bool,
+
+    /// Time the utilization and the power are averaged over, independently of the update interval (e.g. "1s" to show
+    /// the average of the last second at every update) [default: the update interval]
+    #[arg(long, env = "DEEPCOOL_SAMPLE_WINDOW", value_name = "DURATION", value_parser = config::parse_poll)]
+    pub sample_window: Option<Duration>,
+
+    /// Show this metric instead of the CPU reading of its unit, e.g. one defined in the settings file, repeat it for
+    /// metrics of different units
+    #[arg(long, value_name = "NAME")]
+    pub metric: Vec<String>,
+
+    /// Read the GPU metrics from the card at this position among the cards of its driver, in the order of nvidia-smi
+    /// [default: the first card that has the metric]
+    #[arg(long, env = "DEEPCOOL_GPU_INDEX", value_name = "INDEX")]
+    pub gpu_index: Option<u32>,
+
+    /// Read the GPU metrics from the card at this PCI address (e.g. "0000:03:00.0", see lspci -D)
+    #[arg(long, env = "DEEPCOOL_GPU_PCI", value_name = "ADDRESS", value_parser = parse_pci, conflicts_with = "gpu_index")]
+    pub gpu_pci: Option<PciAddress>,
+
+    /// Read the drive temperature from this drive, by name (e.g. "nvme1" or "sda") or by a part of its model
+    /// [default: the first NVMe drive]
+    #[arg(long, env = "DEEPCOOL_DRIVE", value_name = "DRIVE")]
+    pub drive: Option<String>,
+
+    /// Seconds each mode is shown for when cycling through several modes
+    #[arg(long, default_value_t = 6, value_name = "SECONDS")]
+    pub cycle_interval: u64,
+
+    /// Enable the alarm
+    #[arg(short, long, env = "DEEPCOOL_ALARM")]
+    pub alarm: bool,
+
+    /// Temperature in ˚C above which the alarm goes off
+    #[arg(long, env = "DEEPCOOL_ALARM_TEMP", default_value_t = 85)]
+    pub alarm_temp: u8,
+
+    /// Temperature in ˚C at which the alarm is cleared again [default: the alarm temperature]
+    #[arg(long, env = "DEEPCOOL_ALARM_CLEAR")]
+    pub alarm_clear: Option<u8>,
+
+    /// Only use the device with this product ID (hex "0x0001" or decimal "1")
+    #[arg(long, env = "DEEPCOOL_PID", value_parser = parse_pid)]
+    pub pid: Option<u16>,
+
+    /// Only use the device with this serial number
+    #[arg(long, env = "DEEPCOOL_SERIAL")]
+    pub serial: Option<String>,
+
+    /// Force the packet format of a known device, useful for testing devices that are not supported yet
+    #[arg(long, value_parser = devices::PROTOCOLS)]
+    pub protocol: Option<String>,
+
+    /// Brightness of the display in percent, on devices that can be dimmed
+    #[arg(short, long, env = "DEEPCOOL_BRIGHTNESS", default_value_t = 100, value_parser = clap::value_parser!(u8).range(0..=100))]
+    pub brightness: u8,
+
+    /// Number of consecutive failed writes before a device is given up
+    #[arg(long, default_value_t = 5)]
+    pub max_retries: u32,
+
+    /// Time in milliseconds a write may take before the device is considered stuck and reconnected
+    #[arg(long, default_value_t = 1000, value_name = "MS")]
+    pub write_timeout: u64,
+
+    /// Open this hidraw node (e.g. "/dev/hidraw3" or "hidraw3") instead of looking up the devices, the program stops
+    /// once it is unplugged
+    #[arg(short, long, value_parser = parse_device)]
+    pub device: Option<CString>,
+
+    /// Print the packets instead of sending them, no device is needed as the packet format is picked with --protocol
+    /// or --pid
+    #[arg(long, conflicts_with_all = ["daemon", "device"])]
+    pub dry_run: bool,
+
+    /// Send a single update to the devices and exit, e.g. from cron or a udev rule
+    #[arg(long, conflicts_with_all = ["daemon", "wait"])]
+    pub once: bool,
+
+    /// Wait for a device to be plugged in instead of exiting when none is found
+    #[arg(short, long)]
+    pub wait: bool,
+
+    /// Turn the display off while the session is idle or the screen is locked (needs systemd-logind)
+    #[arg(long)]
+    pub blank_when_idle: bool,
+
+    /// Accept commands on a Unix socket [default path: /run/deepcool/control.sock]
+    #[arg(long, value_name = "PATH", num_args = 0..=1, default_missing_value = control::SOCKET_PATH)]
+    pub socket: Option<PathBuf>,
+
+    /// Publish the org.deepcool.Display service on the system D-Bus
+    #[arg(long)]
+    pub dbus: bool,
+
+    /// Serve the readings over HTTP on this address (e.g. "0.0.0.0:9123"), for Prometheus on /metrics and as JSON
+    /// on /status
+    #[arg(long, value_name = "ADDRESS")]
+    pub metrics_listen: Option<String>,
+
+    /// Append the readings to this CSV file every second (e.g. "/var/log/deepcool/metrics.csv")
+    #[arg(long, value_name = "PATH")]
+    pub log_csv: Option<PathBuf>,
+
+    /// Record the packets written to the devices and their replies to this file, they can be sent again with replay
+    #[arg(long, value_name = "PATH")]
+    pub record: Option<PathBuf>,
+
+    /// Run in the background, for init systems like OpenRC or runit that do not do it themselves
+    #[arg(long)]
+    pub daemon: bool,
+
+    /// Write the PID of the program to this file (e.g. "/run/deepcool.pid")
+    #[arg(long, value_name = "PATH")]
+    pub pidfile: Option<PathBuf>,
+
+    /// Append the messages to this file when running in the background, they are discarded otherwise
+    #[arg(long, value_name = "PATH", requires = "daemon")]
+    pub log_file: Option<PathBuf>,
+
+    /// Switch to this user once the devices are open, devices plugged in or reconnected later have to be accessible
+    /// to it (see install-udev-rules)
+    #[arg(long, env = "DEEPCOOL_USER", value_name = "NAME")]
+    pub user: Option<String>,
+
+    /// Only print the errors, e.g. when running as a service
+    #[arg(short, long, conflicts_with_all = ["verbose", "debug"])]
+    pub quiet: bool,
+
+    /// Log the readings sent at every update
+    #[arg(short, long)]
+    pub verbose: bool,
+
+    /// Log every packet written and the replies of the devices, implies --verbose
+    #[arg(long)]
+    pub debug: bool,
+
+    /// Format of the log messages, "json" writes one object per line for log collectors
+    #[arg(long, default_value = "text", value_parser = ["text", "json"])]
+    pub log_format: String,
+
+    /// Read the settings from this file only [default: /etc/deepcool-digital.toml, then ~/.config/deepcool-digital/config.toml]
+    #[arg(short, long, env = "DEEPCOOL_CONFIG")]
+    pub config: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum Commands {
+    /// List the connected DeepCool devices and whether they are supported
+    ListDevices,
+    /// Print the HID details of the DeepCool devices to attach to a bug report
+    Probe,
+    /// Turn the display of the devices off and exit
+    Off,
+    /// Print the firmware version and the capabilities of the devices
+    Info,
+    /// Cycle every digit, the status bar and both temperature units to check the display
+    Test,
+    /// Show a value on the display and exit, it stays there until something else is shown
+    Show {
+        /// Number shown on the digits
+        #[arg(long)]
+        value: u8,
+        /// Status bar level in percent, the value by default (not used in usage mode)
+        #[arg(long, value_parser = clap::value_parser!(u8).range(0..=100))]
+        bar: Option<u8>,
+    },
+    /// Send the packets of a capture made with --record to the device again, with their original timing
+    Replay {
+        /// Capture to send
+        #[arg(value_name = "PATH")]
+        capture: PathBuf,
+    },
+    /// Write a systemd unit running the program with the options given before this command
+    InstallService {
+        /// Location of the unit
+        #[arg(long, default_value = service::UNIT_PATH)]
+        path: PathBuf,
+        /// Enable and start the service once the unit is written
+        #[arg(long)]
+        enable: bool,
+        /// Print the unit instead of writing it
+        #[arg(long, conflicts_with = "enable")]
+        print: bool,
+    },
+    /// Write udev rules giving a group access to the devices, so the program does not have to run as root
+    InstallUdevRules {
+        /// Group given access, it is created if needed
+        #[arg(long, default_value = "deepcool")]
+        group: String,
+        /// Location of the rules
+        #[arg(long, default_value = udev::RULES_PATH)]
+        path: PathBuf,
+        /// Print the rules instead of writing them
+        #[arg(long)]
+        print: bool,
+    },
+    /// Print the completion script of a shell
+    Completions {
+        #[arg(value_parser = completions::SHELLS)]
+        shell: String,
+    },
+    /// Print the manual page
+    Man,
+    /// Check the settings or print the ones in effect
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum ConfigAction {
+    /// Check the settings files, the CPU temperature sensor and the alarm thresholds, exits with an error if
+    /// something is wrong
+    Check,
+    /// Print the settings in effect and where each one comes from
+    Show,
+}
+
+/// Expands "auto" and adds the seconds each mode is shown for when cycling through several modes.
+///
+/// The seconds given in the mode come first, then the ones in the settings file, then `--cycle-interval`. The mode
+/// must have been checked with `devices::valid_mode`.
+fn cycle_spec(spec: &str, args: &Args, config: &Config) -> String {
+    let mut modes = Vec::new();
+    for entry in spec.split(',') {
+        let (mode, seconds) = match entry.split_once(':') {
+            Some((mode, seconds)) => (mode, Some(seconds.parse::<u64>().unwrap())),
+            None => (entry, None),
+        };
+        let expanded = if mode == "auto" {
+            vec!["temp", "usage"]
+        } else {
+            vec![mode]
+        };
+        for mode in expanded {
+            let seconds = seconds.or(config.dwell(mode)).unwrap_or(args.cycle_interval);
+            modes.push((mode, seconds));
+        }
+    }
+    match modes.as_slice() {
+        [(mode, _)] => mode.to_string(),
+        _ => modes
+            .iter()
+            .map(|(mode, seconds)| format!("{mode}:{seconds}"))
+            .collect::<Vec<_>>()
+            .join(","),
+    }
+}
+
+/// Resolves the display settings of a device: the command line first, then the section of the device in the settings
+/// file, then `[display]`. Without a device, only `[display]` is used.
+///
+/// Returns the arguments to connect the device with, and its temperature unit if it does not follow the shared one.
+pub fn resolve_args(
+    args: &Args,
+    cli_mode: Option<&str>,
+    config: &Config,
+    device: Option<(&str, u16)>,
+) -> (Args, Option<bool>) {
+    let section = device.and_then(|(name, product_id)| config.device(name, product_id));
+    let display = &config.display;
+    let mode = cli_mode
+        .or(section.and_then(|section| section.mode.as_deref()))
+        .or(display.mode.as_deref())
+        .unwrap_or("temp");
+    let unit = section
+        .and_then(|section| section.fahrenheit)
+        .filter(|_| !args.fahrenheit);
+    let resolved = Args {
+        mode: cycle_spec(mode, args, config),
+        fahrenheit: args.fahrenheit || unit.or(display.fahrenheit).unwrap_or(false),
+        poll: args.poll.or(section.and_then(|section| section.poll)).or(display.poll),
+        ..args.clone()
+    };
+
+    (resolved, unit)
+}
+
+/// Returns the settings file given with `--config`, or the system wide and user ones that exist.
+pub fn config_paths(args: &Args) -> Vec<PathBuf> {
+    match &args.config {
+        Some(path) => vec![path.clone()],
+        None => config::layers(),
+    }
+}
+
+/// Reads the settings files, each one overrides the values of the previous ones.
+pub fn load_config(args: &Args) -> Result<Config, String> {
+    Config::load_layers(&config_paths(args))
+}
+
+/// Parses the path of a hidraw node, the name alone is looked up in `/dev` as udev passes it.
+fn parse_device(value: &str) -> Result<CString, String> {
+    let path = if value.contains('/') {
+        value.to_string()
+    } else {
+        format!("/dev/{value}")
+    };
+    CString::new(path).map_err(|_| format!("invalid path \"{value}\""))
+}
+
+/// Parses the PCI address of a graphics card.
+fn parse_pci(value: &str) -> Result<PciAddress, String> {
+    PciAddress::parse(value).ok_or(format!("invalid PCI address \"{value}\", expected e.g. \"0000:03:00.0\""))
+}
+
+/// Parses a product ID given either in hexadecimal (with `0x` prefix) or in decimal.
+fn parse_pid(value: &str) -> Result<u16, String> {
+    let parsed = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => value.parse::<u16>(),
+    };
+    parsed.map_err(|_| format!("invalid product ID \"{value}\""))
+}
+
+/// Returns the choices of the hardware of the built-in metrics, with the card selected by `--gpu-index` or `--gpu-pci`
+/// and the drive selected by `--drive` if there are some.
+pub fn builtin_settings(args: &Args, config: &Config) -> monitor::Settings {
+    let card = match (args.gpu_index, args.gpu_pci) {
+        (Some(index), _) => Some(Card::Index(index)),
+        (_, Some(address)) => Some(Card::Pci(address)),
+        _ => config.gpu.card,
+    };
+
+    monitor::Settings {
+        gpu: gpu::Settings { card, ..config.gpu },
+        drive: args.drive.clone().or_else(|| config.drive.clone()),
+    }
+}
+
+/// Returns the metrics selected to be shown, with `--metric` or in the settings.
+pub fn shown_metrics(args: &Args, config: &Config) -> Vec<String> {
+    match args.metric.as_slice() {
+        [] => config.shown.clone(),
+        names => names.iter().map(|name| metric_name(name)).collect(),
+    }
+}
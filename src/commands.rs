@@ -0,0 +1,663 @@
+//! Subcommands and options that do their job and exit instead of driving the devices.
+
+use crate::{
+    capture::{self, Direction},
+    cli::{builtin_settings, config_paths, load_config, resolve_args, shown_metrics, Args},
+    config::{self, Config},
+    control,
+    devices::{self, RunOptions, Status, VENDOR},
+    discovery::{connect, lookup, select_driver, ConnectError},
+    log::{error, info},
+    monitor::{cpu, gpu::Card},
+    service, state,
+    toml::{self, Table},
+    udev,
+};
+use clap::{parser::ValueSource, ArgMatches, CommandFactory};
+use hidapi::{DeviceInfo, HidApi};
+use std::{
+    ffi::CStr,
+    fs::{canonicalize, read_to_string},
+    path::{Path, PathBuf},
+    process::exit,
+};
+
+/// Shows the test pattern on every device.
+///
+/// The pattern is shown in temperature mode in ˚C and ˚F, then in usage mode, with the alarm enabled.
+pub fn self_test(api: &HidApi, args: &Args, definitions: &[devices::generic::Definition], options: &RunOptions) {
+    let found = lookup(api, args, true);
+    if found.is_empty() {
+        error!("No DeepCool device found!");
+        exit(1);
+    }
+    let variant = |mode: &str| Args {
+        mode: mode.to_string(),
+        alarm: true,
+        ..args.clone()
+    };
+    for entries in &found {
+        let (info, device, driver, _lock) = match connect(api, entries, &variant("temp"), definitions) {
+            Ok(connection) => connection,
+            Err(ConnectError::Unsupported | ConnectError::Interface) => continue,
+            Err(ConnectError::Open(err)) => {
+                error!("Failed to open HID device: {err}");
+                continue;
+            }
+            Err(ConnectError::Busy(message)) => {
+                error!("{message}");
+                continue;
+            }
+        };
+        let mut drivers = vec![driver];
+        drivers.extend(select_driver(info.product_id(), &variant("usage"), definitions));
+        match devices::self_test(device, &mut drivers, options) {
+            Ok(()) => info!("Test pattern finished"),
+            Err(err) => error!("Failed to show the test pattern: {err}"),
+        }
+    }
+}
+
+/// Shows the given status on every device, then exits.
+pub fn show(
+    api: &HidApi,
+    args: &Args,
+    definitions: &[devices::generic::Definition],
+    options: &RunOptions,
+    status: &Status,
+) {
+    let found = lookup(api, args, true);
+    if found.is_empty() {
+        error!("No DeepCool device found!");
+        exit(1);
+    }
+    let mut failed = false;
+    for entries in &found {
+        let result = match connect(api, entries, args, definitions) {
+            Ok((_, device, mut driver, _lock)) => devices::show(device, driver.as_mut(), status, options),
+            Err(ConnectError::Unsupported | ConnectError::Interface) => continue,
+            Err(ConnectError::Open(err)) => Err(err.into()),
+            Err(ConnectError::Busy(message)) => {
+                error!("{message}");
+                failed = true;
+                continue;
+            }
+        };
+        if let Err(err) = result {
+            error!("Failed to show the value: {err}");
+            failed = true;
+        }
+    }
+    if failed {
+        exit(1);
+    }
+}
+
+/// Runs the display loop with the driver of `--protocol` or `--pid` and prints its packets, until stopped.
+pub fn dry_run(args: &Args, definitions: &[devices::generic::Definition], options: &RunOptions) {
+    let product_id = args.pid.unwrap_or(0);
+    info!("SENSOR:     {}", options.cpu_temp_sensor);
+    let Some(mut driver) = select_driver(product_id, args, definitions) else {
+        error!("No packet format is known for product ID {product_id:#06x}, pick one with --protocol");
+        exit(1);
+    };
+    let name = match &args.protocol {
+        Some(protocol) => protocol.clone(),
+        None => format!("{product_id:#06x}"),
+    };
+    let result = if args.once {
+        devices::once(devices::Printer::new(&name), driver.as_mut(), options)
+    } else {
+        state::install_signal_handlers();
+        info!("\nPress Ctrl + C to terminate");
+        devices::run(devices::Printer::new(&name), driver.as_mut(), options)
+    };
+    if let Err(err) = result {
+        error!("Failed to run the display loop: {err}");
+        exit(1);
+    }
+}
+
+/// Sends a single update to every device, then exits.
+pub fn update_once(api: &HidApi, args: &Args, definitions: &[devices::generic::Definition], options: &RunOptions) {
+    let found = lookup(api, args, true);
+    if found.is_empty() {
+        error!("No DeepCool device found!");
+        exit(1);
+    }
+    let mut failed = false;
+    for entries in &found {
+        let result = match connect(api, entries, args, definitions) {
+            Ok((_, device, mut driver, _lock)) => devices::once(device, driver.as_mut(), options),
+            Err(ConnectError::Unsupported | ConnectError::Interface) => continue,
+            Err(ConnectError::Open(err)) => Err(err.into()),
+            Err(ConnectError::Busy(message)) => {
+                error!("{message}");
+                failed = true;
+                continue;
+            }
+        };
+        if let Err(err) = result {
+            error!("Failed to update the display: {err}");
+            failed = true;
+        }
+    }
+    if failed {
+        exit(1);
+    }
+}
+
+/// Sends the packets of a capture to the device, then exits.
+///
+/// Only the packets written to the device are sent. If the capture has several devices, the packets of the one with
+/// the same name are sent, or all of them when there is none.
+pub fn replay(
+    api: &HidApi,
+    args: &Args,
+    definitions: &[devices::generic::Definition],
+    options: &RunOptions,
+    path: &Path,
+) {
+    let frames = capture::load(path).unwrap_or_else(|err| {
+        error!("{err}");
+        exit(1);
+    });
+    let found = lookup(api, args, true);
+    let entries = match found.as_slice() {
+        [] => {
+            error!("No DeepCool device found!");
+            exit(1);
+        }
+        [entries] => entries,
+        _ => {
+            error!("Several devices found, pick one with --pid or --serial");
+            exit(1);
+        }
+    };
+    let (info, device, _, _lock) = match connect(api, entries, args, definitions) {
+        Ok(connection) => connection,
+        Err(ConnectError::Unsupported | ConnectError::Interface) => exit(1),
+        Err(ConnectError::Open(err)) => {
+            error!("Failed to open HID device: {err}");
+            exit(1);
+        }
+        Err(ConnectError::Busy(message)) => {
+            error!("{message}");
+            exit(1);
+        }
+    };
+
+    let name = info.product_string().unwrap_or("unknown");
+    let frames: Vec<_> = frames
+        .into_iter()
+        .filter(|frame| frame.direction == Direction::Out)
+        .collect();
+    let matching: Vec<_> = frames.iter().filter(|frame| frame.device == name).collect();
+    let frames: Vec<_> = if matching.is_empty() {
+        frames.iter().collect()
+    } else {
+        matching
+    };
+    state::install_signal_handlers();
+    info!("Sending {} packets from {}", frames.len(), path.display());
+    match devices::replay(device, &frames, options) {
+        Ok(()) => info!("Replay finished"),
+        Err(err) => {
+            error!("Failed to send the packets: {err}");
+            exit(1);
+        }
+    }
+}
+
+/// Blanks the display of every device, then exits.
+pub fn turn_off(api: &HidApi, args: &Args, definitions: &[devices::generic::Definition], options: &RunOptions) {
+    let found = lookup(api, args, true);
+    if found.is_empty() {
+        error!("No DeepCool device found!");
+        exit(1);
+    }
+    let mut failed = false;
+    for entries in &found {
+        let result = match connect(api, entries, args, definitions) {
+            Ok((_, device, mut driver, _lock)) => devices::blank(device, driver.as_mut(), options),
+            Err(ConnectError::Unsupported | ConnectError::Interface) => continue,
+            Err(ConnectError::Open(err)) => Err(err.into()),
+            Err(ConnectError::Busy(message)) => {
+                error!("{message}");
+                failed = true;
+                continue;
+            }
+        };
+        match result {
+            Ok(()) => info!("Display turned off"),
+            Err(err) => {
+                error!("Failed to turn off the display: {err}");
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        exit(1);
+    }
+}
+
+/// Prints the firmware version and the capabilities of every device.
+pub fn info(api: &HidApi, args: &Args, definitions: &[devices::generic::Definition], options: &RunOptions) {
+    let found = lookup(api, args, true);
+    if found.is_empty() {
+        error!("No DeepCool device found!");
+        exit(1);
+    }
+    for entries in &found {
+        let (info, device, mut driver, _lock) = match connect(api, entries, args, definitions) {
+            Ok(connection) => connection,
+            Err(ConnectError::Unsupported | ConnectError::Interface) => continue,
+            Err(ConnectError::Open(err)) => {
+                error!("Failed to open HID device: {err}");
+                continue;
+            }
+            Err(ConnectError::Busy(message)) => {
+                error!("{message}");
+                continue;
+            }
+        };
+        let capabilities = driver.capabilities();
+        println!("PATH:       {}", info.path().to_string_lossy());
+        println!("INTERFACE:  {}", info.interface_number());
+        println!("REPORT:     {} bytes", driver.report_length());
+        println!(
+            "POWER:      {}",
+            if capabilities.power {
+                "supported"
+            } else {
+                "not supported"
+            }
+        );
+        match devices::firmware_version(device, driver.as_mut(), options) {
+            Ok(Some(version)) => println!("FIRMWARE:   {version}"),
+            Ok(None) => println!("FIRMWARE:   unknown"),
+            Err(err) => println!("FIRMWARE:   query failed ({err})"),
+        }
+        println!("-----");
+    }
+}
+
+/// Writes the systemd unit running the program with the options given before `install-service`.
+pub fn install_service(args: &Args, config: &Config, path: &Path, enable: bool, print: bool) {
+    let exe = std::env::current_exe().and_then(canonicalize).unwrap_or_else(|err| {
+        error!("Failed to find the path of the program: {err}");
+        exit(1);
+    });
+    let arguments: Vec<String> = std::env::args()
+        .skip(1)
+        .take_while(|arg| arg != "install-service")
+        .collect();
+
+    // The paths written to have to be allowed, the rest of the file system is read-only
+    let mut access = service::Access {
+        working_directory: std::env::current_dir().unwrap_or_else(|_| PathBuf::from("/")),
+        writable: Vec::new(),
+        runtime_directory: false,
+        network: args.metrics_listen.is_some() || config.mqtt.is_some() || config.influxdb.is_some(),
+        dbus: args.dbus,
+    };
+    match &args.socket {
+        Some(socket) if socket == Path::new(control::SOCKET_PATH) => access.runtime_directory = true,
+        Some(socket) => access.writable.extend(socket.parent().map(Path::to_owned)),
+        None => (),
+    }
+    access
+        .writable
+        .extend(args.log_csv.as_deref().and_then(Path::parent).map(Path::to_owned));
+    for folder in &mut access.writable {
+        if let Ok(absolute) = std::path::absolute(&folder) {
+            *folder = absolute;
+        }
+    }
+
+    let unit = service::unit(&exe, &arguments, &access);
+    if print {
+        print!("{unit}");
+        return;
+    }
+    if let Err(err) = service::install(path, &unit, enable) {
+        error!("{err}");
+        exit(1);
+    }
+    println!("Wrote {}", path.display());
+    if !enable {
+        println!(
+            "Enable it with: sudo systemctl enable --now {}",
+            path.file_name().unwrap_or_default().to_string_lossy()
+        );
+    }
+}
+
+/// Writes the udev rules giving `group` access to the devices.
+pub fn install_udev_rules(group: &str, path: &Path, print: bool) {
+    let rules = udev::rules(VENDOR, group);
+    if print {
+        print!("{rules}");
+        return;
+    }
+    if let Err(err) = udev::install(path, &rules, group) {
+        error!("{err}");
+        exit(1);
+    }
+    println!("Wrote {}", path.display());
+    println!("Add the users running the program to the group with: sudo usermod -aG {group} <user>");
+}
+
+/// Checks the settings files, the CPU temperature sensor and the alarm thresholds, and exits with an error if one of
+/// them is wrong.
+pub fn config_check(args: &Args) {
+    let mut problems = Vec::new();
+
+    // Settings files, the unknown keys are reported but do not prevent reading the rest
+    let paths = config_paths(args);
+    let mut readable = true;
+    for path in &paths {
+        match config::read_table(path) {
+            Ok(table) => problems.extend(
+                config::unknown_keys(&table)
+                    .into_iter()
+                    .map(|key| format!("{}: unknown key \"{key}\"", path.display())),
+            ),
+            Err(err) => {
+                problems.push(err);
+                readable = false;
+            }
+        }
+    }
+    if readable {
+        if let Err(err) = Config::load_layers(&paths) {
+            problems.push(format!("Invalid settings: {err}"));
+        }
+    }
+
+    // Options
+    if !devices::valid_mode(&args.mode) {
+        problems.push(format!("Invalid mode \"{}\"", args.mode));
+    }
+    if args.alarm_clear.is_some_and(|clear| clear > args.alarm_temp) {
+        problems.push(format!(
+            "The alarm clears at {}˚C, above the {}˚C it goes off at",
+            args.alarm_clear.unwrap(),
+            args.alarm_temp
+        ));
+    }
+
+    // CPU temperature sensor
+    let sensor = match &args.sensor {
+        Some(path) => Some(path.to_string_lossy().into_owned()),
+        None => cpu::lookup_temp_sensor(),
+    };
+    match sensor.map(|path| (read_to_string(&path), path)) {
+        Some((Ok(data), path)) if data.trim_end().parse::<u32>().is_err() => {
+            problems.push(format!("{path} is not a temperature sensor"))
+        }
+        Some((Ok(_), _)) => (),
+        Some((Err(err), path)) => problems.push(format!("Failed to read the CPU temperature from {path}: {err}")),
+        None => problems.push("CPU temperature sensor not found".to_string()),
+    }
+
+    if !problems.is_empty() {
+        for problem in &problems {
+            error!("{problem}");
+        }
+        exit(1);
+    }
+    match paths.as_slice() {
+        [] => println!("No settings file, the options are valid"),
+        paths => println!(
+            "{} valid",
+            paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+}
+
+/// Prints the settings in effect, the devices without a section of their own use the shared ones, and where each
+/// one comes from: an option, an environment variable, a settings file or the default.
+pub fn config_show(args: &Args, matches: &ArgMatches, cli_mode: Option<&str>) {
+    if !devices::valid_mode(&args.mode) {
+        error!("Invalid mode \"{}\"", args.mode);
+        exit(1);
+    }
+    let paths = config_paths(args);
+    let config = load_config(args).unwrap_or_else(|err| {
+        error!("Invalid settings file: {err}");
+        exit(1);
+    });
+    let layers: Vec<(PathBuf, Table)> = paths
+        .iter()
+        .filter_map(|path| Some((path.clone(), config::read_table(path).ok()?)))
+        .collect();
+
+    // Where a value comes from, the first source that sets it wins
+    let option = |id: &str| match matches.value_source(id) {
+        Some(ValueSource::CommandLine) => Some(format!("--{}", id.replace('_', "-"))),
+        Some(ValueSource::EnvVariable) => Args::command()
+            .get_arguments()
+            .find(|arg| arg.get_id() == id)
+            .and_then(|arg| arg.get_env())
+            .map(|env| env.to_string_lossy().into_owned()),
+        _ => None,
+    };
+    let file = |section: &[&str], key: &str| {
+        layers
+            .iter()
+            .rev()
+            .find(|(_, root)| {
+                let mut table = Some(root);
+                for name in section {
+                    table = table.and_then(|table| table.get(*name)).and_then(toml::Value::as_table);
+                }
+                table.is_some_and(|table| table.contains_key(key))
+            })
+            .map(|(path, _)| match section {
+                [name] => format!("{} [{name}]", path.display()),
+                [table, name] => format!("{} [{table}.\"{name}\"]", path.display()),
+                _ => path.display().to_string(),
+            })
+    };
+    let print = |setting: String, source: Option<String>| {
+        println!("{setting:<40} # {}", source.as_deref().unwrap_or("default"));
+    };
+
+    match paths.as_slice() {
+        [] => println!("# No settings file"),
+        paths => println!(
+            "# Settings files: {}",
+            paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    }
+
+    // Display settings, of every device then of each device section
+    let mut sections = vec![None];
+    sections.extend(config.devices.iter().map(|(name, _)| Some(name.as_str())));
+    for name in sections {
+        let device = name.map(|name| {
+            let product_id = name
+                .strip_prefix("0x")
+                .and_then(|hex| u16::from_str_radix(hex, 16).ok())
+                .unwrap_or(0);
+            (name, product_id)
+        });
+        let (resolved, unit) = resolve_args(args, cli_mode, &config, device);
+        let section = |key: &str| name.and_then(|name| file(&["device", name], key));
+        if let Some(name) = name {
+            println!("\n[device.\"{name}\"]");
+        }
+        print(
+            format!("mode = \"{}\"", resolved.mode),
+            cli_mode
+                .and_then(|_| option("mode"))
+                .or_else(|| section("mode"))
+                .or_else(|| file(&["display"], "mode")),
+        );
+        print(
+            format!("fahrenheit = {}", unit.unwrap_or(resolved.fahrenheit)),
+            option("fahrenheit")
+                .or_else(|| section("fahrenheit"))
+                .or_else(|| file(&["display"], "fahrenheit")),
+        );
+        print(
+            match resolved.poll {
+                Some(poll) => format!("poll = \"{poll:?}\""),
+                None => "poll = device default".to_string(),
+            },
+            option("poll")
+                .or_else(|| section("poll"))
+                .or_else(|| file(&["display"], "poll")),
+        );
+        if name.is_some() {
+            continue;
+        }
+
+        // Settings that are only set by options
+        print(
+            format!(
+                "sensor = \"{}\"",
+                match &args.sensor {
+                    Some(path) => path.to_string_lossy().into_owned(),
+                    None => cpu::lookup_temp_sensor().unwrap_or_else(|| "not found".to_string()),
+                }
+            ),
+            option("sensor").or(Some("detected".to_string())),
+        );
+        print(format!("alarm = {}", args.alarm), option("alarm"));
+        print(format!("alarm_temp = {}", args.alarm_temp), option("alarm_temp"));
+        print(
+            format!("alarm_clear = {}", args.alarm_clear.unwrap_or(args.alarm_temp)),
+            option("alarm_clear"),
+        );
+        match config.brightness_at(config::local_minute()) {
+            Some(level) => print(format!("brightness = {level}"), file(&[], "schedule")),
+            None => print(format!("brightness = {}", args.brightness), option("brightness")),
+        }
+        print(
+            format!("metric = {:?}", shown_metrics(args, &config)),
+            option("metric").or_else(|| file(&["display"], "metric")),
+        );
+        print(
+            match builtin_settings(args, &config).gpu.card {
+                Some(Card::Index(index)) => format!("gpu.index = {index}"),
+                Some(Card::Pci(address)) => format!("gpu.pci = \"{address}\""),
+                None => "gpu = first card with the metric".to_string(),
+            },
+            option("gpu_index")
+                .or_else(|| option("gpu_pci"))
+                .or_else(|| file(&["gpu"], "index"))
+                .or_else(|| file(&["gpu"], "pci")),
+        );
+        print(format!("gpu.temp = \"{}\"", config.gpu.temp.name()), file(&["gpu"], "temp"));
+        print(
+            match builtin_settings(args, &config).drive {
+                Some(drive) => format!("drive = \"{drive}\""),
+                None => "drive = first drive".to_string(),
+            },
+            option("drive").or_else(|| file(&["drive"], "name")),
+        );
+        print(format!("hooks = {}", config.hooks.len()), file(&[], "hooks"));
+        print(format!("metrics = {}", config.metrics.len()), file(&[], "metrics"));
+        print(format!("mqtt = {}", config.mqtt.is_some()), file(&[], "mqtt"));
+        print(format!("influxdb = {}", config.influxdb.is_some()), file(&[], "influxdb"));
+    }
+}
+
+/// Prints every DeepCool HID device with the driver that would be used for it.
+pub fn list_devices(api: &HidApi) {
+    let definitions = devices::generic::load_definitions(devices::generic::DEFINITIONS_DIR);
+
+    let mut listed: Vec<&DeviceInfo> = Vec::new();
+    for device in api.device_list() {
+        if device.vendor_id() == VENDOR && !listed.iter().any(|d| d.path() == device.path()) {
+            listed.push(device);
+        }
+    }
+    if listed.is_empty() {
+        println!("No DeepCool device found!");
+        return;
+    }
+
+    println!("{:<8}{:<24}{:<20}{:<11}DRIVER", "PID", "PRODUCT", "SERIAL", "INTERFACE");
+    for device in listed {
+        let product_id = device.product_id();
+        let driver = match definitions
+            .iter()
+            .find(|definition| definition.product_id == product_id)
+        {
+            Some(definition) => format!("definition ({})", definition.name),
+            None => devices::protocol_for(product_id).unwrap_or("none").to_string(),
+        };
+        println!(
+            "{:<8}{:<24}{:<20}{:<11}{}",
+            format!("{:#06x}", product_id),
+            device.product_string().unwrap_or("unknown"),
+            device
+                .serial_number()
+                .filter(|serial| !serial.is_empty())
+                .unwrap_or("-"),
+            device.interface_number(),
+            driver
+        );
+    }
+}
+
+/// Prints the HID details of every DeepCool device as a block that can be pasted into an issue.
+pub fn probe(api: &HidApi) {
+    let mut paths: Vec<&CStr> = Vec::new();
+    for device in api.device_list() {
+        if device.vendor_id() == VENDOR && !paths.contains(&device.path()) {
+            paths.push(device.path());
+        }
+    }
+    if paths.is_empty() {
+        println!("No DeepCool device found!");
+        return;
+    }
+
+    println!("```");
+    for path in paths {
+        // Every top-level usage of the same interface is listed as a separate entry
+        let entries: Vec<&DeviceInfo> = api.device_list().filter(|device| device.path() == path).collect();
+        let info = entries[0];
+        println!("Path: {}", path.to_string_lossy());
+        println!("Vendor ID: {:#06x}", info.vendor_id());
+        println!("Product ID: {:#06x}", info.product_id());
+        println!("Release: {:#06x}", info.release_number());
+        println!("Manufacturer: {}", info.manufacturer_string().unwrap_or("unknown"));
+        println!("Product: {}", info.product_string().unwrap_or("unknown"));
+        println!("Serial: {}", info.serial_number().unwrap_or("unknown"));
+        println!("Interface: {}", info.interface_number());
+        for entry in &entries {
+            println!("Usage page: {:#06x}, usage: {:#06x}", entry.usage_page(), entry.usage());
+        }
+
+        // Report descriptor
+        let mut descriptor = [0u8; 4096];
+        match info
+            .open_device(api)
+            .and_then(|device| device.get_report_descriptor(&mut descriptor))
+        {
+            Ok(length) => {
+                println!("Report descriptor ({length} bytes):");
+                for line in descriptor[..length].chunks(16) {
+                    let bytes: Vec<String> = line.iter().map(|byte| format!("{byte:02x}")).collect();
+                    println!("  {}", bytes.join(" "));
+                }
+            }
+            Err(err) => println!("Report descriptor: unavailable ({err})"),
+        }
+        println!();
+    }
+    println!("```");
+}
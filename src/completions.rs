@@ -0,0 +1,270 @@
+//! Shell completion scripts, generated from the definitions of the options and the commands.
+//!
+//! ```bash
+//! deepcool-digital-linux completions bash > /usr/share/bash-completion/completions/deepcool-digital-linux
+//! ```
+
+use clap::{Arg, Command, ValueHint};
+
+/// Shells a script can be generated for
+pub const SHELLS: [&str; 3] = ["bash", "zsh", "fish"];
+
+/// Returns the completion script of `command` for the given shell.
+pub fn generate(shell: &str, command: &mut Command) -> String {
+    // The help options and the help command are only added once the command is built
+    command.build();
+    match shell {
+        "bash" => bash(command),
+        "zsh" => zsh(command),
+        _ => fish(command),
+    }
+}
+
+/// Returns the command with its subcommands, each one with the names leading to it.
+///
+/// The commands of `help` are the same as the others, they are left out.
+fn walk<'a>(command: &'a Command, path: Vec<&'a str>, list: &mut Vec<(Vec<&'a str>, &'a Command)>) {
+    list.push((path.clone(), command));
+    if command.get_name() == "help" {
+        return;
+    }
+    for subcommand in command.get_subcommands() {
+        let mut path = path.clone();
+        path.push(subcommand.get_name());
+        walk(subcommand, path, list);
+    }
+}
+
+/// Returns the options of a command that are not hidden, the positional arguments are left out.
+fn options(command: &Command) -> impl Iterator<Item = &Arg> {
+    command
+        .get_arguments()
+        .filter(|arg| !arg.is_hide_set() && !arg.is_positional())
+}
+
+/// Returns whether the option takes a value.
+fn takes_value(arg: &Arg) -> bool {
+    arg.get_num_args().is_some_and(|num| num.takes_values())
+}
+
+/// Returns whether the value of the option is a path.
+fn takes_path(arg: &Arg) -> bool {
+    matches!(
+        arg.get_value_hint(),
+        ValueHint::AnyPath | ValueHint::FilePath | ValueHint::DirPath
+    )
+}
+
+/// Returns the values the option accepts, if they are limited.
+fn values(arg: &Arg) -> Vec<String> {
+    arg.get_possible_values()
+        .iter()
+        .filter(|value| !value.is_hide_set())
+        .map(|value| value.get_name().to_string())
+        .collect()
+}
+
+/// Returns the first line of the help of an option or a command.
+fn help(text: Option<&clap::builder::StyledStr>) -> String {
+    text.map(|text| text.to_string().lines().next().unwrap_or_default().to_string())
+        .unwrap_or_default()
+}
+
+/// Returns the `-s` and `--long` forms of an option.
+fn flags(arg: &Arg) -> Vec<String> {
+    let mut flags: Vec<String> = arg.get_short().map(|short| format!("-{short}")).into_iter().collect();
+    flags.extend(arg.get_long().map(|long| format!("--{long}")));
+    flags
+}
+
+fn bash(command: &Command) -> String {
+    let name = command.get_name();
+    let function = format!("_{}", name.replace('-', "_"));
+    let mut commands = Vec::new();
+    walk(command, vec![name], &mut commands);
+
+    let mut script = format!(
+        "{function}() {{\n    local cur=\"${{COMP_WORDS[COMP_CWORD]}}\" prev=\"${{COMP_WORDS[COMP_CWORD-1]}}\"\n\n    \
+         # Follow the commands typed so far\n    local command=\"{name}\" i\n    \
+         for ((i = 1; i < COMP_CWORD; i++)); do\n        case \"$command ${{COMP_WORDS[i]}}\" in\n"
+    );
+    for (path, _) in commands.iter().filter(|(path, _)| path.len() > 1) {
+        script.push_str(&format!(
+            "            \"{}\") command=\"{}\" ;;\n",
+            path.join(" "),
+            path.join(" ")
+        ));
+    }
+    script.push_str("        esac\n    done\n\n    case \"$command\" in\n");
+    for (path, command) in &commands {
+        script.push_str(&format!("        \"{}\")\n            case \"$prev\" in\n", path.join(" ")));
+        for arg in options(command).filter(|arg| takes_value(arg)) {
+            let reply = if takes_path(arg) {
+                "COMPREPLY=($(compgen -f -- \"$cur\"))".to_string()
+            } else {
+                format!("COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))", values(arg).join(" "))
+            };
+            script.push_str(&format!("                {}) {reply}; return ;;\n", flags(arg).join("|")));
+        }
+        let words: Vec<String> = options(command)
+            .flat_map(flags)
+            .chain(command.get_subcommands().map(|command| command.get_name().to_string()))
+            .collect();
+        script.push_str(&format!(
+            "            esac\n            COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n            ;;\n",
+            words.join(" ")
+        ));
+    }
+    script.push_str(&format!("    esac\n}}\n\ncomplete -F {function} {name}\n"));
+
+    script
+}
+
+/// Escapes a description for the `_arguments` specs of zsh.
+fn zsh_escape(text: &str) -> String {
+    text.replace('\'', "'\\''")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+        .replace(':', "\\:")
+}
+
+fn zsh(command: &Command) -> String {
+    let name = command.get_name();
+    let mut commands = Vec::new();
+    walk(command, vec![name], &mut commands);
+
+    let mut script = format!("#compdef {name}\n");
+    for (path, command) in &commands {
+        let function = format!("_{}", path.join("__"));
+        script.push_str(&format!("\n{function}() {{\n    local context state state_descr line\n"));
+        script.push_str("    typeset -A opt_args\n    _arguments -C -s \\\n");
+        for arg in options(command) {
+            let flags = flags(arg);
+            let description = zsh_escape(&help(arg.get_help()));
+            let exclusive = if flags.len() > 1 {
+                format!("'({})'", flags.join(" "))
+            } else {
+                String::new()
+            };
+            let optional = arg.get_num_args().is_some_and(|num| num.min_values() == 0);
+            let (suffix, value) = match takes_value(arg) {
+                false => ("", String::new()),
+                true => {
+                    let action = if takes_path(arg) {
+                        "_files".to_string()
+                    } else {
+                        match values(arg).as_slice() {
+                            [] => String::new(),
+                            values => format!("({})", values.join(" ")),
+                        }
+                    };
+                    let value_name = arg
+                        .get_value_names()
+                        .and_then(|names| names.first())
+                        .map_or_else(|| arg.get_id().to_string().to_uppercase(), |name| name.to_string());
+                    let separator = if optional { "::" } else { ":" };
+                    (if optional { "=-" } else { "=" }, format!("{separator}{value_name}:{action}"))
+                }
+            };
+            let forms: Vec<String> = flags
+                .iter()
+                .map(|flag| {
+                    // Short options take their value in the next word, or right after them
+                    let suffix = if flag.starts_with("--") {
+                        suffix
+                    } else {
+                        &suffix.replace('=', "+")
+                    };
+                    format!("{flag}{suffix}")
+                })
+                .collect();
+            let forms = match forms.as_slice() {
+                [form] => form.clone(),
+                forms => format!("{{{}}}", forms.join(",")),
+            };
+            script.push_str(&format!("        {exclusive}{forms}'[{description}]{value}' \\\n"));
+        }
+        if command.has_subcommands() {
+            script.push_str("        '1: :->command' \\\n        '*:: :->arguments'\n\n    case $state in\n");
+            script.push_str("        command)\n            local commands=(\n");
+            for subcommand in command.get_subcommands() {
+                script.push_str(&format!(
+                    "                '{}:{}'\n",
+                    subcommand.get_name(),
+                    zsh_escape(&help(subcommand.get_about()))
+                ));
+            }
+            script.push_str("            )\n            _describe command commands\n            ;;\n");
+            script.push_str("        arguments)\n            case $line[1] in\n");
+            for subcommand in command.get_subcommands() {
+                script.push_str(&format!(
+                    "                {}) {function}__{} ;;\n",
+                    subcommand.get_name(),
+                    subcommand.get_name()
+                ));
+            }
+            script.push_str("            esac\n            ;;\n    esac\n}\n");
+        } else {
+            script.push_str("        && return 0\n}\n");
+        }
+    }
+    script.push_str(&format!("\n_{name} \"$@\"\n"));
+
+    script
+}
+
+/// Escapes a description for fish.
+fn fish_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('\'', "\\'")
+}
+
+fn fish(command: &Command) -> String {
+    let name = command.get_name();
+    let mut commands = Vec::new();
+    walk(command, vec![name], &mut commands);
+
+    let mut script = String::new();
+    for (path, command) in &commands {
+        // The words of the command have been typed, but none of its subcommands yet
+        let mut conditions: Vec<String> = path[1..]
+            .iter()
+            .map(|word| format!("__fish_seen_subcommand_from {word}"))
+            .collect();
+        if command.has_subcommands() {
+            let names: Vec<&str> = command.get_subcommands().map(Command::get_name).collect();
+            conditions.push(format!("not __fish_seen_subcommand_from {}", names.join(" ")));
+        }
+        let condition = match conditions.is_empty() {
+            true => "__fish_use_subcommand".to_string(),
+            false => conditions.join("; and "),
+        };
+
+        for arg in options(command) {
+            let mut line = format!("complete -c {name} -n '{condition}'");
+            if let Some(short) = arg.get_short() {
+                line.push_str(&format!(" -s {short}"));
+            }
+            if let Some(long) = arg.get_long() {
+                line.push_str(&format!(" -l {long}"));
+            }
+            if takes_value(arg) {
+                match values(arg).as_slice() {
+                    _ if takes_path(arg) => line.push_str(" -r -F"),
+                    [] => line.push_str(" -r -f"),
+                    values => line.push_str(&format!(" -r -f -a '{}'", values.join(" "))),
+                }
+            }
+            line.push_str(&format!(" -d '{}'\n", fish_escape(&help(arg.get_help()))));
+            script.push_str(&line);
+        }
+        for subcommand in command.get_subcommands() {
+            script.push_str(&format!(
+                "complete -c {name} -n '{condition}' -f -a {} -d '{}'\n",
+                subcommand.get_name(),
+                fish_escape(&help(subcommand.get_about()))
+            ));
+        }
+    }
+
+    script
+}
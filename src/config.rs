@@ -0,0 +1,527 @@
+//! Settings file.
+//!
+//! ```toml
+//! # Brightness schedule, each entry applies from its time until the next one
+//! [[schedule]]
+//! from = "07:00"
+//! brightness = 100
+//!
+//! [[schedule]]
+//! from = "22:30"
+//! brightness = 20
+//!
+//! [display]
+//! mode = "temp,usage"           # same as --mode, which takes precedence
+//! fahrenheit = true             # same as --fahrenheit
+//! poll = "1s"                   # same as --poll, a number is in milliseconds
+//! # Seconds each mode is shown for when cycling through several modes
+//! dwell = { temp = 10, usage = 3 }
+//! metric = "loop_temp"          # same as --metric
+//!
+//! # Display settings of a single device, by product name, first word of the name or product ID ("0x0002")
+//! [device."CH560"]
+//! mode = "usage"
+//! poll = "500ms"
+//! ```
+//!
+//! The display settings, the brightness schedule and the hooks are applied again on SIGHUP.
+//!
+//! Commands can also be run when a reading crosses a limit, see `hooks`, and the readings can be
+//! published to an MQTT broker, see `mqtt`, or written to InfluxDB, see `influxdb`. Metrics besides the CPU ones are
+//! defined in `[[metrics]]`, see `sources`.
+
+use crate::{
+    devices::{valid_mode, MAX_POLL, MIN_POLL},
+    hooks::{self, Hook},
+    influxdb,
+    log::local_time,
+    monitor::{
+        builtin_metrics,
+        gpu::{self, Card, PciAddress, TempSensor},
+        metric_name, CPU_METRICS,
+    },
+    mqtt,
+    sources::{self, Source},
+    toml::{self, get_bool, get_int, get_str, Table, Value},
+};
+use std::{
+    env,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Default location of the settings file, it is optional
+pub const CONFIG_PATH: &str = "/etc/deepcool-digital.toml";
+/// Location of the settings file of the user, relative to the XDG config folder
+const USER_CONFIG_PATH: &str = "deepcool-digital/config.toml";
+
+/// Display settings of `[display]` or of a device section, the unset ones fall back to the next level.
+#[derive(Default)]
+pub struct DisplayConfig {
+    /// Display mode
+    pub mode: Option<String>,
+    /// Show the temperature in Fahrenheit
+    pub fahrenheit: Option<bool>,
+    /// Time between two updates
+    pub poll: Option<Duration>,
+}
+
+impl DisplayConfig {
+    /// Parses and validates the display settings of a table.
+    fn parse(table: &Table) -> Result<Self, String> {
+        let mode = get_str(table, "mode")?;
+        if let Some(mode) = mode.filter(|mode| !valid_mode(mode)) {
+            return Err(format!("invalid mode \"{mode}\""));
+        }
+        let poll = match table.get("poll") {
+            Some(Value::Integer(poll)) => Some(parse_poll(&poll.to_string())),
+            Some(Value::String(poll)) => Some(parse_poll(poll)),
+            Some(value) => return Err(format!("\"poll\" must be a duration, found {}", value.type_name())),
+            None => None,
+        };
+        let poll = poll.transpose().map_err(|err| format!("\"poll\": {err}"))?;
+
+        Ok(DisplayConfig {
+            mode: mode.map(str::to_string),
+            fahrenheit: get_bool(table, "fahrenheit")?,
+            poll,
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct Config {
+    /// Brightness levels by the minute of the day they start at, sorted by time
+    pub schedule: Vec<(u32, u8)>,
+    /// Display settings of every device
+    pub display: DisplayConfig,
+    /// Display settings of single devices, by name or product ID
+    pub devices: Vec<(String, DisplayConfig)>,
+    /// Seconds each mode is shown for when cycling
+    dwell: Vec<(String, u64)>,
+    /// Commands run when a reading crosses a limit
+    pub hooks: Vec<Hook>,
+    /// Metrics defined in the settings
+    pub metrics: Vec<Source>,
+    /// Metrics shown instead of the CPU reading of their unit
+    pub shown: Vec<String>,
+    /// Sensors the GPU metrics are read from
+    pub gpu: gpu::Settings,
+    /// Drive the drive metrics are read from
+    pub drive: Option<String>,
+    /// MQTT broker the readings are published to
+    pub mqtt: Option<mqtt::Settings>,
+    /// InfluxDB bucket the readings are written to
+    pub influxdb: Option<influxdb::Settings>,
+}
+
+impl Config {
+    /// Reads and validates several settings files, each one overrides the values of the previous ones.
+    ///
+    /// Tables are merged key by key, so a later file only has to contain the values it changes. Arrays, like the
+    /// brightness schedule or the hooks, are replaced as a whole.
+    pub fn load_layers(paths: &[PathBuf]) -> Result<Self, String> {
+        let mut root = Table::new();
+        for path in paths {
+            toml::merge(&mut root, read_table(path)?);
+        }
+        Self::from_table(&root)
+    }
+
+    /// Validates the contents of a settings file.
+    fn from_table(root: &Table) -> Result<Self, String> {
+        let mut schedule = Vec::new();
+        match root.get("schedule") {
+            Some(Value::Array(entries)) => {
+                for entry in entries {
+                    let entry = entry.as_table().ok_or("\"schedule\" must be an array of tables")?;
+                    let from = get_str(entry, "from")?.ok_or("missing \"schedule.from\"")?;
+                    let from = parse_time(from).ok_or(format!("invalid time \"{from}\", expected \"HH:MM\""))?;
+                    let brightness = get_int(entry, "brightness", 100)?.ok_or("missing \"schedule.brightness\"")?;
+                    schedule.push((from, brightness as u8));
+                }
+            }
+            Some(_) => return Err("\"schedule\" must be an array of tables".to_string()),
+            None => (),
+        }
+        schedule.sort_by_key(|&(from, _)| from);
+
+        let mut display_config = DisplayConfig::default();
+        let mut dwell = Vec::new();
+        let mut shown = Vec::new();
+        if let Some(display) = root.get("display") {
+            let display = display.as_table().ok_or("\"display\" must be a table")?;
+            display_config = DisplayConfig::parse(display).map_err(|err| format!("display: {err}"))?;
+            shown = match display.get("metric") {
+                Some(Value::String(name)) => vec![metric_name(name)],
+                Some(Value::Array(names)) => names
+                    .iter()
+                    .map(|name| name.as_str().map(metric_name))
+                    .collect::<Option<_>>()
+                    .ok_or("\"display.metric\" must be a string or an array of strings")?,
+                Some(value) => {
+                    return Err(format!(
+                        "\"display.metric\" must be a string or an array of strings, found {}",
+                        value.type_name()
+                    ))
+                }
+                None => Vec::new(),
+            };
+            if let Some(modes) = display.get("dwell") {
+                let modes = modes.as_table().ok_or("\"display.dwell\" must be a table")?;
+                for mode in modes.keys() {
+                    if !["temp", "usage"].contains(&mode.as_str()) {
+                        return Err(format!("unknown mode \"{mode}\" in \"display.dwell\""));
+                    }
+                    let seconds = get_int(modes, mode, 3600)?.filter(|&seconds| seconds > 0);
+                    dwell.push((
+                        mode.clone(),
+                        seconds.ok_or(format!("\"{mode}\" must be at least 1 second"))? as u64,
+                    ));
+                }
+            }
+        }
+
+        let mut devices = Vec::new();
+        if let Some(sections) = root.get("device") {
+            let sections = sections.as_table().ok_or("\"device\" must be a table")?;
+            for (name, section) in sections {
+                let section = section.as_table().ok_or(format!("\"device.{name}\" must be a table"))?;
+                let settings = DisplayConfig::parse(section).map_err(|err| format!("device.\"{name}\": {err}"))?;
+                devices.push((name.clone(), settings));
+            }
+        }
+
+        let hooks = match root.get("hooks") {
+            Some(Value::Array(entries)) => entries
+                .iter()
+                .map(|entry| Hook::parse(entry.as_table().ok_or("\"hooks\" must be an array of tables")?))
+                .collect::<Result<_, String>>()?,
+            Some(_) => return Err("\"hooks\" must be an array of tables".to_string()),
+            None => Vec::new(),
+        };
+
+        let metrics: Vec<Source> = match root.get("metrics") {
+            Some(Value::Array(entries)) => entries
+                .iter()
+                .map(|entry| Source::parse(entry.as_table().ok_or("\"metrics\" must be an array of tables")?))
+                .collect::<Result<_, String>>()?,
+            Some(_) => return Err("\"metrics\" must be an array of tables".to_string()),
+            None => Vec::new(),
+        };
+        for (index, metric) in metrics.iter().enumerate() {
+            if metrics[..index].iter().any(|other| other.name == metric.name) {
+                return Err(format!("metric \"{}\" is defined twice", metric.name));
+            }
+            // Computed metrics are calculated in order, after the other ones are read
+            let known = |name: &str| {
+                CPU_METRICS.contains(&name)
+                    || builtin_metrics().iter().any(|&(builtin, _)| builtin == name)
+                    || metrics
+                        .iter()
+                        .enumerate()
+                        .any(|(other, source)| source.name == name && (!source.is_computed() || other < index))
+            };
+            if let Some(name) = metric.reads().into_iter().find(|name| !known(name)) {
+                return Err(format!(
+                    "metric \"{}\" reads \"{name}\", which is not a built-in metric or a metric defined before it",
+                    metric.name
+                ));
+            }
+        }
+
+        let mut gpu = gpu::Settings::default();
+        if let Some(table) = root.get("gpu") {
+            let table = table.as_table().ok_or("\"gpu\" must be a table")?;
+            gpu.card = match (get_int(table, "index", u32::MAX as i64)?, get_str(table, "pci")?) {
+                (Some(index), None) => Some(Card::Index(index as u32)),
+                (None, Some(address)) => Some(Card::Pci(
+                    PciAddress::parse(address)
+                        .ok_or(format!("invalid PCI address \"{address}\", expected e.g. \"0000:03:00.0\""))?,
+                )),
+                (None, None) => None,
+                _ => return Err("\"gpu.index\" and \"gpu.pci\" cannot be used together".to_string()),
+            };
+            if let Some(name) = get_str(table, "temp")? {
+                gpu.temp = TempSensor::parse(name)
+                    .ok_or(format!("unknown GPU temperature \"{name}\", expected edge or junction"))?;
+            }
+        }
+
+        let drive = match root.get("drive") {
+            Some(table) => get_str(table.as_table().ok_or("\"drive\" must be a table")?, "name")?.map(str::to_string),
+            None => None,
+        };
+
+        let mqtt = match root.get("mqtt") {
+            Some(table) => Some(mqtt::Settings::parse(table.as_table().ok_or("\"mqtt\" must be a table")?)?),
+            None => None,
+        };
+
+        let influxdb = match root.get("influxdb") {
+            Some(table) => Some(influxdb::Settings::parse(
+                table.as_table().ok_or("\"influxdb\" must be a table")?,
+            )?),
+            None => None,
+        };
+
+        Ok(Config {
+            schedule,
+            display: display_config,
+            devices,
+            dwell,
+            hooks,
+            metrics,
+            shown,
+            gpu,
+            drive,
+            mqtt,
+            influxdb,
+        })
+    }
+
+    /// Returns the section of a device, matched by product ID or product name first, then by the first word of the
+    /// name (e.g. "AK620" for "AK620 DIGITAL").
+    pub fn device(&self, name: &str, product_id: u16) -> Option<&DisplayConfig> {
+        let exact = |key: &str| {
+            key.eq_ignore_ascii_case(name)
+                || key
+                    .strip_prefix("0x")
+                    .and_then(|hex| u16::from_str_radix(hex, 16).ok())
+                    .is_some_and(|id| id == product_id)
+        };
+        let first_word = |key: &str| {
+            name.split_whitespace()
+                .next()
+                .is_some_and(|word| word.eq_ignore_ascii_case(key))
+        };
+        self.devices
+            .iter()
+            .find(|(key, _)| exact(key))
+            .or_else(|| self.devices.iter().find(|(key, _)| first_word(key)))
+            .map(|(_, settings)| settings)
+    }
+
+    /// Returns the seconds the given mode is shown for when cycling, if it is set.
+    pub fn dwell(&self, mode: &str) -> Option<u64> {
+        self.dwell
+            .iter()
+            .find(|(name, _)| name == mode)
+            .map(|&(_, seconds)| seconds)
+    }
+
+    /// Returns the scheduled brightness at the given minute of the day, if there is a schedule.
+    ///
+    /// Before the first entry of the day, the last entry of the previous day still applies.
+    pub fn brightness_at(&self, minute: u32) -> Option<u8> {
+        self.schedule
+            .iter()
+            .rev()
+            .find(|&&(from, _)| from <= minute)
+            .or(self.schedule.last())
+            .map(|&(_, brightness)| brightness)
+    }
+}
+
+/// Reads a settings file without validating it.
+pub fn read_table(path: &Path) -> Result<Table, String> {
+    let text = read_to_string(path).map_err(|err| format!("{}: {err}", path.display()))?;
+    toml::parse(&text).map_err(|err| format!("{}: {err}", path.display()))
+}
+
+/// Returns the keys of a settings file that are not used, e.g. misspelled ones, as dotted paths.
+pub fn unknown_keys(root: &Table) -> Vec<String> {
+    let mut unknown = Vec::new();
+    let mut check = |table: &Table, prefix: &str, known: &[&str]| {
+        for key in table.keys().filter(|key| !known.contains(&key.as_str())) {
+            unknown.push(format!("{prefix}{key}"));
+        }
+    };
+    check(
+        root,
+        "",
+        &[
+            "schedule", "display", "device", "hooks", "metrics", "gpu", "drive", "mqtt", "influxdb",
+        ],
+    );
+    let tables = |key: &str| match root.get(key) {
+        Some(Value::Array(entries)) => entries.iter().filter_map(Value::as_table).collect(),
+        Some(value) => value.as_table().into_iter().collect(),
+        None => Vec::new(),
+    };
+    for entry in tables("schedule") {
+        check(entry, "schedule.", &["from", "brightness"]);
+    }
+    for display in tables("display") {
+        check(display, "display.", &["mode", "fahrenheit", "poll", "dwell", "metric"]);
+    }
+    for sections in tables("device") {
+        for (name, section) in sections {
+            if let Some(section) = section.as_table() {
+                check(section, &format!("device.\"{name}\"."), &["mode", "fahrenheit", "poll"]);
+            }
+        }
+    }
+    for entry in tables("hooks") {
+        check(entry, "hooks.", hooks::KEYS);
+    }
+    for entry in tables("metrics") {
+        check(entry, "metrics.", sources::KEYS);
+    }
+    for table in tables("gpu") {
+        check(table, "gpu.", &["index", "pci", "temp"]);
+    }
+    for table in tables("drive") {
+        check(table, "drive.", &["name"]);
+    }
+    for table in tables("mqtt") {
+        check(table, "mqtt.", mqtt::KEYS);
+    }
+    for table in tables("influxdb") {
+        check(table, "influxdb.", influxdb::KEYS);
+    }
+
+    unknown
+}
+
+/// Returns the settings files that exist, the system wide one first, then the one of the user in
+/// `$XDG_CONFIG_HOME/deepcool-digital/config.toml` (`~/.config` by default).
+pub fn layers() -> Vec<PathBuf> {
+    let user_dir = env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+    [
+        Some(PathBuf::from(CONFIG_PATH)),
+        user_dir.map(|dir| dir.join(USER_CONFIG_PATH)),
+    ]
+    .into_iter()
+    .flatten()
+    .filter(|path| path.exists())
+    .collect()
+}
+
+/// Parses a duration like `500ms`, `2.5s` or `1m`, a plain number is in milliseconds.
+pub fn parse_duration(text: &str) -> Result<Duration, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid duration \"{text}\", expected e.g. \"500ms\" or \"2.5s\""))?;
+    let seconds = match unit.trim() {
+        "" | "ms" => number / 1000.0,
+        "s" => number,
+        "m" | "min" => number * 60.0,
+        unit => return Err(format!("unknown unit \"{unit}\" in \"{text}\", expected ms, s or m")),
+    };
+
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("invalid duration \"{text}\""))
+}
+
+/// Parses an update interval, from `MIN_POLL` to `MAX_POLL`.
+pub fn parse_poll(text: &str) -> Result<Duration, String> {
+    match parse_duration(text)? {
+        poll if (MIN_POLL..=MAX_POLL).contains(&poll) => Ok(poll),
+        poll => Err(format!(
+            "{poll:?} is out of range, it must be between {MIN_POLL:?} and {MAX_POLL:?}"
+        )),
+    }
+}
+
+/// Parses a `HH:MM` time into the minute of the day.
+fn parse_time(time: &str) -> Option<u32> {
+    let (hours, minutes) = time.split_once(':')?;
+    let hours: u32 = hours.parse().ok().filter(|&hours| hours < 24)?;
+    let minutes: u32 = minutes.parse().ok().filter(|&minutes| minutes < 60)?;
+
+    Some(hours * 60 + minutes)
+}
+
+/// Returns the current minute of the day in local time.
+pub fn local_minute() -> u32 {
+    let time = local_time();
+    (time.tm_hour * 60 + time.tm_min) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drive_section_is_known() {
+        let path = env::temp_dir().join(format!("deepcool-config-{}.toml", std::process::id()));
+        std::fs::write(&path, "[drive]\nname = \"nvme1\"\n").unwrap();
+        let root = read_table(&path);
+        let config = Config::load_layers(std::slice::from_ref(&path));
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(config.unwrap().drive.as_deref(), Some("nvme1"));
+        assert!(unknown_keys(&root.unwrap()).is_empty());
+
+        let root = toml::parse("[drive]\nname = \"sda\"\nmodel = \"WD\"\n").unwrap();
+        assert_eq!(unknown_keys(&root), ["drive.model"]);
+        assert_eq!(
+            Config::from_table(&toml::parse("drive = \"sda\"").unwrap())
+                .err()
+                .as_deref(),
+            Some("\"drive\" must be a table")
+        );
+    }
+
+    #[test]
+    fn durations() {
+        let ms = Duration::from_millis;
+        assert_eq!(parse_duration("500ms"), Ok(ms(500)));
+        assert_eq!(parse_duration("750"), Ok(ms(750)));
+        assert_eq!(parse_duration(" 2.5s "), Ok(ms(2500)));
+        assert_eq!(parse_duration("1 min"), Ok(Duration::from_secs(60)));
+        assert_eq!(parse_duration("0.5m"), Ok(Duration::from_secs(30)));
+
+        assert_eq!(
+            parse_duration("fast"),
+            Err("invalid duration \"fast\", expected e.g. \"500ms\" or \"2.5s\"".to_string())
+        );
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("1.2.3s").is_err());
+        assert!(parse_duration("-1s").is_err());
+        assert_eq!(
+            parse_duration("2h"),
+            Err("unknown unit \"h\" in \"2h\", expected ms, s or m".to_string())
+        );
+        assert_eq!(
+            parse_duration("1e3s"),
+            Err("unknown unit \"e3s\" in \"1e3s\", expected ms, s or m".to_string())
+        );
+        assert_eq!(
+            parse_duration("100000000000000000000s"),
+            Err("invalid duration \"100000000000000000000s\"".to_string())
+        );
+    }
+
+    #[test]
+    fn poll_intervals() {
+        assert_eq!(parse_poll("100ms"), Ok(MIN_POLL));
+        assert_eq!(parse_poll("1m"), Ok(MAX_POLL));
+        assert_eq!(
+            parse_poll("99"),
+            Err("99ms is out of range, it must be between 100ms and 60s".to_string())
+        );
+        assert!(parse_poll("61s").is_err());
+        assert!(parse_poll("soon").is_err());
+
+        let root = toml::parse("[display]\npoll = true").unwrap();
+        assert_eq!(
+            Config::from_table(&root).err().as_deref(),
+            Some("display: \"poll\" must be a duration, found boolean")
+        );
+        let root = toml::parse("[device.\"CH560\"]\npoll = \"10ms\"").unwrap();
+        assert_eq!(
+            Config::from_table(&root).err().as_deref(),
+            Some("device.\"CH560\": \"poll\": 10ms is out of range, it must be between 100ms and 60s")
+        );
+    }
+}
@@ -0,0 +1,103 @@
+//! Control socket, scripts and desktop widgets send one command per line and get one line back.
+//!
+//! ```text
+//! next                switch to the next display mode
+//! unit                toggle the temperature unit
+//! brightness <0-100>  set the brightness
+//! blank <on|off>      blank the display or show it again
+//! status              print the last readings, e.g. "temp=54 unit=C usage=12 power=35 alarm=off"
+//! ```
+
+use crate::{devices::RunOptions, log::warning, timer};
+use std::{
+    fs::{create_dir_all, remove_file},
+    io::{BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::Path,
+    sync::atomic::Ordering,
+    thread,
+};
+
+/// Default location of the control socket
+pub const SOCKET_PATH: &str = "/run/deepcool/control.sock";
+
+/// Listens on the control socket on a separate thread.
+pub fn serve(path: &Path, options: RunOptions) -> std::io::Result<()> {
+    if let Some(dir) = path.parent() {
+        create_dir_all(dir)?;
+    }
+    // A socket left behind by a previous run would make the bind fail
+    let _ = remove_file(path);
+    let listener = UnixListener::bind(path)?;
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(err) = handle(stream, &options) {
+                        warning!("Control connection failed: {err}");
+                    }
+                }
+                Err(err) => warning!("Control socket failed: {err}"),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Answers the commands of a single connection.
+fn handle(stream: UnixStream, options: &RunOptions) -> std::io::Result<()> {
+    let mut writer = stream.try_clone()?;
+    for line in BufReader::new(stream).lines() {
+        let reply = match execute(line?.trim(), options) {
+            Ok(reply) => reply,
+            Err(err) => format!("error: {err}"),
+        };
+        writeln!(writer, "{reply}")?;
+    }
+
+    Ok(())
+}
+
+/// Runs a single command and returns the reply.
+fn execute(command: &str, options: &RunOptions) -> Result<String, String> {
+    let mut words = command.split_whitespace();
+    match (words.next(), words.next(), words.next()) {
+        (Some("next"), None, None) => {
+            options.next_mode.fetch_add(1, Ordering::Relaxed);
+        }
+        (Some("unit"), None, None) => {
+            options.fahrenheit.fetch_xor(true, Ordering::Relaxed);
+        }
+        (Some("brightness"), Some(level), None) => {
+            let level = level
+                .parse::<u8>()
+                .ok()
+                .filter(|level| *level <= 100)
+                .ok_or(format!("invalid brightness \"{level}\", expected 0-100"))?;
+            options.brightness.store(level, Ordering::Relaxed);
+        }
+        (Some("blank"), Some(state @ ("on" | "off")), None) => {
+            options.blank.store(state == "on", Ordering::Relaxed);
+        }
+        (Some("status"), None, None) => {
+            let status = options.status.lock().unwrap();
+            return match &*status {
+                Some(status) => Ok(format!(
+                    "temp={} unit={} usage={} power={} alarm={}",
+                    status.temp,
+                    if status.fahrenheit { "F" } else { "C" },
+                    status.usage,
+                    status.power,
+                    if status.overheat { "on" } else { "off" }
+                )),
+                None => Err("no readings yet".to_string()),
+            };
+        }
+        _ => return Err(format!("unknown command \"{command}\"")),
+    }
+    timer::wake();
+
+    Ok("ok".to_string())
+}
@@ -0,0 +1,50 @@
+//! Running in the background for init systems that do not supervise the program themselves, like OpenRC.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io,
+    os::fd::AsRawFd,
+    path::Path,
+    process,
+};
+
+/// Detaches the program from the terminal: forks twice so it is not a session leader and cannot get a terminal back,
+/// starts a new session, moves to `/` and redirects stdin to `/dev/null` and stdout & stderr to `log`.
+///
+/// Only the final process returns, it has to be called before any thread is started.
+pub fn daemonize(log: &Path) -> Result<(), String> {
+    let output = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(log)
+        .map_err(|err| format!("Failed to open {}: {err}", log.display()))?;
+    let null = File::open("/dev/null").map_err(|err| format!("Failed to open /dev/null: {err}"))?;
+
+    unsafe {
+        fork()?;
+        if libc::setsid() == -1 {
+            return Err(format!("Failed to start a new session: {}", io::Error::last_os_error()));
+        }
+        fork()?;
+        libc::chdir(c"/".as_ptr());
+        libc::dup2(null.as_raw_fd(), libc::STDIN_FILENO);
+        libc::dup2(output.as_raw_fd(), libc::STDOUT_FILENO);
+        libc::dup2(output.as_raw_fd(), libc::STDERR_FILENO);
+    }
+
+    Ok(())
+}
+
+/// Forks, the parent exits right away.
+unsafe fn fork() -> Result<(), String> {
+    match libc::fork() {
+        -1 => Err(format!("Failed to fork: {}", io::Error::last_os_error())),
+        0 => Ok(()),
+        _ => libc::_exit(0),
+    }
+}
+
+/// Writes the PID of the program to a file.
+pub fn write_pidfile(path: &Path) -> Result<(), String> {
+    fs::write(path, format!("{}\n", process::id())).map_err(|err| format!("Failed to write {}: {err}", path.display()))
+}
@@ -0,0 +1,625 @@
+//! D-Bus service on the system bus.
+//!
+//! The `org.deepcool.Display` service exports `/org/deepcool/Display` with:
+//! - methods: `NextMode()`, `ToggleUnit()`, `SetBrightness(y level)`, `SetBlank(b blank)`
+//! - properties: `Temperature` (y), `Fahrenheit` (b), `Utilization` (y), `Power` (q), `Alarm` (b), `Brightness` (y)
+//!
+//! Only the small part of the wire protocol needed for that is implemented, the bus has to allow the name with the
+//! policy in `contrib/dbus/org.deepcool.Display.conf`.
+
+use crate::{devices::RunOptions, log::error, timer};
+use std::{
+    env,
+    io::{self, BufRead, BufReader, Read, Write},
+    os::unix::net::UnixStream,
+    sync::atomic::Ordering,
+    thread,
+};
+
+const SYSTEM_BUS: &str = "/run/dbus/system_bus_socket";
+const NAME: &str = "org.deepcool.Display";
+const PATH: &str = "/org/deepcool/Display";
+const INTERFACE: &str = "org.deepcool.Display";
+const PROPERTIES: &str = "org.freedesktop.DBus.Properties";
+const INTROSPECTABLE: &str = "org.freedesktop.DBus.Introspectable";
+
+const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="org.deepcool.Display">
+    <method name="NextMode"/>
+    <method name="ToggleUnit"/>
+    <method name="SetBrightness"><arg name="level" type="y" direction="in"/></method>
+    <method name="SetBlank"><arg name="blank" type="b" direction="in"/></method>
+    <property name="Temperature" type="y" access="read"/>
+    <property name="Fahrenheit" type="b" access="read"/>
+    <property name="Utilization" type="y" access="read"/>
+    <property name="Power" type="q" access="read"/>
+    <property name="Alarm" type="b" access="read"/>
+    <property name="Brightness" type="y" access="read"/>
+  </interface>
+  <interface name="org.freedesktop.DBus.Properties">
+    <method name="Get">
+      <arg name="interface" type="s" direction="in"/><arg name="name" type="s" direction="in"/>
+      <arg name="value" type="v" direction="out"/>
+    </method>
+    <method name="GetAll">
+      <arg name="interface" type="s" direction="in"/><arg name="values" type="a{sv}" direction="out"/>
+    </method>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect"><arg name="data" type="s" direction="out"/></method>
+  </interface>
+</node>
+"#;
+
+/// Message types
+const METHOD_CALL: u8 = 1;
+const METHOD_RETURN: u8 = 2;
+const ERROR: u8 = 3;
+
+/// Header fields
+const FIELD_PATH: u8 = 1;
+const FIELD_INTERFACE: u8 = 2;
+const FIELD_MEMBER: u8 = 3;
+const FIELD_ERROR_NAME: u8 = 4;
+const FIELD_REPLY_SERIAL: u8 = 5;
+const FIELD_DESTINATION: u8 = 6;
+const FIELD_SENDER: u8 = 7;
+const FIELD_SIGNATURE: u8 = 8;
+
+/// Value of a property or argument.
+enum Value {
+    Byte(u8),
+    Bool(bool),
+    U16(u16),
+    U32(u32),
+    Str(String),
+    Path(String),
+    Signature(String),
+}
+
+impl Value {
+    fn signature(&self) -> &'static str {
+        match self {
+            Value::Byte(_) => "y",
+            Value::Bool(_) => "b",
+            Value::U16(_) => "q",
+            Value::U32(_) => "u",
+            Value::Str(_) => "s",
+            Value::Path(_) => "o",
+            Value::Signature(_) => "g",
+        }
+    }
+}
+
+/// Builds the body or the header of a message, always little-endian.
+#[derive(Default)]
+struct Encoder {
+    data: Vec<u8>,
+}
+
+impl Encoder {
+    fn align(&mut self, alignment: usize) {
+        while !self.data.len().is_multiple_of(alignment) {
+            self.data.push(0);
+        }
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.align(4);
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len() as u32);
+        self.data.extend_from_slice(value.as_bytes());
+        self.data.push(0);
+    }
+
+    fn signature(&mut self, value: &str) {
+        self.data.push(value.len() as u8);
+        self.data.extend_from_slice(value.as_bytes());
+        self.data.push(0);
+    }
+
+    fn value(&mut self, value: &Value) {
+        match value {
+            Value::Byte(byte) => self.data.push(*byte),
+            Value::Bool(bool) => self.u32(*bool as u32),
+            Value::U16(value) => {
+                self.align(2);
+                self.data.extend_from_slice(&value.to_le_bytes());
+            }
+            Value::U32(value) => self.u32(*value),
+            Value::Str(string) | Value::Path(string) => self.string(string),
+            Value::Signature(signature) => self.signature(signature),
+        }
+    }
+
+    fn variant(&mut self, value: &Value) {
+        self.signature(value.signature());
+        self.value(value);
+    }
+}
+
+/// Reads the body or the header of a received message.
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    big_endian: bool,
+}
+
+impl Decoder<'_> {
+    fn align(&mut self, alignment: usize) {
+        self.pos = self.pos.div_ceil(alignment) * alignment;
+    }
+
+    fn take(&mut self, length: usize) -> Option<&[u8]> {
+        let bytes = self.data.get(self.pos..self.pos + length)?;
+        self.pos += length;
+        Some(bytes)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        self.align(4);
+        let bytes: [u8; 4] = self.take(4)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let length = self.u32()? as usize;
+        let string = String::from_utf8(self.take(length)?.to_vec()).ok()?;
+        self.pos += 1;
+        Some(string)
+    }
+
+    fn signature(&mut self) -> Option<String> {
+        let length = self.u8()? as usize;
+        let signature = String::from_utf8(self.take(length)?.to_vec()).ok()?;
+        self.pos += 1;
+        Some(signature)
+    }
+}
+
+#[derive(Default)]
+struct Message {
+    kind: u8,
+    serial: u32,
+    path: Option<String>,
+    interface: Option<String>,
+    member: Option<String>,
+    error_name: Option<String>,
+    reply_serial: Option<u32>,
+    destination: Option<String>,
+    sender: Option<String>,
+    signature: String,
+    body: Vec<u8>,
+    big_endian: bool,
+}
+
+impl Message {
+    fn method_call(serial: u32, destination: &str, path: &str, interface: &str, member: &str) -> Self {
+        Message {
+            kind: METHOD_CALL,
+            serial,
+            path: Some(path.to_string()),
+            interface: Some(interface.to_string()),
+            member: Some(member.to_string()),
+            destination: Some(destination.to_string()),
+            ..Default::default()
+        }
+    }
+
+    /// Builds the reply to this message.
+    fn reply(&self, serial: u32, kind: u8) -> Self {
+        Message {
+            kind,
+            serial,
+            reply_serial: Some(self.serial),
+            destination: self.sender.clone(),
+            ..Default::default()
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut header = Encoder::default();
+        header.data.extend_from_slice(&[b'l', self.kind, 0, 1]);
+        header.u32(self.body.len() as u32);
+        header.u32(self.serial);
+
+        let mut fields = Encoder { data: vec![0; 16] };
+        let values = [
+            (FIELD_PATH, self.path.clone().map(Value::Path)),
+            (FIELD_INTERFACE, self.interface.clone().map(Value::Str)),
+            (FIELD_MEMBER, self.member.clone().map(Value::Str)),
+            (FIELD_ERROR_NAME, self.error_name.clone().map(Value::Str)),
+            (FIELD_DESTINATION, self.destination.clone().map(Value::Str)),
+            (FIELD_REPLY_SERIAL, self.reply_serial.map(Value::U32)),
+            (
+                FIELD_SIGNATURE,
+                Some(Value::Signature(self.signature.clone())).filter(|_| !self.signature.is_empty()),
+            ),
+        ];
+        for (code, value) in values {
+            if let Some(value) = value {
+                fields.align(8);
+                fields.data.push(code);
+                fields.variant(&value);
+            }
+        }
+
+        // The fields were encoded at their offset in the message to get the alignment right
+        header.u32((fields.data.len() - 16) as u32);
+        header.data.extend_from_slice(&fields.data[16..]);
+        header.align(8);
+        header.data.extend_from_slice(&self.body);
+
+        header.data
+    }
+
+    fn read(stream: &mut impl Read) -> io::Result<Self> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "malformed D-Bus message");
+
+        let mut fixed = [0; 16];
+        stream.read_exact(&mut fixed)?;
+        let big_endian = fixed[0] == b'B';
+        let mut decoder = Decoder {
+            data: &fixed,
+            pos: 4,
+            big_endian,
+        };
+        let body_length = decoder.u32().ok_or_else(invalid)? as usize;
+        let serial = decoder.u32().ok_or_else(invalid)?;
+        let fields_length = decoder.u32().ok_or_else(invalid)? as usize;
+
+        // Fields, padding to 8 bytes, then the body
+        let padded = (16 + fields_length).div_ceil(8) * 8 - 16;
+        let mut rest = vec![0; padded + body_length];
+        stream.read_exact(&mut rest)?;
+        let mut data = fixed.to_vec();
+        data.extend_from_slice(&rest);
+
+        let mut message = Message {
+            kind: fixed[1],
+            serial,
+            body: data[16 + padded..].to_vec(),
+            big_endian,
+            ..Default::default()
+        };
+        let mut decoder = Decoder {
+            data: &data[..16 + fields_length],
+            pos: 16,
+            big_endian,
+        };
+        while decoder.pos < 16 + fields_length {
+            decoder.align(8);
+            let code = decoder.u8().ok_or_else(invalid)?;
+            let signature = decoder.signature().ok_or_else(invalid)?;
+            match signature.as_str() {
+                "s" | "o" => {
+                    let value = decoder.string().ok_or_else(invalid)?;
+                    match code {
+                        FIELD_PATH => message.path = Some(value),
+                        FIELD_INTERFACE => message.interface = Some(value),
+                        FIELD_MEMBER => message.member = Some(value),
+                        FIELD_ERROR_NAME => message.error_name = Some(value),
+                        FIELD_DESTINATION => message.destination = Some(value),
+                        FIELD_SENDER => message.sender = Some(value),
+                        _ => (),
+                    }
+                }
+                "g" => {
+                    let value = decoder.signature().ok_or_else(invalid)?;
+                    if code == FIELD_SIGNATURE {
+                        message.signature = value;
+                    }
+                }
+                "u" => {
+                    let value = decoder.u32().ok_or_else(invalid)?;
+                    if code == FIELD_REPLY_SERIAL {
+                        message.reply_serial = Some(value);
+                    }
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(message)
+    }
+
+    /// Returns a decoder for the body.
+    fn body(&self) -> Decoder<'_> {
+        Decoder {
+            data: &self.body,
+            pos: 0,
+            big_endian: self.big_endian,
+        }
+    }
+}
+
+/// Connects to the system bus, claims the service name and answers the calls on a separate thread.
+pub fn serve(options: RunOptions) -> io::Result<()> {
+    let path = match env::var("DBUS_SYSTEM_BUS_ADDRESS") {
+        Ok(address) => address
+            .strip_prefix("unix:path=")
+            .map(|path| path.split(',').next().unwrap_or(path).to_string())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Unsupported, format!("unsupported address {address}")))?,
+        Err(_) => SYSTEM_BUS.to_string(),
+    };
+    let mut stream = UnixStream::connect(path)?;
+    authenticate(&mut stream)?;
+
+    let mut serial = 1;
+    let mut call = |stream: &mut UnixStream, member: &str, body: Encoder, signature: &str| {
+        let mut message = Message::method_call(
+            serial,
+            "org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus",
+            member,
+        );
+        message.body = body.data;
+        message.signature = signature.to_string();
+        serial += 1;
+        stream.write_all(&message.encode())
+    };
+    call(&mut stream, "Hello", Encoder::default(), "")?;
+    let mut name = Encoder::default();
+    name.string(NAME);
+    name.u32(0);
+    call(&mut stream, "RequestName", name, "su")?;
+
+    thread::spawn(move || {
+        if let Err(err) = answer(stream, serial, &options) {
+            error!("D-Bus connection lost: {err}");
+        }
+    });
+
+    Ok(())
+}
+
+/// Authenticates with the credentials of the process.
+fn authenticate(stream: &mut UnixStream) -> io::Result<()> {
+    let uid = unsafe { libc::getuid() }.to_string();
+    let hex: String = uid.bytes().map(|byte| format!("{byte:02x}")).collect();
+    stream.write_all(format!("\0AUTH EXTERNAL {hex}\r\n").as_bytes())?;
+
+    let mut line = String::new();
+    BufReader::new(&mut *stream).read_line(&mut line)?;
+    if !line.starts_with("OK") {
+        return Err(io::Error::new(io::ErrorKind::PermissionDenied, line.trim().to_string()));
+    }
+
+    stream.write_all(b"BEGIN\r\n")
+}
+
+/// Answers the method calls until the connection is closed.
+fn answer(mut stream: UnixStream, mut serial: u32, options: &RunOptions) -> io::Result<()> {
+    loop {
+        let message = Message::read(&mut stream)?;
+        if message.kind == ERROR && message.reply_serial.is_some() {
+            error!("D-Bus error: {}", message.error_name.as_deref().unwrap_or("unknown"));
+        }
+        if message.kind != METHOD_CALL {
+            continue;
+        }
+
+        let reply = match handle(&message, options) {
+            Ok((signature, body)) => {
+                let mut reply = message.reply(serial, METHOD_RETURN);
+                reply.signature = signature.to_string();
+                reply.body = body.data;
+                reply
+            }
+            Err((name, text)) => {
+                let mut reply = message.reply(serial, ERROR);
+                let mut body = Encoder::default();
+                body.string(&text);
+                reply.error_name = Some(name.to_string());
+                reply.signature = "s".to_string();
+                reply.body = body.data;
+                reply
+            }
+        };
+        serial += 1;
+        stream.write_all(&reply.encode())?;
+    }
+}
+
+/// Runs a method call, returns the signature and the body of the reply, or the name and the text of the error.
+fn handle(message: &Message, options: &RunOptions) -> Result<(&'static str, Encoder), (&'static str, String)> {
+    let unknown_method = || {
+        (
+            "org.freedesktop.DBus.Error.UnknownMethod",
+            format!("unknown method {}", message.member.as_deref().unwrap_or("")),
+        )
+    };
+    let invalid_args = || ("org.freedesktop.DBus.Error.InvalidArgs", "invalid arguments".to_string());
+    if message.path.as_deref() != Some(PATH) {
+        return Err(("org.freedesktop.DBus.Error.UnknownObject", "unknown object".to_string()));
+    }
+
+    let mut body = Encoder::default();
+    let member = message.member.as_deref().unwrap_or("");
+    match (message.interface.as_deref().unwrap_or(INTERFACE), member) {
+        (INTERFACE, "NextMode") => {
+            options.next_mode.fetch_add(1, Ordering::Relaxed);
+            timer::wake();
+            Ok(("", body))
+        }
+        (INTERFACE, "ToggleUnit") => {
+            options.fahrenheit.fetch_xor(true, Ordering::Relaxed);
+            timer::wake();
+            Ok(("", body))
+        }
+        (INTERFACE, "SetBrightness") if message.signature == "y" => {
+            let level = message
+                .body()
+                .u8()
+                .filter(|level| *level <= 100)
+                .ok_or_else(invalid_args)?;
+            options.brightness.store(level, Ordering::Relaxed);
+            timer::wake();
+            Ok(("", body))
+        }
+        (INTERFACE, "SetBlank") if message.signature == "b" => {
+            let blank = message.body().u32().ok_or_else(invalid_args)?;
+            options.blank.store(blank != 0, Ordering::Relaxed);
+            timer::wake();
+            Ok(("", body))
+        }
+        (PROPERTIES, "Get") if message.signature == "ss" => {
+            let mut args = message.body();
+            let (_, name) = args.string().zip(args.string()).ok_or_else(invalid_args)?;
+            let value = properties(options)
+                .into_iter()
+                .find(|(property, _)| *property == name)
+                .ok_or_else(invalid_args)?;
+            body.variant(&value.1);
+            Ok(("v", body))
+        }
+        (PROPERTIES, "GetAll") if message.signature == "s" => {
+            let values = properties(options);
+            // The array length is written once the entries are encoded
+            body.u32(0);
+            body.align(8);
+            let start = body.data.len();
+            for (name, value) in values {
+                body.align(8);
+                body.string(name);
+                body.variant(&value);
+            }
+            let length = (body.data.len() - start) as u32;
+            body.data[..4].copy_from_slice(&length.to_le_bytes());
+            Ok(("a{sv}", body))
+        }
+        (INTROSPECTABLE, "Introspect") => {
+            body.string(INTROSPECTION);
+            Ok(("s", body))
+        }
+        _ => Err(unknown_method()),
+    }
+}
+
+/// Returns the current values of the properties.
+fn properties(options: &RunOptions) -> Vec<(&'static str, Value)> {
+    let status = *options.status.lock().unwrap();
+    vec![
+        ("Temperature", Value::Byte(status.map_or(0, |status| status.temp))),
+        ("Fahrenheit", Value::Bool(options.fahrenheit.load(Ordering::Relaxed))),
+        ("Utilization", Value::Byte(status.map_or(0, |status| status.usage))),
+        ("Power", Value::U16(status.map_or(0, |status| status.power))),
+        ("Alarm", Value::Bool(status.is_some_and(|status| status.overheat))),
+        ("Brightness", Value::Byte(options.brightness.load(Ordering::Relaxed))),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_a_method_call() {
+        let message = Message::method_call(1, "d", "/", "i", "m");
+        #[rustfmt::skip]
+        assert_eq!(message.encode(), [
+            b'l', METHOD_CALL, 0, 1, 0, 0, 0, 0, 1, 0, 0, 0, 58, 0, 0, 0,
+            FIELD_PATH, 1, b'o', 0, 1, 0, 0, 0, b'/', 0, 0, 0, 0, 0, 0, 0,
+            FIELD_INTERFACE, 1, b's', 0, 1, 0, 0, 0, b'i', 0, 0, 0, 0, 0, 0, 0,
+            FIELD_MEMBER, 1, b's', 0, 1, 0, 0, 0, b'm', 0, 0, 0, 0, 0, 0, 0,
+            FIELD_DESTINATION, 1, b's', 0, 1, 0, 0, 0, b'd', 0, 0, 0, 0, 0, 0, 0,
+        ]);
+    }
+
+    #[test]
+    fn method_call_round_trip() {
+        let mut call = Message::method_call(7, NAME, PATH, INTERFACE, "SetBrightness");
+        call.signature = "y".to_string();
+        call.body = vec![42];
+        call.sender = Some(":1.5".to_string());
+        let data = call.encode();
+        // The body starts on a multiple of 8
+        assert_eq!((data.len() - 1) % 8, 0);
+
+        let read = Message::read(&mut data.as_slice()).unwrap();
+        assert_eq!(read.kind, METHOD_CALL);
+        assert_eq!(read.serial, 7);
+        assert_eq!(read.destination.as_deref(), Some(NAME));
+        assert_eq!(read.path.as_deref(), Some(PATH));
+        assert_eq!(read.interface.as_deref(), Some(INTERFACE));
+        assert_eq!(read.member.as_deref(), Some("SetBrightness"));
+        assert_eq!(read.signature, "y");
+        assert_eq!(read.body().u8(), Some(42));
+        assert!(!read.big_endian);
+    }
+
+    #[test]
+    fn reply_round_trip() {
+        let mut call = Message::method_call(7, NAME, PATH, PROPERTIES, "GetAll");
+        call.sender = Some(":1.5".to_string());
+
+        let mut reply = call.reply(3, METHOD_RETURN);
+        let mut body = Encoder::default();
+        body.u32(0);
+        body.variant(&Value::U16(350));
+        reply.signature = "uv".to_string();
+        reply.body = body.data;
+        let read = Message::read(&mut reply.encode().as_slice()).unwrap();
+        assert_eq!(read.kind, METHOD_RETURN);
+        assert_eq!(read.serial, 3);
+        assert_eq!(read.reply_serial, Some(7));
+        assert_eq!(read.destination.as_deref(), Some(":1.5"));
+        assert_eq!(read.signature, "uv");
+        let mut args = read.body();
+        assert_eq!(args.u32(), Some(0));
+        assert_eq!(args.signature().as_deref(), Some("q"));
+        args.align(2);
+        assert_eq!(args.take(2), Some(&350u16.to_le_bytes()[..]));
+
+        let mut error = call.reply(4, ERROR);
+        let mut body = Encoder::default();
+        body.string("invalid arguments");
+        error.error_name = Some("org.freedesktop.DBus.Error.InvalidArgs".to_string());
+        error.signature = "s".to_string();
+        error.body = body.data;
+        let read = Message::read(&mut error.encode().as_slice()).unwrap();
+        assert_eq!(read.kind, ERROR);
+        assert_eq!(read.reply_serial, Some(7));
+        assert_eq!(read.error_name.as_deref(), Some("org.freedesktop.DBus.Error.InvalidArgs"));
+        assert_eq!(read.body().string().as_deref(), Some("invalid arguments"));
+    }
+
+    #[test]
+    fn decodes_big_endian() {
+        let mut decoder = Decoder {
+            data: &[0, 0, 0, 42, 0, 0, 0, 2, b'o', b'k', 0],
+            pos: 0,
+            big_endian: true,
+        };
+        assert_eq!(decoder.u32(), Some(42));
+        assert_eq!(decoder.string().as_deref(), Some("ok"));
+        assert_eq!(decoder.u8(), None);
+    }
+
+    #[test]
+    fn rejects_truncated_messages() {
+        let data = Message::method_call(1, NAME, PATH, INTERFACE, "NextMode").encode();
+        let err = Message::read(&mut &data[..data.len() - 1]).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+
+        // A field with an unknown type
+        let mut data = data.clone();
+        data[18] = b'x';
+        let err = Message::read(&mut data.as_slice()).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
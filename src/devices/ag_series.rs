@@ -0,0 +1,98 @@
+//! AG400 DIGITAL & AG620 DIGITAL
+//!
+//! The AG series moves the numeric display in front of the status bar, and the bar itself is
+//! encoded as a bitmask of the lit segments instead of a count.
+
+use super::{digits, mode_name, on_off, Alarm, Capabilities, DeviceDriver, ModeCycle, PacketField, Status};
+use std::time::Duration;
+
+const POLLING_RATE: Duration = Duration::from_millis(750);
+
+pub struct Display {
+    mode: ModeCycle,
+    alarm: bool,
+}
+
+impl Display {
+    pub fn new(mode: &str, alarm: bool) -> Self {
+        Display {
+            mode: ModeCycle::new(mode),
+            alarm,
+        }
+    }
+
+    /// Encodes the utilization as a 10-segment bitmask, will show at least 1 segment.
+    fn status_bar(usage: u8) -> [u8; 2] {
+        let segments = (usage.saturating_add(5) / 10).clamp(1, 10);
+        ((1u16 << segments) - 1).to_be_bytes()
+    }
+}
+
+impl DeviceDriver for Display {
+    fn init(&self) -> Vec<[u8; 64]> {
+        let mut init_data: [u8; 64] = [0; 64];
+        init_data[0] = 16;
+        init_data[1] = 170;
+
+        vec![init_data]
+    }
+
+    fn build_status_packet(&mut self, status: &Status) -> [u8; 64] {
+        let (flag, value) = match self.mode.current() {
+            "usage" => (76, status.usage),
+            _ => (if status.fahrenheit { 35 } else { 19 }, status.temp),
+        };
+
+        // Data packet
+        let mut data: [u8; 64] = [0; 64];
+        data[0] = 16;
+        data[1] = flag;
+
+        // Main display
+        data[2] = value / 100;
+        data[3] = value % 100 / 10;
+        data[4] = value % 10;
+
+        // Status bar
+        let bar = Self::status_bar(status.usage);
+        data[5] = bar[0];
+        data[6] = bar[1];
+
+        // Alarm
+        data[7] = (self.alarm && status.overheat) as u8;
+
+        data
+    }
+
+    fn next_mode(&mut self) -> Option<String> {
+        Some(self.mode.advance().to_string())
+    }
+
+    fn set_mode(&mut self, mode: &str) {
+        self.mode = ModeCycle::new(mode);
+    }
+
+    fn describe(&self, packet: &[u8; 64]) -> Vec<PacketField> {
+        let mut fields = vec![
+            PacketField::new(0..1, "report id", ""),
+            PacketField::new(1..2, "mode", mode_name(packet[1])),
+        ];
+        if [19, 35, 76].contains(&packet[1]) {
+            let segments = u16::from_be_bytes([packet[5], packet[6]]).count_ones();
+            fields.push(PacketField::new(2..5, "digits", digits(&packet[2..5])));
+            fields.push(PacketField::new(5..7, "status bar", format!("{segments}/10")));
+            fields.push(PacketField::new(7..8, "alarm", on_off(packet[7])));
+        }
+
+        fields
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            display_modes: true,
+            alarm: Alarm::Configurable,
+            power: false,
+            polling_rate: POLLING_RATE,
+        }
+    }
+}
@@ -0,0 +1,54 @@
+//! AK400 DIGITAL
+//!
+//! The entry-level cooler has a smaller display that only reads the first seven bytes of the AK
+//! series layout, up to the alarm byte `D6`, the status packet is trimmed to them.
+
+use super::{ak_series, Capabilities, DeviceDriver, PacketField, Status};
+
+/// Bytes of the status packet read by the display
+const STATUS_LENGTH: usize = 7;
+
+pub struct Display {
+    base: ak_series::Display,
+}
+
+impl Display {
+    pub fn new(mode: &str, alarm: bool) -> Self {
+        Display {
+            base: ak_series::Display::new(mode, alarm),
+        }
+    }
+}
+
+impl DeviceDriver for Display {
+    fn init(&self) -> Vec<[u8; 64]> {
+        self.base.init()
+    }
+
+    fn build_status_packet(&mut self, status: &Status) -> [u8; 64] {
+        let mut data = self.base.build_status_packet(status);
+        data[STATUS_LENGTH..].fill(0);
+
+        data
+    }
+
+    fn handle_input(&mut self, report: &[u8]) -> bool {
+        self.base.handle_input(report)
+    }
+
+    fn next_mode(&mut self) -> Option<String> {
+        self.base.next_mode()
+    }
+
+    fn set_mode(&mut self, mode: &str) {
+        self.base.set_mode(mode);
+    }
+
+    fn describe(&self, packet: &[u8; 64]) -> Vec<PacketField> {
+        self.base.describe(packet)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.base.capabilities()
+    }
+}
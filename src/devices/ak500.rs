@@ -0,0 +1,77 @@
+//! AK500 DIGITAL
+//!
+//! Uses the AK series layout, but the display stays dark unless every packet, including the
+//! init sequence, has the display enable byte set, so a wake packet with only that byte is sent
+//! first.
+//!
+//! The enable byte comes from the report of a unit that stays dark with the plain AK series
+//! packets. `--protocol ak620` sends those packets instead, for the units that light up without
+//! the enable byte.
+
+use super::{ak_series, on_off, Capabilities, DeviceDriver, PacketField, Status};
+
+/// Display enable byte, `D7` in the AK series layout
+const DISPLAY_ON: usize = 7;
+
+pub struct Display {
+    base: ak_series::Display,
+}
+
+impl Display {
+    pub fn new(mode: &str, alarm: bool) -> Self {
+        Display {
+            base: ak_series::Display::new(mode, alarm),
+        }
+    }
+}
+
+impl DeviceDriver for Display {
+    /// Enables the display first, then plays the status bar animation.
+    fn init(&self) -> Vec<[u8; 64]> {
+        let mut wake_data: [u8; 64] = [0; 64];
+        wake_data[0] = 16;
+        wake_data[DISPLAY_ON] = 1;
+
+        let mut init_data = self.base.init();
+        for data in &mut init_data {
+            data[DISPLAY_ON] = 1;
+        }
+        init_data.insert(0, wake_data);
+
+        init_data
+    }
+
+    fn build_status_packet(&mut self, status: &Status) -> [u8; 64] {
+        let mut data = self.base.build_status_packet(status);
+        data[DISPLAY_ON] = 1;
+
+        data
+    }
+
+    fn handle_input(&mut self, report: &[u8]) -> bool {
+        self.base.handle_input(report)
+    }
+
+    fn next_mode(&mut self) -> Option<String> {
+        self.base.next_mode()
+    }
+
+    fn set_mode(&mut self, mode: &str) {
+        self.base.set_mode(mode);
+    }
+
+    fn describe(&self, packet: &[u8; 64]) -> Vec<PacketField> {
+        let mut fields = self.base.describe(packet);
+        fields.push(PacketField::new(
+            DISPLAY_ON..DISPLAY_ON + 1,
+            "display",
+            on_off(packet[DISPLAY_ON]),
+        ));
+
+        fields
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.base.capabilities()
+    }
+}
@@ -0,0 +1,158 @@
+//! AK620 DIGITAL PRO
+//!
+//! Unlike the original AK series, the Pro model shows the temperature, power consumption and
+//! utilization at the same time, so it uses a framed report similar to the LD series.
+//!
+//! The layout is the one of the LD series frames, worked out in
+//! [deepcool-ld-digital-hidapi](https://github.com/asdfzdfj/deepcool-ld-digital-hidapi): the
+//! same header, length, checksum and termination byte, with `6` as the device byte and the
+//! status payload below. It has not been checked against a capture of the DeepCool software
+//! yet, `--record` captures of a real unit are welcome.
+//!
+//! | Byte  | Status payload            |
+//! |-------|---------------------------|
+//! | 5     | command, `1`              |
+//! | 6     | unit, `1` for ˚F          |
+//! | 7-8   | power in W, big-endian    |
+//! | 9-10  | temperature, big-endian   |
+//! | 11    | utilization in percent    |
+//! | 12    | alarm                     |
+
+use super::{
+    check_frame, describe_frame, on_off, parse_version_frame, terminate_frame, version_query_frame, Alarm,
+    Capabilities, DeviceDriver, PacketField, Status,
+};
+use std::time::Duration;
+
+const POLLING_RATE: Duration = Duration::from_millis(1000);
+
+pub struct Display {
+    alarm: bool,
+}
+
+/// Status report of the display.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct StatusPacket {
+    /// CPU temperature, in the unit below
+    pub temp: u16,
+    /// The temperature is in Fahrenheit
+    pub fahrenheit: bool,
+    /// CPU power consumption in watts
+    pub power: u16,
+    /// CPU utilization in percent
+    pub utilization: u8,
+    /// Sets off the alarm of the display
+    pub alarm: bool,
+}
+
+impl StatusPacket {
+    /// Command of the status report
+    const COMMAND: u8 = 1;
+
+    /// Serializes the packet into a report, with its checksum and termination byte.
+    pub fn to_report(&self) -> [u8; 64] {
+        let power = self.power.to_be_bytes();
+        let temp = self.temp.to_be_bytes();
+        let payload = [
+            Self::COMMAND,
+            self.fahrenheit as u8,
+            power[0],
+            power[1],
+            temp[0],
+            temp[1],
+            self.utilization,
+            self.alarm as u8,
+        ];
+
+        // The length announced in the header counts the bytes of the payload
+        let mut data = Display::header();
+        data[4] = payload.len() as u8;
+        data[5..5 + payload.len()].copy_from_slice(&payload);
+        terminate_frame(&mut data, 5 + payload.len());
+
+        data
+    }
+}
+
+impl Display {
+    pub fn new(alarm: bool) -> Self {
+        Display { alarm }
+    }
+
+    /// Returns the common header of every packet.
+    fn header() -> [u8; 64] {
+        let mut data: [u8; 64] = [0; 64];
+        data[0] = 16;
+        data[1] = 104;
+        data[2] = 1;
+        data[3] = 6;
+
+        data
+    }
+}
+
+impl DeviceDriver for Display {
+    fn init(&self) -> Vec<[u8; 64]> {
+        let mut init_data = Self::header();
+        init_data[4] = 2;
+        init_data[5] = 3;
+        init_data[6] = 1;
+        terminate_frame(&mut init_data, 7);
+
+        vec![init_data]
+    }
+
+    fn build_status_packet(&mut self, status: &Status) -> [u8; 64] {
+        StatusPacket {
+            temp: status.temp as u16,
+            fahrenheit: status.fahrenheit,
+            power: status.power,
+            utilization: status.usage,
+            alarm: self.alarm && status.overheat,
+        }
+        .to_report()
+    }
+
+    fn brightness_packet(&self, level: u8) -> Option<[u8; 64]> {
+        let mut data = Self::header();
+        data[4] = 2;
+        data[5] = 4;
+        data[6] = level.min(100);
+        terminate_frame(&mut data, 7);
+
+        Some(data)
+    }
+
+    fn version_query(&self) -> Option<[u8; 64]> {
+        Some(version_query_frame(Self::header()))
+    }
+
+    fn parse_version(&self, reply: &[u8]) -> Option<String> {
+        parse_version_frame(reply)
+    }
+
+    fn describe(&self, packet: &[u8; 64]) -> Vec<PacketField> {
+        describe_frame(packet, || {
+            vec![
+                PacketField::new(6..7, "unit", if packet[6] == 1 { "˚F" } else { "˚C" }),
+                PacketField::new(7..9, "power", format!("{} W", u16::from_be_bytes([packet[7], packet[8]]))),
+                PacketField::new(9..11, "temp", u16::from_be_bytes([packet[9], packet[10]])),
+                PacketField::new(11..12, "usage", format!("{}%", packet[11])),
+                PacketField::new(12..13, "alarm", on_off(packet[12])),
+            ]
+        })
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            display_modes: false,
+            alarm: Alarm::Configurable,
+            power: true,
+            polling_rate: POLLING_RATE,
+        }
+    }
+
+    fn check_response(&self, response: &[u8]) -> Result<(), String> {
+        check_frame(response)
+    }
+}
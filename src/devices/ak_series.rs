@@ -1,99 +1,93 @@
-use crate::monitor::cpu;
-use hidapi::HidApi;
-use std::{thread::sleep, time::Duration};
+use super::{describe_ak, Alarm, Capabilities, DeviceDriver, ModeCycle, PacketField, Status};
+use crate::log::info;
+use std::time::Duration;
 
-const VENDOR: u16 = 0x3633;
-const POLLING_RATE: u64 = 750;
+const POLLING_RATE: Duration = Duration::from_millis(750);
+
+/// Input report sent when the mode button on the display is pressed
+const MODE_BUTTON: [u8; 2] = [16, 85];
 
 pub struct Display {
-    product_id: u16,
-    fahrenheit: bool,
+    mode: ModeCycle,
     alarm: bool,
 }
 
 impl Display {
-    pub fn new(product_id: u16, fahrenheit: bool, alarm: bool) -> Self {
+    pub fn new(mode: &str, alarm: bool) -> Self {
         Display {
-            product_id,
-            fahrenheit,
+            mode: ModeCycle::new(mode),
             alarm,
         }
     }
+}
 
-    pub fn run(&self, api: &HidApi, mode: &str, cpu_temp_sensor: &str) {
-        // Connect to device
-        let device = api.open(VENDOR, self.product_id).expect("Failed to open HID device");
+impl DeviceDriver for Display {
+    fn init(&self) -> Vec<[u8; 64]> {
+        let mut init_data: [u8; 64] = [0; 64];
+        init_data[0] = 16;
+        init_data[1] = 170;
+
+        vec![init_data]
+    }
 
+    fn build_status_packet(&mut self, status: &Status) -> [u8; 64] {
         // Data packet
         let mut data: [u8; 64] = [0; 64];
         data[0] = 16;
 
-        // Init sequence
-        {
-            let mut init_data = data.clone();
-            init_data[1] = 170;
-            device.write(&init_data).expect("Failed to write data");
-        }
-
-        // Display loop
-        if mode == "auto" {
-            loop {
-                for _ in 0..8 {
-                    device
-                        .write(&self.status_message(&data, "temp", &cpu_temp_sensor))
-                        .expect("Failed to write data");
-                }
-                for _ in 0..8 {
-                    device
-                        .write(&self.status_message(&data, "usage", &cpu_temp_sensor))
-                        .expect("Failed to write data");
-                }
-            }
-        } else {
-            loop {
-                device
-                    .write(&self.status_message(&data, &mode, &cpu_temp_sensor))
-                    .expect("Failed to write data");
-            }
-        }
-    }
-
-    /// Reads the CPU status information and returns the data packet.
-    fn status_message(&self, inital_data: &[u8; 64], mode: &str, cpu_temp_sensor: &str) -> [u8; 64] {
-        // Clone the data packet
-        let mut data = inital_data.clone();
-
-        // Read CPU utilization
-        let cpu_instant = cpu::read_instant();
-
-        // Wait
-        sleep(Duration::from_millis(POLLING_RATE));
-
-        // Calculate usage & temperature
-        let usage = cpu::get_usage(cpu_instant);
-        let temp = cpu::get_temp(cpu_temp_sensor, self.fahrenheit);
-
         // Main display
-        match mode {
+        match self.mode.current() {
             "temp" => {
-                data[1] = if self.fahrenheit { 35 } else { 19 };
-                data[3] = temp / 100;
-                data[4] = temp % 100 / 10;
-                data[5] = temp % 10;
+                data[1] = if status.fahrenheit { 35 } else { 19 };
+                data[3] = status.temp / 100;
+                data[4] = status.temp % 100 / 10;
+                data[5] = status.temp % 10;
             }
             "usage" => {
                 data[1] = 76;
-                data[3] = usage / 100;
-                data[4] = usage % 100 / 10;
-                data[5] = usage % 10;
+                data[3] = status.usage / 100;
+                data[4] = status.usage % 100 / 10;
+                data[5] = status.usage % 10;
             }
             _ => (),
         }
         // Status bar, will show at least 1 box, also fixed point rounding
-        data[2] = ((usage + 5) / 10).clamp(1, 10) as u8;
+        data[2] = (status.usage.saturating_add(5) / 10).clamp(1, 10);
         // Alarm
-        data[6] = (self.alarm && temp > if self.fahrenheit { 185 } else { 85 }) as u8;
+        data[6] = (self.alarm && status.overheat) as u8;
 
         data
     }
+
+    /// Switches the display mode when the mode button is pressed.
+    fn handle_input(&mut self, report: &[u8]) -> bool {
+        if !report.starts_with(&MODE_BUTTON) {
+            return false;
+        }
+        let mode = self.mode.switch();
+        info!(mode = mode; "Mode button pressed, display mode: {mode}");
+
+        true
+    }
+
+    fn next_mode(&mut self) -> Option<String> {
+        Some(self.mode.advance().to_string())
+    }
+
+    fn set_mode(&mut self, mode: &str) {
+        self.mode = ModeCycle::new(mode);
+    }
+
+    fn describe(&self, packet: &[u8; 64]) -> Vec<PacketField> {
+        describe_ak(packet, Some(6))
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            display_modes: true,
+            alarm: Alarm::Configurable,
+            power: false,
+            polling_rate: POLLING_RATE,
+        }
+    }
 }
@@ -0,0 +1,56 @@
+//! CH510 MESH DIGITAL
+//!
+//! The status packet follows the AK series layout, but the panel stays blank until it receives
+//! its own wake-up packet before the status bar animation.
+
+use super::{ak_series, Capabilities, DeviceDriver, PacketField, Status};
+
+pub struct Display {
+    base: ak_series::Display,
+}
+
+impl Display {
+    pub fn new(mode: &str, alarm: bool) -> Self {
+        Display {
+            base: ak_series::Display::new(mode, alarm),
+        }
+    }
+}
+
+impl DeviceDriver for Display {
+    fn init(&self) -> Vec<[u8; 64]> {
+        let mut wake_data: [u8; 64] = [0; 64];
+        wake_data[0] = 16;
+        wake_data[1] = 180;
+        wake_data[2] = 1;
+
+        let mut init_data = self.base.init();
+        init_data.insert(0, wake_data);
+
+        init_data
+    }
+
+    fn build_status_packet(&mut self, status: &Status) -> [u8; 64] {
+        self.base.build_status_packet(status)
+    }
+
+    fn handle_input(&mut self, report: &[u8]) -> bool {
+        self.base.handle_input(report)
+    }
+
+    fn next_mode(&mut self) -> Option<String> {
+        self.base.next_mode()
+    }
+
+    fn set_mode(&mut self, mode: &str) {
+        self.base.set_mode(mode);
+    }
+
+    fn describe(&self, packet: &[u8; 64]) -> Vec<PacketField> {
+        self.base.describe(packet)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.base.capabilities()
+    }
+}
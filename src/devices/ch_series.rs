@@ -0,0 +1,86 @@
+//! CH560 DIGITAL
+//!
+//! The case panel has two AK style readouts, each with its own status bar. The upper one
+//! shows the temperature and the lower one shows the utilization.
+
+use super::{digits, mode_name, on_off, Alarm, Capabilities, DeviceDriver, PacketField, Status};
+use std::time::Duration;
+
+const POLLING_RATE: Duration = Duration::from_millis(750);
+
+pub struct Display {
+    alarm: bool,
+}
+
+impl Display {
+    pub fn new(alarm: bool) -> Self {
+        Display { alarm }
+    }
+
+    /// Writes one readout (mode, status bar, 3 digits) starting at `offset`.
+    fn write_readout(data: &mut [u8; 64], offset: usize, flag: u8, value: u8, usage: u8) {
+        data[offset] = flag;
+        // Status bar, will show at least 1 box, also fixed point rounding
+        data[offset + 1] = (usage.saturating_add(5) / 10).clamp(1, 10);
+        data[offset + 2] = value / 100;
+        data[offset + 3] = value % 100 / 10;
+        data[offset + 4] = value % 10;
+    }
+}
+
+impl DeviceDriver for Display {
+    fn init(&self) -> Vec<[u8; 64]> {
+        let mut init_data: [u8; 64] = [0; 64];
+        init_data[0] = 16;
+        init_data[1] = 170;
+        init_data[6] = 170;
+
+        vec![init_data]
+    }
+
+    fn build_status_packet(&mut self, status: &Status) -> [u8; 64] {
+        // Data packet
+        let mut data: [u8; 64] = [0; 64];
+        data[0] = 16;
+
+        // Upper & lower display
+        let temp_flag = if status.fahrenheit { 35 } else { 19 };
+        Self::write_readout(&mut data, 1, temp_flag, status.temp, status.usage);
+        Self::write_readout(&mut data, 6, 76, status.usage, status.usage);
+
+        // Alarm
+        data[11] = (self.alarm && status.overheat) as u8;
+
+        data
+    }
+
+    fn describe(&self, packet: &[u8; 64]) -> Vec<PacketField> {
+        let mut fields = vec![PacketField::new(0..1, "report id", "")];
+        for (offset, name) in [(1, "upper mode"), (6, "lower mode")] {
+            fields.push(PacketField::new(offset..offset + 1, name, mode_name(packet[offset])));
+            if [19, 35, 76].contains(&packet[offset]) {
+                let bar = format!("{}/10", packet[offset + 1]);
+                fields.push(PacketField::new(offset + 1..offset + 2, "status bar", bar));
+                fields.push(PacketField::new(
+                    offset + 2..offset + 5,
+                    "digits",
+                    digits(&packet[offset + 2..offset + 5]),
+                ));
+            }
+        }
+        if packet[1] != 170 {
+            fields.push(PacketField::new(11..12, "alarm", on_off(packet[11])));
+        }
+
+        fields
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            display_modes: false,
+            alarm: Alarm::Configurable,
+            power: false,
+            polling_rate: POLLING_RATE,
+        }
+    }
+}
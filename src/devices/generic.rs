@@ -0,0 +1,428 @@
+//! Devices described by a definition file instead of a built-in protocol.
+//!
+//! Every `*.toml` file in the definitions folder describes one device:
+//! ```toml
+//! name = "AK620 DIGITAL"
+//! product_id = 0x0002
+//! report_length = 64     # optional, 64 by default
+//! polling_rate = 750     # optional, 1000 by default, from 100 to 60000
+//! init = [[16, 170]]     # packets sent after connecting
+//! header = [16]          # bytes every status packet starts with
+//! interface = 1          # optional, HID interface number on composite devices
+//! usage_page = 0xff00    # optional, or select the interface by usage page & usage
+//! usage = 0x0001
+//!
+//! [fields]               # formats: u8, u16, f32, digits, bar
+//! temp = { offset = 3, format = "digits" }
+//! usage = { offset = 2, format = "bar" }
+//! unit = { offset = 1, values = [19, 35] }   # values for ˚C and ˚F
+//! alarm = { offset = 6 }
+//!
+//! [checksum]             # optional, sum of data[start..end] written to data[end]
+//! start = 1
+//! end = 16
+//! terminator = 22        # optional, written after the checksum
+//! ```
+
+use super::{digits, on_off, Alarm, Capabilities, DeviceDriver, Interface, PacketField, Status, MAX_POLL, MIN_POLL};
+use crate::{
+    log::warning,
+    toml::{self, get_int, get_str, Table, Value},
+};
+use std::{
+    fs::{read_dir, read_to_string},
+    time::Duration,
+};
+
+/// Folder of the device definition files
+pub const DEFINITIONS_DIR: &str = "/usr/share/deepcool-digital/devices";
+
+#[derive(Clone, Copy)]
+enum Format {
+    /// A single byte
+    U8,
+    /// 2 bytes, big-endian
+    U16,
+    /// 4 bytes, big-endian float
+    F32,
+    /// 3 bytes, one for each decimal digit
+    Digits,
+    /// A single byte, the number of lit status bar boxes (1-10)
+    Bar,
+}
+
+#[derive(Clone, Copy)]
+struct Field {
+    offset: usize,
+    format: Format,
+}
+
+#[derive(Clone)]
+struct Checksum {
+    start: usize,
+    end: usize,
+    terminator: Option<u8>,
+}
+
+#[derive(Clone)]
+pub struct Definition {
+    pub name: String,
+    pub product_id: u16,
+    report_length: usize,
+    polling_rate: Duration,
+    interface: Interface,
+    init: Vec<Vec<u8>>,
+    header: Vec<u8>,
+    temp: Option<Field>,
+    usage: Option<Field>,
+    power: Option<Field>,
+    /// Offset and the values for ˚C and ˚F
+    unit: Option<(usize, [u8; 2])>,
+    alarm: Option<usize>,
+    checksum: Option<Checksum>,
+}
+
+impl Definition {
+    /// Parses and validates a definition file.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let root = toml::parse(text)?;
+
+        let name = get_str(&root, "name")?.ok_or("missing \"name\"")?.to_string();
+        let product_id = get_int(&root, "product_id", u16::MAX as i64)?.ok_or("missing \"product_id\"")? as u16;
+        let report_length = get_int(&root, "report_length", 64)?.unwrap_or(64) as usize;
+        let polling_rate = Duration::from_millis(get_int(&root, "polling_rate", 60_000)?.unwrap_or(1000) as u64);
+
+        let interface = match (
+            get_int(&root, "interface", 255)?,
+            get_int(&root, "usage_page", u16::MAX as i64)?,
+            get_int(&root, "usage", u16::MAX as i64)?,
+        ) {
+            (Some(number), None, None) => Interface::Number(number as i32),
+            (None, Some(page), Some(usage)) => Interface::Usage {
+                page: page as u16,
+                usage: usage as u16,
+            },
+            (None, None, None) => Interface::Any,
+            _ => return Err("use either \"interface\" or both \"usage_page\" and \"usage\"".to_string()),
+        };
+
+        let init = match root.get("init") {
+            Some(Value::Array(packets)) => packets.iter().map(bytes).collect::<Result<_, _>>()?,
+            Some(_) => return Err("\"init\" must be an array of packets".to_string()),
+            None => Vec::new(),
+        };
+        let header = root.get("header").map(bytes).transpose()?.unwrap_or_default();
+
+        let empty = Table::new();
+        let fields = match root.get("fields") {
+            Some(value) => value.as_table().ok_or("\"fields\" must be a table")?,
+            None => &empty,
+        };
+        let field = |key: &str| -> Result<Option<&Table>, String> {
+            match fields.get(key) {
+                Some(value) => value
+                    .as_table()
+                    .map(Some)
+                    .ok_or(format!("\"fields.{key}\" must be a table")),
+                None => Ok(None),
+            }
+        };
+        let offset = |table: &Table, key: &str| -> Result<usize, String> {
+            get_int(table, "offset", 63)?
+                .map(|offset| offset as usize)
+                .ok_or(format!("missing \"fields.{key}.offset\""))
+        };
+        let data_field = |key: &str| -> Result<Option<Field>, String> {
+            let Some(table) = field(key)? else { return Ok(None) };
+            let format = match get_str(table, "format")?.unwrap_or("u8") {
+                "u8" => Format::U8,
+                "u16" => Format::U16,
+                "f32" => Format::F32,
+                "digits" => Format::Digits,
+                "bar" => Format::Bar,
+                other => return Err(format!("unknown format \"{other}\" for \"fields.{key}\"")),
+            };
+            Ok(Some(Field {
+                offset: offset(table, key)?,
+                format,
+            }))
+        };
+
+        let unit = match field("unit")? {
+            Some(table) => {
+                let values = match table.get("values") {
+                    Some(value) => bytes(value)?,
+                    None => vec![0, 1],
+                };
+                let values: [u8; 2] = values.try_into().map_err(|_| "\"fields.unit.values\" needs 2 values")?;
+                Some((offset(table, "unit")?, values))
+            }
+            None => None,
+        };
+        let alarm = field("alarm")?.map(|table| offset(table, "alarm")).transpose()?;
+
+        let checksum = match root.get("checksum") {
+            Some(value) => {
+                let table = value.as_table().ok_or("\"checksum\" must be a table")?;
+                Some(Checksum {
+                    start: get_int(table, "start", 63)?.unwrap_or(1) as usize,
+                    end: get_int(table, "end", 63)?.ok_or("missing \"checksum.end\"")? as usize,
+                    terminator: get_int(table, "terminator", 255)?.map(|value| value as u8),
+                })
+            }
+            None => None,
+        };
+
+        let definition = Definition {
+            name,
+            product_id,
+            report_length,
+            polling_rate,
+            interface,
+            init,
+            header,
+            temp: data_field("temp")?,
+            usage: data_field("usage")?,
+            power: data_field("power")?,
+            unit,
+            alarm,
+            checksum,
+        };
+        definition.validate()?;
+
+        Ok(definition)
+    }
+
+    /// Makes sure every byte written by the definition fits in the report.
+    fn validate(&self) -> Result<(), String> {
+        let fits = |end: usize, what: &str| {
+            if end > self.report_length {
+                Err(format!("{what} does not fit in the {} byte report", self.report_length))
+            } else {
+                Ok(())
+            }
+        };
+
+        if self.report_length == 0 {
+            return Err("\"report_length\" must be at least 1".to_string());
+        }
+        if !(MIN_POLL..=MAX_POLL).contains(&self.polling_rate) {
+            return Err(format!(
+                "\"polling_rate\" must be between {} and {}",
+                MIN_POLL.as_millis(),
+                MAX_POLL.as_millis()
+            ));
+        }
+        for packet in &self.init {
+            fits(packet.len(), "an init packet")?;
+        }
+        fits(self.header.len(), "the header")?;
+        for (key, field) in [("temp", self.temp), ("usage", self.usage), ("power", self.power)] {
+            if let Some(field) = field {
+                let width = match field.format {
+                    Format::U8 | Format::Bar => 1,
+                    Format::U16 => 2,
+                    Format::Digits => 3,
+                    Format::F32 => 4,
+                };
+                fits(field.offset + width, &format!("field \"{key}\""))?;
+            }
+        }
+        if let Some((offset, _)) = self.unit {
+            fits(offset + 1, "field \"unit\"")?;
+        }
+        if let Some(offset) = self.alarm {
+            fits(offset + 1, "field \"alarm\"")?;
+        }
+        if let Some(checksum) = &self.checksum {
+            if checksum.start > checksum.end {
+                return Err("\"checksum.start\" is after \"checksum.end\"".to_string());
+            }
+            let end = checksum.end + 1 + checksum.terminator.is_some() as usize;
+            fits(end, "the checksum")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Reads every definition file in the given folder.
+///
+/// Files that cannot be parsed are skipped with a warning, a missing folder is not an error.
+pub fn load_definitions(dir: &str) -> Vec<Definition> {
+    let Ok(entries) = read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+    paths.sort();
+
+    let mut definitions = Vec::new();
+    for path in paths {
+        match read_to_string(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|text| Definition::parse(&text))
+        {
+            Ok(definition) => definitions.push(definition),
+            Err(err) => warning!(definition = path.display(); "Skipping device definition {}: {}", path.display(), err),
+        }
+    }
+
+    definitions
+}
+
+/// Converts an array of integers to bytes.
+fn bytes(value: &Value) -> Result<Vec<u8>, String> {
+    let array = value.as_array().ok_or("expected an array of bytes")?;
+    array
+        .iter()
+        .map(|byte| match byte.as_integer() {
+            Some(byte @ 0..=255) => Ok(byte as u8),
+            _ => Err(format!("invalid byte: {byte:?}")),
+        })
+        .collect()
+}
+
+pub struct Display {
+    definition: Definition,
+    alarm: bool,
+}
+
+impl Display {
+    pub fn new(definition: Definition, alarm: bool) -> Self {
+        Display { definition, alarm }
+    }
+
+    /// Returns an empty report starting with the given bytes.
+    fn packet(bytes: &[u8]) -> [u8; 64] {
+        let mut data: [u8; 64] = [0; 64];
+        data[..bytes.len()].copy_from_slice(bytes);
+
+        data
+    }
+
+    fn write_field(data: &mut [u8; 64], field: Field, value: u16) {
+        let offset = field.offset;
+        match field.format {
+            Format::U8 => data[offset] = value.min(255) as u8,
+            Format::U16 => data[offset..offset + 2].copy_from_slice(&value.to_be_bytes()),
+            Format::F32 => data[offset..offset + 4].copy_from_slice(&(value as f32).to_be_bytes()),
+            Format::Digits => {
+                let value = value.min(999);
+                data[offset] = (value / 100) as u8;
+                data[offset + 1] = (value % 100 / 10) as u8;
+                data[offset + 2] = (value % 10) as u8;
+            }
+            // Status bar, will show at least 1 box, also fixed point rounding
+            Format::Bar => data[offset] = (value.saturating_add(5) / 10).clamp(1, 10) as u8,
+        }
+    }
+
+    /// Names a field and decodes its value.
+    fn read_field(data: &[u8; 64], field: Field, name: &'static str) -> PacketField {
+        let offset = field.offset;
+        let (width, value) = match field.format {
+            Format::U8 => (1, data[offset].to_string()),
+            Format::U16 => (2, u16::from_be_bytes([data[offset], data[offset + 1]]).to_string()),
+            Format::F32 => {
+                let bytes = [data[offset], data[offset + 1], data[offset + 2], data[offset + 3]];
+                (4, f32::from_be_bytes(bytes).to_string())
+            }
+            Format::Digits => (3, digits(&data[offset..offset + 3])),
+            Format::Bar => (1, format!("{}/10", data[offset])),
+        };
+
+        PacketField::new(offset..offset + width, name, value)
+    }
+}
+
+impl DeviceDriver for Display {
+    fn init(&self) -> Vec<[u8; 64]> {
+        self.definition.init.iter().map(|bytes| Self::packet(bytes)).collect()
+    }
+
+    fn build_status_packet(&mut self, status: &Status) -> [u8; 64] {
+        let definition = &self.definition;
+        let mut data = Self::packet(&definition.header);
+
+        if let Some(field) = definition.temp {
+            Self::write_field(&mut data, field, status.temp as u16);
+        }
+        if let Some(field) = definition.usage {
+            Self::write_field(&mut data, field, status.usage as u16);
+        }
+        if let Some(field) = definition.power {
+            Self::write_field(&mut data, field, status.power);
+        }
+        if let Some((offset, values)) = definition.unit {
+            data[offset] = values[status.fahrenheit as usize];
+        }
+        if let Some(offset) = definition.alarm {
+            data[offset] = (self.alarm && status.overheat) as u8;
+        }
+        if let Some(checksum) = &definition.checksum {
+            let sum: u16 = data[checksum.start..checksum.end].iter().map(|&x| x as u16).sum();
+            data[checksum.end] = (sum % 256) as u8;
+            if let Some(terminator) = checksum.terminator {
+                data[checksum.end + 1] = terminator;
+            }
+        }
+
+        data
+    }
+
+    fn describe(&self, packet: &[u8; 64]) -> Vec<PacketField> {
+        let definition = &self.definition;
+        if let Some(init) = definition.init.iter().find(|init| Self::packet(init) == *packet) {
+            return vec![PacketField::new(0..init.len(), "init", "")];
+        }
+
+        let mut fields = vec![PacketField::new(0..definition.header.len(), "header", "")];
+        for (name, field) in [
+            ("temp", definition.temp),
+            ("usage", definition.usage),
+            ("power", definition.power),
+        ] {
+            fields.extend(field.map(|field| Self::read_field(packet, field, name)));
+        }
+        if let Some((offset, values)) = definition.unit {
+            let unit = if packet[offset] == values[1] { "˚F" } else { "˚C" };
+            fields.push(PacketField::new(offset..offset + 1, "unit", unit));
+        }
+        if let Some(offset) = definition.alarm {
+            fields.push(PacketField::new(offset..offset + 1, "alarm", on_off(packet[offset])));
+        }
+        if let Some(checksum) = &definition.checksum {
+            fields.push(PacketField::new(checksum.end..checksum.end + 1, "checksum", ""));
+            if checksum.terminator.is_some() {
+                fields.push(PacketField::new(checksum.end + 1..checksum.end + 2, "terminator", ""));
+            }
+        }
+
+        fields
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            display_modes: false,
+            alarm: if self.definition.alarm.is_some() {
+                Alarm::Configurable
+            } else {
+                Alarm::Unsupported
+            },
+            power: self.definition.power.is_some(),
+            polling_rate: self.definition.polling_rate,
+        }
+    }
+
+    fn report_length(&self) -> usize {
+        self.definition.report_length
+    }
+
+    fn interface(&self) -> Interface {
+        self.definition.interface
+    }
+}
@@ -1,84 +1,117 @@
-use crate::monitor::cpu;
-use hidapi::HidApi;
-use std::{thread::sleep, time::Duration};
+//! LD240 & LD360
+//!
+//! Although these coolers have a full LCD, the firmware draws the screen itself: the host only
+//! sends the power consumption, temperature and utilization in a framed report, there is no
+//! framebuffer upload.
 
-const VENDOR: u16 = 0x3633;
-const POLLING_RATE: u64 = 1000;
+use super::{
+    check_frame, describe_frame, parse_version_frame, terminate_frame, version_query_frame, Alarm, Capabilities,
+    DeviceDriver, PacketField, Status,
+};
+use std::time::Duration;
 
-pub struct Display {
-    product_id: u16,
-    fahrenheit: bool,
-}
-
-impl Display {
-    pub fn new(product_id: u16, fahrenheit: bool) -> Self {
-        Display { product_id, fahrenheit }
-    }
+const POLLING_RATE: Duration = Duration::from_millis(1000);
 
-    pub fn run(&self, api: &HidApi, cpu_temp_sensor: &str) {
-        // Connect to device
-        let device = api.open(VENDOR, self.product_id).expect("Failed to open HID device");
+pub struct Display;
 
-        // Data packet
+impl Display {
+    /// Returns the common header of every packet.
+    fn header() -> [u8; 64] {
         let mut data: [u8; 64] = [0; 64];
         data[0] = 16;
         data[1] = 104;
         data[2] = 1;
         data[3] = 1;
 
-        // Init sequence
-        {
-            let mut init_data = data.clone();
-            init_data[4] = 2;
-            init_data[5] = 3;
-            init_data[6] = 1;
-            init_data[7] = 112;
-            init_data[8] = 22;
-            device.write(&init_data).expect("Failed to write data");
-            init_data[5] = 2;
-            init_data[7] = 111;
-            device.write(&init_data).expect("Failed to write data");
-        }
+        data
+    }
+}
+
+impl DeviceDriver for Display {
+    fn init(&self) -> Vec<[u8; 64]> {
+        let mut first = Self::header();
+        first[4] = 2;
+        first[5] = 3;
+        first[6] = 1;
+        terminate_frame(&mut first, 7);
+        let mut second = first;
+        second[5] = 2;
+        terminate_frame(&mut second, 7);
+
+        vec![first, second]
+    }
+
+    fn build_status_packet(&mut self, status: &Status) -> [u8; 64] {
+        // Initialize the packet
+        let mut status_data = Self::header();
+        status_data[4] = 11;
+        status_data[5] = 1;
+        status_data[6] = 2;
+        status_data[7] = 5;
+
+        // ----- Write data to the package -----
+        // Power consumption
+        let power = status.power.to_be_bytes();
+        status_data[8] = power[0];
+        status_data[9] = power[1];
+
+        // Temperature
+        let temp = (status.temp as f32).to_be_bytes();
+        status_data[10] = if status.fahrenheit { 1 } else { 0 };
+        status_data[11] = temp[0];
+        status_data[12] = temp[1];
+        status_data[13] = temp[2];
+        status_data[14] = temp[3];
+
+        // Utilization
+        status_data[15] = status.usage;
 
-        // Display loop
-        data[4] = 11;
-        data[5] = 1;
-        data[6] = 2;
-        data[7] = 5;
-        loop {
-            // Initialize the packet
-            let mut status_data = data.clone();
-
-            // Read CPU utilization & energy consumption
-            let cpu_instant = cpu::read_instant();
-            let cpu_energy = cpu::read_energy();
-
-            // Wait
-            sleep(Duration::from_millis(POLLING_RATE));
-
-            // ----- Write data to the package -----
-            // Power consumption
-            let power = (cpu::get_power(cpu_energy, POLLING_RATE)).to_be_bytes();
-            status_data[8] = power[0];
-            status_data[9] = power[1];
-
-            // Temperature
-            let temp = (cpu::get_temp(cpu_temp_sensor, self.fahrenheit) as f32).to_be_bytes();
-            status_data[10] = if self.fahrenheit { 1 } else { 0 };
-            status_data[11] = temp[0];
-            status_data[12] = temp[1];
-            status_data[13] = temp[2];
-            status_data[14] = temp[3];
-
-            // Utilization
-            status_data[15] = cpu::get_usage(cpu_instant);
-
-            // Checksum & termination byte
-            let checksum: u16 = status_data[1..=15].iter().map(|&x| x as u16).sum();
-            status_data[16] = (checksum % 256) as u8;
-            status_data[17] = 22;
-
-            device.write(&status_data).expect("Failed to write data");
+        // Checksum & termination byte
+        terminate_frame(&mut status_data, 16);
+
+        status_data
+    }
+
+    fn brightness_packet(&self, level: u8) -> Option<[u8; 64]> {
+        let mut data = Self::header();
+        data[4] = 2;
+        data[5] = 4;
+        data[6] = level.min(100);
+        terminate_frame(&mut data, 7);
+
+        Some(data)
+    }
+
+    fn version_query(&self) -> Option<[u8; 64]> {
+        Some(version_query_frame(Self::header()))
+    }
+
+    fn parse_version(&self, reply: &[u8]) -> Option<String> {
+        parse_version_frame(reply)
+    }
+
+    fn describe(&self, packet: &[u8; 64]) -> Vec<PacketField> {
+        describe_frame(packet, || {
+            let temp = f32::from_be_bytes([packet[11], packet[12], packet[13], packet[14]]);
+            vec![
+                PacketField::new(8..10, "power", format!("{} W", u16::from_be_bytes([packet[8], packet[9]]))),
+                PacketField::new(10..11, "unit", if packet[10] == 1 { "˚F" } else { "˚C" }),
+                PacketField::new(11..15, "temp", temp),
+                PacketField::new(15..16, "usage", format!("{}%", packet[15])),
+            ]
+        })
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            display_modes: false,
+            alarm: Alarm::BuiltIn,
+            power: true,
+            polling_rate: POLLING_RATE,
         }
     }
+
+    fn check_response(&self, response: &[u8]) -> Result<(), String> {
+        check_frame(response)
+    }
 }
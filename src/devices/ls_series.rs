@@ -0,0 +1,128 @@
+//! LS720 SE DIGITAL
+//!
+//! The pump cap display uses the framed report of the LD series, but shows a single value
+//! selected by the display mode.
+
+use super::{
+    check_frame, describe_frame, on_off, parse_version_frame, terminate_frame, version_query_frame, Alarm,
+    Capabilities, DeviceDriver, ModeCycle, PacketField, Status,
+};
+use std::time::Duration;
+
+const POLLING_RATE: Duration = Duration::from_millis(1000);
+
+pub struct Display {
+    mode: ModeCycle,
+    alarm: bool,
+}
+
+impl Display {
+    pub fn new(mode: &str, alarm: bool) -> Self {
+        Display {
+            mode: ModeCycle::new(mode),
+            alarm,
+        }
+    }
+
+    /// Returns the common header of every packet.
+    fn header() -> [u8; 64] {
+        let mut data: [u8; 64] = [0; 64];
+        data[0] = 16;
+        data[1] = 104;
+        data[2] = 1;
+        data[3] = 4;
+
+        data
+    }
+}
+
+impl DeviceDriver for Display {
+    fn init(&self) -> Vec<[u8; 64]> {
+        let mut init_data = Self::header();
+        init_data[4] = 2;
+        init_data[5] = 3;
+        init_data[6] = 1;
+        terminate_frame(&mut init_data, 7);
+
+        vec![init_data]
+    }
+
+    fn build_status_packet(&mut self, status: &Status) -> [u8; 64] {
+        let mut status_data = Self::header();
+        status_data[4] = 5;
+        status_data[5] = 1;
+
+        // Display mode & value
+        let (flag, value) = match self.mode.current() {
+            "usage" => (3, status.usage),
+            _ => (if status.fahrenheit { 2 } else { 1 }, status.temp),
+        };
+        status_data[6] = flag;
+        let value = (value as u16).to_be_bytes();
+        status_data[7] = value[0];
+        status_data[8] = value[1];
+
+        // Alarm
+        status_data[9] = (self.alarm && status.overheat) as u8;
+
+        // Checksum & termination byte
+        terminate_frame(&mut status_data, 10);
+
+        status_data
+    }
+
+    fn brightness_packet(&self, level: u8) -> Option<[u8; 64]> {
+        let mut data = Self::header();
+        data[4] = 2;
+        data[5] = 4;
+        data[6] = level.min(100);
+        terminate_frame(&mut data, 7);
+
+        Some(data)
+    }
+
+    fn version_query(&self) -> Option<[u8; 64]> {
+        Some(version_query_frame(Self::header()))
+    }
+
+    fn parse_version(&self, reply: &[u8]) -> Option<String> {
+        parse_version_frame(reply)
+    }
+
+    fn next_mode(&mut self) -> Option<String> {
+        Some(self.mode.advance().to_string())
+    }
+
+    fn set_mode(&mut self, mode: &str) {
+        self.mode = ModeCycle::new(mode);
+    }
+
+    fn describe(&self, packet: &[u8; 64]) -> Vec<PacketField> {
+        describe_frame(packet, || {
+            let mode = match packet[6] {
+                1 => "temp ˚C".to_string(),
+                2 => "temp ˚F".to_string(),
+                3 => "usage".to_string(),
+                other => format!("unknown ({other})"),
+            };
+            vec![
+                PacketField::new(6..7, "mode", mode),
+                PacketField::new(7..9, "value", u16::from_be_bytes([packet[7], packet[8]])),
+                PacketField::new(9..10, "alarm", on_off(packet[9])),
+            ]
+        })
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            display_modes: true,
+            alarm: Alarm::Configurable,
+            power: false,
+            polling_rate: POLLING_RATE,
+        }
+    }
+
+    fn check_response(&self, response: &[u8]) -> Result<(), String> {
+        check_frame(response)
+    }
+}
@@ -0,0 +1,54 @@
+//! In-memory device for the tests, it records the packets written and plays back queued replies.
+
+use super::HidBackend;
+use hidapi::HidResult;
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+/// Device without hardware, its clones share the recorded packets and the replies.
+#[derive(Clone, Default)]
+pub struct MockDevice {
+    /// Packets written so far
+    written: Arc<Mutex<Vec<Vec<u8>>>>,
+    /// Replies read after the next writes, in order
+    replies: Arc<Mutex<VecDeque<Vec<u8>>>>,
+}
+
+impl MockDevice {
+    pub fn new() -> Self {
+        MockDevice::default()
+    }
+
+    /// Queues the reply read after the next write that has none queued before it.
+    pub fn reply(&self, report: &[u8]) {
+        self.replies.lock().unwrap().push_back(report.to_vec());
+    }
+
+    /// Returns the packets written so far.
+    pub fn frames(&self) -> Vec<Vec<u8>> {
+        self.written.lock().unwrap().clone()
+    }
+}
+
+impl HidBackend for MockDevice {
+    fn write(&self, data: &[u8]) -> HidResult<usize> {
+        self.written.lock().unwrap().push(data.to_vec());
+        Ok(data.len())
+    }
+
+    fn read_timeout(&self, buffer: &mut [u8], _timeout: i32) -> HidResult<usize> {
+        let Some(reply) = self.replies.lock().unwrap().pop_front() else {
+            return Ok(0);
+        };
+        let length = reply.len().min(buffer.len());
+        buffer[..length].copy_from_slice(&reply[..length]);
+
+        Ok(length)
+    }
+
+    fn name(&self) -> String {
+        "Mock".to_string()
+    }
+}
@@ -1,2 +1,1314 @@
+pub mod ag_series;
+pub mod ak400;
+pub mod ak500;
+pub mod ak620_pro;
 pub mod ak_series;
+pub mod ch510;
+pub mod ch_series;
+pub mod generic;
 pub mod ld_series;
+pub mod ls_series;
+#[cfg(test)]
+pub mod mock;
+pub mod morpheus;
+#[cfg(test)]
+mod tests;
+
+use crate::{
+    capture::{Capture, Direction, Frame},
+    error::Error,
+    log::{self, debug, info, trace, warning, Level},
+    monitor::{cpu, Metrics, Reading, Unit},
+    timer::Timer,
+};
+use cpu_monitor::CpuInstant;
+use hidapi::{DeviceInfo, HidApi, HidDevice, HidError, HidResult};
+use std::{
+    collections::VecDeque,
+    ffi::CStr,
+    fs::canonicalize,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, Ordering},
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, sleep},
+    time::{Duration, Instant},
+};
+
+/// USB vendor ID of DeepCool
+pub const VENDOR: u16 = 0x3633;
+
+/// Shortest update interval, the displays do not keep up with faster updates
+pub const MIN_POLL: Duration = Duration::from_millis(100);
+/// Longest update interval
+pub const MAX_POLL: Duration = Duration::from_secs(60);
+
+/// Suspend time within one update interval that triggers a re-initialization
+const RESUME_THRESHOLD: Duration = Duration::from_millis(500);
+
+/// Delay before the first retry of a failed write, doubled after every further failure
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+/// Longest delay between two retries
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Longest interval of the adaptive polling, it doubles at every update while the CPU is idle and the
+/// readings are stable, and goes back to the one of the device as soon as they change
+const MAX_ADAPTIVE_POLL: Duration = Duration::from_secs(4);
+/// Number of times the interval can double, enough to reach the longest one from 125ms
+const MAX_SLOWDOWN: u32 = 5;
+/// Utilization below which the CPU is idle, in percent
+const IDLE_USAGE: u8 = 10;
+/// Largest change of the utilization and of the temperature between two stable updates
+const STABLE_USAGE: u8 = 5;
+const STABLE_TEMP: u8 = 1;
+
+/// Time each mode is shown when cycling through several modes
+const DWELL: Duration = Duration::from_secs(6);
+
+/// Time each step of the test pattern is shown
+const TEST_STEP: Duration = Duration::from_millis(400);
+
+/// Time to wait for the reply of the device after a write, in milliseconds
+const RESPONSE_TIMEOUT: i32 = 50;
+
+/// Settings of the display loop shared by every device.
+#[derive(Clone)]
+pub struct RunOptions {
+    /// Path of the CPU temperature sensor
+    pub cpu_temp_sensor: String,
+    /// Show the temperature in Fahrenheit, can be toggled while running
+    pub fahrenheit: &'static AtomicBool,
+    /// Incremented to switch the display to the next mode
+    pub next_mode: &'static AtomicU32,
+    /// Display settings of the device, they change when the settings file is reloaded
+    pub settings: Arc<DeviceSettings>,
+    /// Time of the last update of the device
+    pub heartbeat: Arc<Heartbeat>,
+    /// Number of consecutive failed writes after which the device is given up
+    pub max_retries: u32,
+    /// Time a single write may take before the device is considered stuck
+    pub write_timeout: Duration,
+    /// Temperature in ˚C above which the alarm goes off
+    pub alarm_temp: u8,
+    /// Temperature in ˚C at or below which the alarm is cleared again
+    pub alarm_clear: u8,
+    /// Blank the display and stop once set
+    pub stop: &'static AtomicBool,
+    /// Brightness of the display in percent, can be changed while running
+    pub brightness: &'static AtomicU8,
+    /// Keep the display blank while set, e.g. when the screen is locked
+    pub blank: &'static AtomicBool,
+    /// Last status sent to a device
+    pub status: &'static Mutex<Option<Status>>,
+    /// Models of the devices being driven
+    pub devices: &'static Mutex<Vec<String>>,
+    /// Connection problems since the start
+    pub counters: &'static Counters,
+    /// Capture the packets and the replies are recorded to
+    pub capture: Option<Arc<Capture>>,
+    /// Slow the updates down while the CPU is idle and the readings are stable
+    pub adaptive_poll: bool,
+    /// Time the utilization and the power are averaged over, the update interval if not set
+    pub sample_window: Option<Duration>,
+    /// Metrics read besides the CPU, and the ones shown instead of the CPU readings
+    pub metrics: Arc<Metrics>,
+    /// Readings of every metric at the last update
+    pub readings: &'static Mutex<Vec<Reading>>,
+}
+
+/// Display settings of a single device that can change while it runs.
+pub struct DeviceSettings {
+    /// Display mode, the device switches to it when it changes
+    mode: Mutex<String>,
+    /// Time between two updates, the default of the device if not set
+    poll: Mutex<Option<Duration>>,
+    /// Temperature unit of the device, `None` to follow the shared one
+    fahrenheit: Mutex<Option<bool>>,
+}
+
+impl DeviceSettings {
+    pub fn new(mode: &str, poll: Option<Duration>, fahrenheit: Option<bool>) -> Self {
+        DeviceSettings {
+            mode: Mutex::new(mode.to_string()),
+            poll: Mutex::new(poll),
+            fahrenheit: Mutex::new(fahrenheit),
+        }
+    }
+
+    /// Replaces the settings, the device picks them up on its next update.
+    pub fn set(&self, mode: &str, poll: Option<Duration>, fahrenheit: Option<bool>) {
+        *self.mode.lock().unwrap() = mode.to_string();
+        *self.poll.lock().unwrap() = poll;
+        *self.fahrenheit.lock().unwrap() = fahrenheit;
+    }
+}
+
+/// Time of the last update of a device, to find out whether its display loop is stuck.
+pub struct Heartbeat {
+    /// Time of the last update and the interval of the updates at that time
+    last: Mutex<(Instant, Duration)>,
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Heartbeat {
+    pub fn new() -> Self {
+        Heartbeat {
+            last: Mutex::new((Instant::now(), Duration::ZERO)),
+        }
+    }
+
+    /// Records an update, the next one is expected within `interval`.
+    fn beat(&self, interval: Duration) {
+        *self.last.lock().unwrap() = (Instant::now(), interval);
+    }
+
+    /// Returns whether the next update is late by more than `grace`.
+    pub fn stalled(&self, grace: Duration) -> bool {
+        let (last, interval) = *self.last.lock().unwrap();
+        last.elapsed() > interval + grace
+    }
+}
+
+/// Connection problems counted since the start.
+pub struct Counters {
+    /// Writes that failed or timed out
+    pub write_errors: AtomicU64,
+    /// Connections lost to a device, it is reconnected once it is found again
+    pub reconnects: AtomicU64,
+}
+
+/// CPU readings collected over one update interval.
+#[derive(Clone, Copy)]
+pub struct Status {
+    /// Temperature in the unit selected for the display
+    pub temp: u8,
+    /// Temperature is in ˚F
+    pub fahrenheit: bool,
+    /// Utilization as a `0-100` number
+    pub usage: u8,
+    /// Power consumption in watts, `0` if the device does not show it
+    pub power: u16,
+    /// Temperature is over the alarm limit
+    pub overheat: bool,
+}
+
+/// How the device handles the temperature alarm.
+pub enum Alarm {
+    /// The alarm can be turned on and off
+    Configurable,
+    /// The alarm is always on and handled by the device itself
+    BuiltIn,
+    /// The device has no alarm
+    Unsupported,
+}
+
+/// Features of a device, used to validate the options and to print the device info.
+pub struct Capabilities {
+    /// The display can switch between the "temp, usage, auto" modes
+    pub display_modes: bool,
+    /// Support for the temperature alarm
+    pub alarm: Alarm,
+    /// The display shows the CPU power consumption
+    pub power: bool,
+    /// Time between two status packets
+    pub polling_rate: Duration,
+}
+
+/// The HID interface that receives the data packets on devices exposing several of them.
+#[derive(Clone, Copy, Debug)]
+pub enum Interface {
+    /// Any interface, the one with a vendor-defined usage page is preferred
+    Any,
+    /// The interface with the given number
+    Number(i32),
+    /// The interface with the given usage page and usage
+    Usage { page: u16, usage: u16 },
+}
+
+impl Interface {
+    /// Picks the matching entry from the HID entries of a single device.
+    pub fn select<'a>(&self, entries: &'a [DeviceInfo]) -> Option<&'a DeviceInfo> {
+        match *self {
+            Interface::Any => entries
+                .iter()
+                .find(|entry| entry.usage_page() >= 0xff00)
+                .or(entries.first()),
+            Interface::Number(number) => entries.iter().find(|entry| entry.interface_number() == number),
+            Interface::Usage { page, usage } => entries
+                .iter()
+                .find(|entry| entry.usage_page() == page && entry.usage() == usage),
+        }
+    }
+}
+
+/// Selects the mode of the current packet.
+///
+/// The mode is either a single mode or a comma separated list of modes to cycle through, each followed by the
+/// seconds it is shown for (e.g. `temp:10,usage:3`). "auto" is short for `temp,usage`.
+pub struct ModeCycle {
+    modes: Vec<(String, Duration)>,
+    current: usize,
+    since: Instant,
+}
+
+impl ModeCycle {
+    pub fn new(mode: &str) -> Self {
+        let modes = mode
+            .split(',')
+            .flat_map(|entry| {
+                let (name, seconds) = entry.split_once(':').unwrap_or((entry, ""));
+                let dwell = seconds.parse().map_or(DWELL, Duration::from_secs);
+                let names = if name == "auto" {
+                    vec!["temp", "usage"]
+                } else {
+                    vec![name]
+                };
+                names.into_iter().map(move |name| (name.to_string(), dwell))
+            })
+            .collect();
+
+        ModeCycle {
+            modes,
+            current: 0,
+            since: Instant::now(),
+        }
+    }
+
+    /// Returns the mode to use for the next packet.
+    pub fn current(&mut self) -> &str {
+        if self.since.elapsed() >= self.modes[self.current].1 {
+            self.current = (self.current + 1) % self.modes.len();
+            self.since = Instant::now();
+        }
+
+        &self.modes[self.current].0
+    }
+
+    /// Jumps to the next mode of the cycle, or switches between `temp` and `usage` if there is a single mode.
+    pub fn advance(&mut self) -> &str {
+        if self.modes.len() > 1 {
+            self.current = (self.current + 1) % self.modes.len();
+            self.since = Instant::now();
+        } else {
+            let next = if self.modes[0].0 == "temp" { "usage" } else { "temp" };
+            *self = ModeCycle::new(next);
+        }
+
+        &self.modes[self.current].0
+    }
+
+    /// Switches to the next display mode, in the `temp, usage, auto` order, and returns it.
+    pub fn switch(&mut self) -> &str {
+        let next = match self.modes.as_slice() {
+            [(mode, _)] if mode == "temp" => "usage",
+            [(mode, _)] if mode == "usage" => "auto",
+            _ => "temp",
+        };
+        *self = ModeCycle::new(next);
+
+        next
+    }
+}
+
+/// Returns whether a mode is valid for `ModeCycle`, every entry has to be a known mode with an optional non-zero
+/// number of seconds.
+pub fn valid_mode(spec: &str) -> bool {
+    spec.split(',').all(|entry| {
+        let (mode, seconds) = entry.split_once(':').unwrap_or((entry, "1"));
+        ["temp", "usage", "auto"].contains(&mode) && seconds.parse::<u64>().is_ok_and(|seconds| seconds > 0)
+    })
+}
+
+/// Writes the checksum of `data[1..end]` and the termination byte after it.
+///
+/// Used by the framed reports (LD series and newer models) that start with `16, 104`.
+pub fn terminate_frame(data: &mut [u8; 64], end: usize) {
+    data[end] = frame_checksum(&data[1..end]);
+    data[end + 1] = 22;
+}
+
+/// Validates a framed reply: header, length, checksum and termination byte.
+pub fn check_frame(reply: &[u8]) -> Result<(), String> {
+    if reply.len() < 7 {
+        return Err(format!("reply is only {} bytes long", reply.len()));
+    }
+    if reply[..2] != [16, 104] {
+        return Err(format!("unexpected header {:02x?}", &reply[..2]));
+    }
+    let end = 5 + reply[4] as usize;
+    if end + 1 >= reply.len() {
+        return Err(format!("length {} does not fit the report", reply[4]));
+    }
+    let checksum = frame_checksum(&reply[1..end]);
+    if reply[end] != checksum {
+        return Err(format!("checksum is {:#04x} instead of {checksum:#04x}", reply[end]));
+    }
+    if reply[end + 1] != 22 {
+        return Err(format!("termination byte is {:#04x} instead of 0x16", reply[end + 1]));
+    }
+
+    Ok(())
+}
+
+/// Builds the framed firmware version query from the header of the device.
+pub fn version_query_frame(header: [u8; 64]) -> [u8; 64] {
+    let mut data = header;
+    data[4] = 1;
+    data[5] = 5;
+    terminate_frame(&mut data, 6);
+
+    data
+}
+
+/// Reads the firmware version from the framed reply to `version_query_frame`.
+pub fn parse_version_frame(reply: &[u8]) -> Option<String> {
+    check_frame(reply).ok()?;
+    match reply[4..] {
+        [length, 5, major, minor, ..] if length >= 3 => Some(format!("{major}.{minor}")),
+        _ => None,
+    }
+}
+
+fn frame_checksum(data: &[u8]) -> u8 {
+    (data.iter().map(|&x| x as u16).sum::<u16>() % 256) as u8
+}
+
+/// A field of a packet, named in the annotated hexdump of the debug log.
+pub struct PacketField {
+    /// Bytes of the field
+    pub range: Range<usize>,
+    /// Name of the field
+    pub name: &'static str,
+    /// Decoded value, empty if the bytes say it all
+    pub value: String,
+}
+
+impl PacketField {
+    pub fn new(range: Range<usize>, name: &'static str, value: impl ToString) -> Self {
+        PacketField {
+            range,
+            name,
+            value: value.to_string(),
+        }
+    }
+}
+
+/// Decodes the mode byte of the AK series layout, shared by most models.
+pub fn mode_name(flag: u8) -> String {
+    match flag {
+        0 => "none".to_string(),
+        19 => "temp ˚C".to_string(),
+        35 => "temp ˚F".to_string(),
+        76 => "usage".to_string(),
+        170 => "init".to_string(),
+        180 => "wake".to_string(),
+        other => format!("unknown ({other})"),
+    }
+}
+
+/// Decodes bytes holding one decimal digit each.
+pub fn digits(bytes: &[u8]) -> String {
+    bytes.iter().map(|digit| digit.to_string()).collect()
+}
+
+/// Decodes a flag byte.
+pub fn on_off(byte: u8) -> String {
+    match byte {
+        0 => "off".to_string(),
+        1 => "on".to_string(),
+        other => format!("unknown ({other})"),
+    }
+}
+
+/// Names the fields of the AK series layout: mode, status bar, 3 digits and the alarm at `alarm` on the models that
+/// have one.
+pub fn describe_ak(packet: &[u8; 64], alarm: Option<usize>) -> Vec<PacketField> {
+    let mut fields = vec![
+        PacketField::new(0..1, "report id", ""),
+        PacketField::new(1..2, "mode", mode_name(packet[1])),
+    ];
+    if [19, 35, 76].contains(&packet[1]) {
+        fields.push(PacketField::new(2..3, "status bar", format!("{}/10", packet[2])));
+        fields.push(PacketField::new(3..6, "digits", digits(&packet[3..6])));
+        if let Some(offset) = alarm {
+            fields.push(PacketField::new(offset..offset + 1, "alarm", on_off(packet[offset])));
+        }
+    }
+
+    fields
+}
+
+/// Names the fields of a framed report, `payload` names the ones of a status packet.
+pub fn describe_frame(packet: &[u8; 64], payload: impl FnOnce() -> Vec<PacketField>) -> Vec<PacketField> {
+    // Blank reports are not framed
+    if packet[..2] != [16, 104] {
+        return vec![PacketField::new(0..1, "report id", "")];
+    }
+    let command = match packet[5] {
+        1 => "status".to_string(),
+        4 => "brightness".to_string(),
+        5 => "version query".to_string(),
+        other => other.to_string(),
+    };
+    let mut fields = vec![
+        PacketField::new(0..2, "header", ""),
+        PacketField::new(2..4, "device", ""),
+        PacketField::new(4..5, "length", packet[4]),
+        PacketField::new(5..6, "command", command),
+    ];
+    match packet[5] {
+        1 => fields.extend(payload()),
+        4 => fields.push(PacketField::new(6..7, "brightness", format!("{}%", packet[6]))),
+        _ => (),
+    }
+    let end = 5 + packet[4] as usize;
+    if end + 1 < packet.len() {
+        let checksum = frame_checksum(&packet[1..end]);
+        let value = if packet[end] == checksum {
+            "ok".to_string()
+        } else {
+            format!("expected {checksum:02x}")
+        };
+        fields.push(PacketField::new(end..end + 1, "checksum", value));
+        fields.push(PacketField::new(end + 1..end + 2, "terminator", ""));
+    }
+
+    fields
+}
+
+/// Formats a packet as a hexdump with a line per field, the other bytes are dumped 8 per line and runs of zeros are
+/// shortened.
+fn hexdump(packet: &[u8], fields: &[PacketField]) -> String {
+    let line = |offset: usize, bytes: &[u8], name: &str, value: &str| {
+        format!("  {offset:04x}  {:<23}  {name:<12} {value}", hex(bytes))
+            .trim_end()
+            .to_string()
+    };
+    let mut fields: Vec<&PacketField> = fields
+        .iter()
+        .filter(|field| !field.range.is_empty() && field.range.end <= packet.len())
+        .collect();
+    fields.sort_by_key(|field| field.range.start);
+
+    let mut lines = Vec::new();
+    let mut fields = fields.into_iter().peekable();
+    let mut offset = 0;
+    while offset < packet.len() {
+        if let Some(field) = fields.next_if(|field| field.range.start <= offset) {
+            // Overlapping fields are left out
+            if field.range.start == offset {
+                lines.push(line(offset, &packet[field.range.clone()], field.name, &field.value));
+                offset = field.range.end;
+            }
+            continue;
+        }
+        let end = fields.peek().map_or(packet.len(), |field| field.range.start);
+        let bytes = &packet[offset..end];
+        if bytes.len() > 8 && bytes.iter().all(|&byte| byte == 0) {
+            lines.push(format!("  {offset:04x}  00 × {}", bytes.len()));
+        } else {
+            for (index, chunk) in bytes.chunks(8).enumerate() {
+                lines.push(line(offset + index * 8, chunk, "", ""));
+            }
+        }
+        offset = end;
+    }
+
+    lines.join("\n")
+}
+
+/// Protocol of a single device model.
+pub trait DeviceDriver {
+    /// Returns the packets that have to be sent once after connecting to the device.
+    fn init(&self) -> Vec<[u8; 64]>;
+
+    /// Builds the data packet that shows the given status on the display.
+    fn build_status_packet(&mut self, status: &Status) -> [u8; 64];
+
+    /// Returns the features supported by the device.
+    fn capabilities(&self) -> Capabilities;
+
+    /// Returns the number of bytes sent from each packet.
+    fn report_length(&self) -> usize {
+        64
+    }
+
+    /// Returns the HID interface the packets have to be sent to.
+    fn interface(&self) -> Interface {
+        Interface::Any
+    }
+
+    /// Returns the packet that sets the brightness of the display (`0-100`), if the device can be dimmed.
+    fn brightness_packet(&self, _level: u8) -> Option<[u8; 64]> {
+        None
+    }
+
+    /// Switches the display to the next mode and returns it, if the device has display modes.
+    fn next_mode(&mut self) -> Option<String> {
+        None
+    }
+
+    /// Switches the display to the given mode, if the device has display modes.
+    fn set_mode(&mut self, _mode: &str) {}
+
+    /// Returns the packet that asks the device for its firmware version, if it can be queried.
+    fn version_query(&self) -> Option<[u8; 64]> {
+        None
+    }
+
+    /// Reads the firmware version from the reply to `version_query`.
+    fn parse_version(&self, _reply: &[u8]) -> Option<String> {
+        None
+    }
+
+    /// Returns the packets that blank the display before the program exits.
+    ///
+    /// The default is a zeroed report, which clears the digits and the status bar.
+    fn blank(&self) -> Vec<[u8; 64]> {
+        let mut data: [u8; 64] = [0; 64];
+        data[0] = 16;
+
+        vec![data]
+    }
+
+    /// Reacts to an input report sent by the device on its own, e.g. when a button is pressed.
+    ///
+    /// Returns `false` if the report is not such an event, it is then treated as the reply to the last packet.
+    fn handle_input(&mut self, _report: &[u8]) -> bool {
+        false
+    }
+
+    /// Validates the reply of the device to a packet, replies are accepted as they are by default.
+    fn check_response(&self, _response: &[u8]) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Names the fields of a packet built by the driver, for the annotated hexdump of the debug log.
+    ///
+    /// The bytes without a field are dumped as they are, which is all of them by default.
+    fn describe(&self, _packet: &[u8; 64]) -> Vec<PacketField> {
+        Vec::new()
+    }
+}
+
+/// Names of the known packet formats, accepted by the `--protocol` option.
+pub const PROTOCOLS: [&str; 10] = [
+    "ak400",
+    "ak500",
+    "ak620",
+    "ak620-pro",
+    "ag",
+    "ch510",
+    "ch560",
+    "ld",
+    "ls720",
+    "morpheus",
+];
+
+/// Returns the packet format used by the given product ID, or `None` if the device is not supported yet.
+pub fn protocol_for(product_id: u16) -> Option<&'static str> {
+    match product_id {
+        1 => Some("ak400"),
+        2 | 4 => Some("ak620"),
+        3 => Some("ak500"),
+        5 => Some("ch560"),
+        6 => Some("ls720"),
+        7 => Some("morpheus"),
+        8 | 9 => Some("ag"),
+        10 => Some("ld"),
+        11 => Some("ch510"),
+        17 => Some("ak620-pro"),
+        _ => None,
+    }
+}
+
+/// Returns the DeepCool devices plugged in, optionally only the ones with the given product ID or serial number.
+///
+/// Each device comes with all of its HID interfaces, pick the one receiving the packets with `Interface::select`.
+pub fn find(api: &HidApi, product_id: Option<u16>, serial: Option<&str>) -> Vec<Vec<DeviceInfo>> {
+    let mut found: Vec<(PathBuf, Vec<DeviceInfo>)> = Vec::new();
+    for device in api.device_list() {
+        if device.vendor_id() == VENDOR
+            && product_id.is_none_or(|pid| device.product_id() == pid)
+            && serial.is_none_or(|serial| device.serial_number() == Some(serial))
+        {
+            let usb_device = usb_device_of(device.path())
+                .unwrap_or_else(|| PathBuf::from(device.path().to_string_lossy().into_owned()));
+            match found.iter_mut().find(|(path, _)| *path == usb_device) {
+                Some((_, entries)) => entries.push(device.clone()),
+                None => found.push((usb_device, vec![device.clone()])),
+            }
+        }
+    }
+
+    found.into_iter().map(|(_, entries)| entries).collect()
+}
+
+/// Returns the sysfs path of the USB device a hidraw node belongs to.
+pub fn usb_device_of(path: &CStr) -> Option<PathBuf> {
+    let name = Path::new(path.to_str().ok()?).file_name()?;
+    // hidrawN/device links to the HID device, its parent is the USB interface then the USB device
+    let hid_device = canonicalize(Path::new("/sys/class/hidraw").join(name).join("device")).ok()?;
+
+    Some(hid_device.parent()?.parent()?.to_path_buf())
+}
+
+/// Returns the driver implementing the given packet format, or `None` if the name is unknown.
+pub fn driver_for(protocol: &str, mode: &str, alarm: bool) -> Option<Box<dyn DeviceDriver + Send>> {
+    match protocol {
+        "ak400" => Some(Box::new(ak400::Display::new(mode, alarm))),
+        "ak500" => Some(Box::new(ak500::Display::new(mode, alarm))),
+        "ak620" => Some(Box::new(ak_series::Display::new(mode, alarm))),
+        "ak620-pro" => Some(Box::new(ak620_pro::Display::new(alarm))),
+        "ag" => Some(Box::new(ag_series::Display::new(mode, alarm))),
+        "ch510" => Some(Box::new(ch510::Display::new(mode, alarm))),
+        "ch560" => Some(Box::new(ch_series::Display::new(alarm))),
+        "ld" => Some(Box::new(ld_series::Display)),
+        "ls720" => Some(Box::new(ls_series::Display::new(mode, alarm))),
+        "morpheus" => Some(Box::new(morpheus::Display)),
+        _ => None,
+    }
+}
+
+/// Converts a temperature in ˚C to the unit selected for the display, rounded and capped to what it can show.
+fn display_temp(celsius: f64, fahrenheit: bool) -> u8 {
+    let temp = if fahrenheit {
+        celsius * 9.0 / 5.0 + 32.0
+    } else {
+        celsius
+    };
+    // Float to integer casts saturate
+    temp.round() as u8
+}
+
+/// Formats bytes as space separated hex, e.g. for the packet fields of the log events.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Returns the total time the system has spent suspended since boot.
+///
+/// The boot time clock keeps running during suspend while the monotonic clock stops.
+fn suspended_time() -> Duration {
+    let read = |clock| {
+        let mut time = libc::timespec { tv_sec: 0, tv_nsec: 0 };
+        unsafe { libc::clock_gettime(clock, &mut time) };
+        Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+    };
+
+    read(libc::CLOCK_BOOTTIME).saturating_sub(read(libc::CLOCK_MONOTONIC))
+}
+
+/// HID device the packets are written to, implemented by `HidDevice` and by the stand-ins used without hardware.
+pub trait HidBackend: Send + 'static {
+    /// Writes a report, returns the number of bytes written.
+    fn write(&self, data: &[u8]) -> HidResult<usize>;
+
+    /// Reads a report into `buffer`, waiting at most `timeout` milliseconds; `0` bytes are read if none arrived.
+    fn read_timeout(&self, buffer: &mut [u8], timeout: i32) -> HidResult<usize>;
+
+    /// Returns the product name of the device, attached to the log events.
+    fn name(&self) -> String;
+}
+
+impl HidBackend for HidDevice {
+    fn write(&self, data: &[u8]) -> HidResult<usize> {
+        HidDevice::write(self, data)
+    }
+
+    fn read_timeout(&self, buffer: &mut [u8], timeout: i32) -> HidResult<usize> {
+        HidDevice::read_timeout(self, buffer, timeout)
+    }
+
+    fn name(&self) -> String {
+        self.get_product_string()
+            .ok()
+            .flatten()
+            .unwrap_or("unknown".to_string())
+    }
+}
+
+/// Stand-in for a device that logs the packets instead of sending them, used by `--dry-run`.
+pub struct Printer {
+    name: String,
+}
+
+impl Printer {
+    pub fn new(name: &str) -> Self {
+        Printer { name: name.to_string() }
+    }
+}
+
+impl HidBackend for Printer {
+    fn write(&self, data: &[u8]) -> HidResult<usize> {
+        info!(device = self.name, packet = hex(data); "Packet: {}", hex(data));
+        Ok(data.len())
+    }
+
+    fn read_timeout(&self, _buffer: &mut [u8], _timeout: i32) -> HidResult<usize> {
+        Ok(0)
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+}
+
+/// Sends the packets to the device from a separate thread, so a stuck write cannot hang the display loop.
+struct Writer {
+    packets: Sender<Vec<u8>>,
+    results: Receiver<HidResult<Vec<u8>>>,
+    timeout: Duration,
+    max_retries: u32,
+    write_errors: &'static AtomicU64,
+    /// Product name of the device, attached to the log events
+    name: String,
+}
+
+impl Writer {
+    fn new(device: impl HidBackend, report_length: usize, options: &RunOptions) -> Self {
+        let name = device.name();
+        let (packets, queue) = channel::<Vec<u8>>();
+        let (done, results) = channel();
+        let capture = options.capture.clone();
+        let device_name = name.clone();
+        // The thread ends once the writer is dropped, or when its write returns after a timeout
+        thread::spawn(move || {
+            let mut buffer = vec![0; report_length];
+            for packet in queue {
+                if let Some(capture) = &capture {
+                    capture.record(&device_name, Direction::Out, &packet);
+                }
+                // Not every device replies, so a missing or unreadable reply is left empty
+                let result = device.write(&packet).map(|_| {
+                    let length = device.read_timeout(&mut buffer, RESPONSE_TIMEOUT).unwrap_or(0);
+                    buffer[..length].to_vec()
+                });
+                if let (Some(capture), Ok(reply)) = (&capture, &result) {
+                    if !reply.is_empty() {
+                        capture.record(&device_name, Direction::In, reply);
+                    }
+                }
+                if done.send(result).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Writer {
+            packets,
+            results,
+            timeout: options.write_timeout,
+            max_retries: options.max_retries,
+            write_errors: &options.counters.write_errors,
+            name,
+        }
+    }
+
+    /// Writes a single packet, waiting at most `timeout` for it to complete.
+    ///
+    /// The outer error means the write did not complete in time, the inner one is the result of the write
+    /// with the reply of the device.
+    fn write_once(&self, packet: &[u8]) -> Result<HidResult<Vec<u8>>, HidError> {
+        let stopped = || HidError::HidApiError {
+            message: "writer thread stopped".to_string(),
+        };
+        self.packets.send(packet.to_vec()).map_err(|_| stopped())?;
+        match self.results.recv_timeout(self.timeout) {
+            Ok(result) => Ok(result),
+            Err(RecvTimeoutError::Timeout) => Err(HidError::HidApiError {
+                message: format!("write timed out after {:?}", self.timeout),
+            }),
+            Err(RecvTimeoutError::Disconnected) => Err(stopped()),
+        }
+    }
+
+    /// Writes a packet, failed writes are retried with an exponentially growing delay.
+    ///
+    /// Returns the last error after `max_retries` consecutive failures. A timed out write is not retried since the
+    /// device is still busy with it, the error is returned right away so the device gets reconnected.
+    fn write(&self, packet: &[u8]) -> Result<Vec<u8>, HidError> {
+        let mut delay = RETRY_DELAY;
+        let mut retries = 0;
+        loop {
+            let result = self.write_once(packet).inspect_err(|_| {
+                self.write_errors.fetch_add(1, Ordering::Relaxed);
+            })?;
+            match result {
+                Ok(response) => return Ok(response),
+                Err(err) => {
+                    self.write_errors.fetch_add(1, Ordering::Relaxed);
+                    if retries >= self.max_retries {
+                        return Err(err);
+                    }
+                    retries += 1;
+                    warning!(
+                        device = self.name;
+                        "Failed to write data ({err}), retry {retries}/{} in {delay:?}",
+                        self.max_retries
+                    );
+                    sleep(delay);
+                    delay = (delay * 2).min(MAX_RETRY_DELAY);
+                }
+            }
+        }
+    }
+}
+
+/// Sends a packet and checks the reply of the device, rejected packets are only logged.
+///
+/// Button presses are read along with the replies, so they are handled on the next update.
+fn send(writer: &Writer, driver: &mut dyn DeviceDriver, data: &[u8; 64]) -> Result<(), HidError> {
+    let packet = &data[..driver.report_length()];
+    if log::enabled(Level::Trace) {
+        trace!(
+            device = writer.name, packet = hex(packet);
+            "Writing {} bytes:\n{}", packet.len(), hexdump(packet, &driver.describe(data))
+        );
+    }
+    let response = writer.write(packet)?;
+    if !response.is_empty() && log::enabled(Level::Trace) {
+        trace!(device = writer.name, reply = hex(&response); "Reply: {:02x?}", response);
+    }
+    if response.is_empty() || driver.handle_input(&response) {
+        return Ok(());
+    }
+    if let Err(err) = driver.check_response(&response) {
+        warning!(
+            device = writer.name, packet = hex(&packet[..8]), reply = hex(&response);
+            "Device rejected packet {:02x?}: {err}\nReply: {:02x?}", &packet[..8], response
+        );
+    }
+
+    Ok(())
+}
+
+/// Sends the init sequence of the driver.
+fn init(writer: &Writer, driver: &mut dyn DeviceDriver) -> Result<(), HidError> {
+    for packet in driver.init() {
+        send(writer, driver, &packet)?;
+    }
+
+    Ok(())
+}
+
+/// Sends the packets that blank the display.
+fn send_blank(writer: &Writer, driver: &mut dyn DeviceDriver) -> Result<(), HidError> {
+    for packet in driver.blank() {
+        send(writer, driver, &packet)?;
+    }
+
+    Ok(())
+}
+
+/// Blanks the display of the device, used to turn it off without running the display loop.
+pub fn blank(device: impl HidBackend, driver: &mut dyn DeviceDriver, options: &RunOptions) -> Result<(), Error> {
+    let writer = Writer::new(device, driver.report_length(), options);
+    Ok(send_blank(&writer, driver)?)
+}
+
+/// Asks the device for its firmware version.
+///
+/// Returns `None` if the device cannot be queried or its reply is not understood.
+pub fn firmware_version(
+    device: impl HidBackend,
+    driver: &mut dyn DeviceDriver,
+    options: &RunOptions,
+) -> Result<Option<String>, Error> {
+    let Some(query) = driver.version_query() else {
+        return Ok(None);
+    };
+    let writer = Writer::new(device, driver.report_length(), options);
+    let reply = writer.write(&query[..driver.report_length()])?;
+
+    Ok(driver.parse_version(&reply))
+}
+
+/// Initializes the device and shows a single status on it.
+pub fn show(
+    device: impl HidBackend,
+    driver: &mut dyn DeviceDriver,
+    status: &Status,
+    options: &RunOptions,
+) -> Result<(), Error> {
+    let writer = Writer::new(device, driver.report_length(), options);
+    init(&writer, driver)?;
+    let packet = driver.build_status_packet(status);
+    Ok(send(&writer, driver, &packet)?)
+}
+
+/// Shows the test pattern with each driver, then blanks the display.
+///
+/// The pattern counts from `00` to `99` in steps of `11` while the status bar fills up, then shows `100` which also
+/// triggers the alarm. It is shown with the first driver in ˚C then in ˚F, then with the second driver.
+pub fn self_test(
+    device: impl HidBackend,
+    drivers: &mut [Box<dyn DeviceDriver + Send>],
+    options: &RunOptions,
+) -> Result<(), Error> {
+    let writer = Writer::new(device, drivers[0].report_length(), options);
+    for (index, fahrenheit) in [(0, false), (0, true), (1, false)] {
+        let Some(driver) = drivers.get_mut(index) else {
+            continue;
+        };
+        init(&writer, driver.as_mut())?;
+        for step in 0..=10 {
+            let value = if step == 10 { 100 } else { step * 11 };
+            let status = Status {
+                temp: value,
+                fahrenheit,
+                usage: value,
+                power: value as u16 * 3,
+                overheat: step == 10,
+            };
+            let packet = driver.build_status_packet(&status);
+            send(&writer, driver.as_mut(), &packet)?;
+            sleep(TEST_STEP);
+        }
+    }
+
+    Ok(send_blank(&writer, drivers[0].as_mut())?)
+}
+
+/// Sends the packets of a capture again, with the time between them they were recorded with.
+///
+/// Stops early once `stop` is set, the display is left as it is.
+pub fn replay(device: impl HidBackend, frames: &[&Frame], options: &RunOptions) -> Result<(), Error> {
+    let report_length = frames.iter().map(|frame| frame.data.len()).max().unwrap_or(64);
+    let writer = Writer::new(device, report_length, options);
+    let start = Instant::now();
+    let first = frames.first().map_or(Duration::ZERO, |frame| frame.time);
+    for frame in frames {
+        sleep((frame.time - first).saturating_sub(start.elapsed()));
+        if options.stop.load(Ordering::Relaxed) {
+            break;
+        }
+        if log::enabled(Level::Trace) {
+            trace!(device = writer.name, packet = hex(&frame.data); "Writing: {}", hex(&frame.data));
+        }
+        writer.write(&frame.data)?;
+    }
+
+    Ok(())
+}
+
+/// Initializes the device, then keeps sending the CPU status to it.
+///
+/// The device loses its state during suspend, so the init sequence is sent again after a resume. Updates are skipped
+/// while the sensors cannot be read, with a warning when they start failing and a note once they recover.
+/// Returns the error once the device cannot be written anymore, e.g. when it has been unplugged or stopped responding,
+/// or `Ok` after blanking the display when `stop` is set.
+pub fn run(device: impl HidBackend, driver: &mut dyn DeviceDriver, options: &RunOptions) -> Result<(), Error> {
+    // Init sequence
+    let writer = Writer::new(device, driver.report_length(), options);
+    init(&writer, driver)?;
+
+    // Display loop
+    let capabilities = driver.capabilities();
+    let mut suspended = suspended_time();
+    let mut brightness = None;
+    let mut blanked = false;
+    let mut overheat = false;
+    let mut mode_requests = options.next_mode.load(Ordering::Relaxed);
+    let settings = &options.settings;
+    let mut mode = settings.mode.lock().unwrap().clone();
+    let power = power_readable(&writer, driver);
+    let mut slowdown = 0;
+    let mut previous = None;
+    let mut samples = VecDeque::new();
+    let mut checked_poll = None;
+    let mut failing = false;
+    let timer = Timer::new();
+    loop {
+        let polling_rate = settings.poll.lock().unwrap().unwrap_or(capabilities.polling_rate);
+        if checked_poll != Some(polling_rate) {
+            checked_poll = Some(polling_rate);
+            check_poll(&writer, polling_rate, capabilities.polling_rate);
+        }
+        let polling_rate = if options.adaptive_poll {
+            adaptive_interval(polling_rate, slowdown)
+        } else {
+            polling_rate
+        };
+
+        // Read CPU utilization & energy consumption, right away or `window` before the update if it is shorter
+        let window = options.sample_window.unwrap_or(polling_rate);
+        let early = polling_rate.saturating_sub(window);
+        options.heartbeat.beat(polling_rate);
+        let woken = !early.is_zero() && timer.wait(early);
+        let sample = Sample::take(power);
+        if let Ok(sample) = &sample {
+            samples.push_back(*sample);
+            prune(&mut samples, window);
+        }
+
+        // Wait, the previous update is done, runtime controls cut the wait short
+        if !woken {
+            timer.wait(polling_rate - early);
+        }
+
+        // Blank the display on exit
+        if options.stop.load(Ordering::Relaxed) {
+            return Ok(send_blank(&writer, driver)?);
+        }
+
+        // Re-initialize after resume, the readings spanning the suspend are skipped
+        let now_suspended = suspended_time();
+        let resumed = now_suspended.saturating_sub(suspended) > RESUME_THRESHOLD;
+        suspended = now_suspended;
+        if resumed {
+            info!(device = writer.name; "Resumed from suspend, re-initializing the device");
+            init(&writer, driver)?;
+            brightness = None;
+            samples.clear();
+            continue;
+        }
+
+        // Keep the display blank while requested, it has to be initialized again afterwards
+        if options.blank.load(Ordering::Relaxed) {
+            if !blanked {
+                send_blank(&writer, driver)?;
+                blanked = true;
+            }
+            continue;
+        }
+        if blanked {
+            init(&writer, driver)?;
+            brightness = None;
+            blanked = false;
+        }
+
+        // Apply brightness changes
+        let level = options.brightness.load(Ordering::Relaxed);
+        if brightness != Some(level) {
+            if let Some(packet) = driver.brightness_packet(level) {
+                send(&writer, driver, &packet)?;
+            }
+            brightness = Some(level);
+        }
+
+        // Follow the mode of the settings, it changes when they are reloaded
+        let current = settings.mode.lock().unwrap().clone();
+        if current != mode {
+            mode = current;
+            driver.set_mode(&mode);
+        }
+
+        // Switch to the next mode on request
+        let requests = options.next_mode.load(Ordering::Relaxed);
+        if requests != mode_requests {
+            mode_requests = requests;
+            if let Some(mode) = driver.next_mode() {
+                info!(device = writer.name, mode = mode; "Display mode: {mode}");
+            }
+        }
+
+        // Calculate usage, temperature & power, the update is skipped while the sensors cannot be read
+        prune(&mut samples, window);
+        let readings = sample.and_then(|_| samples[0].readings(options));
+        let status = match readings.and_then(|readings| {
+            let status = build_status(&readings, options, overheat, capabilities.power)?;
+            *options.readings.lock().unwrap() = readings;
+            Ok(status)
+        }) {
+            Ok(status) => status,
+            Err(err) => {
+                if !failing {
+                    warning!(device = writer.name; "{err}, the display is not updated until it can be read again");
+                    failing = true;
+                }
+                continue;
+            }
+        };
+        if failing {
+            info!(device = writer.name; "The sensors can be read again");
+            failing = false;
+        }
+        overheat = status.overheat;
+        slowdown = match previous {
+            Some(previous) if idle_and_stable(&previous, &status) => (slowdown + 1).min(MAX_SLOWDOWN),
+            _ => 0,
+        };
+        previous = Some(status);
+        send_status(&writer, driver, status, options)?;
+    }
+}
+
+/// Warns when the updates are sent faster than the display refreshes, the extra ones are not shown.
+fn check_poll(writer: &Writer, polling_rate: Duration, refresh: Duration) {
+    if polling_rate < refresh {
+        warning!(
+            device = writer.name;
+            "Updating every {polling_rate:?} is faster than {} refreshes (every {refresh:?}), some updates will not be shown",
+            writer.name
+        );
+    }
+}
+
+/// Returns the interval of the adaptive polling, `base` doubled `slowdown` times up to `MAX_ADAPTIVE_POLL`.
+fn adaptive_interval(base: Duration, slowdown: u32) -> Duration {
+    (base * (1 << slowdown)).min(base.max(MAX_ADAPTIVE_POLL))
+}
+
+/// Returns whether the CPU is idle and the readings barely changed since the previous update.
+fn idle_and_stable(previous: &Status, status: &Status) -> bool {
+    status.usage < IDLE_USAGE
+        && previous.usage.abs_diff(status.usage) < STABLE_USAGE
+        && previous.temp.abs_diff(status.temp) <= STABLE_TEMP
+        && previous.overheat == status.overheat
+}
+
+/// Initializes the device and sends a single status, measured over one update interval.
+///
+/// The display keeps showing it, it is not blanked.
+pub fn once(device: impl HidBackend, driver: &mut dyn DeviceDriver, options: &RunOptions) -> Result<(), Error> {
+    let writer = Writer::new(device, driver.report_length(), options);
+    init(&writer, driver)?;
+    if let Some(packet) = driver.brightness_packet(options.brightness.load(Ordering::Relaxed)) {
+        send(&writer, driver, &packet)?;
+    }
+
+    let polling_rate = options
+        .settings
+        .poll
+        .lock()
+        .unwrap()
+        .unwrap_or(driver.capabilities().polling_rate);
+    let sample = Sample::take(power_readable(&writer, driver))?;
+    sleep(options.sample_window.unwrap_or(polling_rate));
+    let readings = sample.readings(options)?;
+    let status = build_status(&readings, options, false, driver.capabilities().power)?;
+    *options.readings.lock().unwrap() = readings;
+    Ok(send_status(&writer, driver, status, options)?)
+}
+
+/// Returns whether the device shows the power consumption and the energy counter can be read, with a warning if it
+/// cannot.
+fn power_readable(writer: &Writer, driver: &dyn DeviceDriver) -> bool {
+    if !driver.capabilities().power {
+        return false;
+    }
+    let readable = cpu::open_energy().is_some();
+    if !readable {
+        warning!(device = writer.name; "Failed to read the CPU energy counter, the power is shown as 0. {}", cpu::ENERGY_HINT);
+    }
+
+    readable
+}
+
+/// Drops the readings that are older than needed, the first one left is the newest one at least `window` old, or the
+/// oldest one if there is none.
+fn prune(samples: &mut VecDeque<Sample>, window: Duration) {
+    while samples.get(1).is_some_and(|sample| sample.time.elapsed() >= window) {
+        samples.pop_front();
+    }
+}
+
+/// CPU readings at the start of a sampling window, the status is calculated from the change until its end.
+#[derive(Clone, Copy)]
+struct Sample {
+    time: Instant,
+    instant: CpuInstant,
+    /// Energy counter, `None` if the power is not shown
+    energy: Option<u64>,
+}
+
+impl Sample {
+    /// Reads the CPU utilization & energy consumption.
+    fn take(power: bool) -> Result<Self, Error> {
+        Ok(Sample {
+            time: Instant::now(),
+            instant: cpu::read_instant()?,
+            energy: power.then(cpu::read_energy).transpose()?,
+        })
+    }
+
+    /// Reads the metrics, the CPU ones over the time since the sample was taken.
+    fn readings(self, options: &RunOptions) -> Result<Vec<Reading>, Error> {
+        let reading = |name: &str, unit, value| Reading {
+            name: name.to_string(),
+            unit,
+            value,
+        };
+        let mut readings = vec![
+            reading("cpu_temp", Unit::Celsius, cpu::read_temp(&options.cpu_temp_sensor)?),
+            reading("cpu_usage", Unit::Percent, cpu::read_usage(self.instant)?),
+        ];
+        if let Some(energy) = self.energy {
+            readings.push(reading("cpu_power", Unit::Watts, cpu::read_power(energy, self.time.elapsed())?));
+        }
+        options.metrics.sample(&mut readings);
+
+        Ok(readings)
+    }
+}
+
+/// Calculates the status from the readings of the metrics shown by the display.
+///
+/// `overheat` is whether the alarm was on at the last update. The power is `0` if the device does not show it, or if
+/// the CPU energy counter cannot be read.
+fn build_status(readings: &[Reading], options: &RunOptions, overheat: bool, power: bool) -> Result<Status, Error> {
+    let value = |unit, default| {
+        let metric = options.metrics.shown(unit, default);
+        match readings.iter().find(|reading| reading.name == metric) {
+            Some(reading) => Ok(Some(reading.value)),
+            None if metric == "cpu_power" => Ok(None),
+            None => Err(Error::Unavailable { metric }),
+        }
+    };
+    let fahrenheit = options
+        .settings
+        .fahrenheit
+        .lock()
+        .unwrap()
+        .unwrap_or_else(|| options.fahrenheit.load(Ordering::Relaxed));
+    let temp = display_temp(value(Unit::Celsius, "cpu_temp")?.unwrap_or_default(), fahrenheit);
+    // The alarm is cleared at a lower temperature than it goes off, so it does not flicker around the limit
+    let limit = if overheat {
+        options.alarm_clear
+    } else {
+        options.alarm_temp
+    };
+
+    Ok(Status {
+        temp,
+        fahrenheit,
+        usage: value(Unit::Percent, "cpu_usage")?.unwrap_or_default().round() as u8,
+        power: match power {
+            true => value(Unit::Watts, "cpu_power")?.unwrap_or_default().round() as u16,
+            false => 0,
+        },
+        overheat: temp > display_temp(limit as f64, fahrenheit),
+    })
+}
+
+/// Sends the status to the device and keeps it as the last status sent.
+fn send_status(
+    writer: &Writer,
+    driver: &mut dyn DeviceDriver,
+    status: Status,
+    options: &RunOptions,
+) -> Result<(), HidError> {
+    let packet = driver.build_status_packet(&status);
+    debug!(
+        device = writer.name, packet_type = "status", temp = status.temp, usage = status.usage,
+        power = status.power, alarm = status.overheat;
+        "Status: temp {} usage {}% power {}W alarm {}", status.temp, status.usage, status.power, status.overheat
+    );
+    send(writer, driver, &packet)?;
+    *options.status.lock().unwrap() = Some(status);
+
+    Ok(())
+}
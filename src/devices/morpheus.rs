@@ -0,0 +1,100 @@
+//! MORPHEUS
+//!
+//! The readout module of the case shows the temperature and the load side by side, using the
+//! framed report of the LD series.
+
+use super::{
+    check_frame, describe_frame, parse_version_frame, terminate_frame, version_query_frame, Alarm, Capabilities,
+    DeviceDriver, PacketField, Status,
+};
+use std::time::Duration;
+
+const POLLING_RATE: Duration = Duration::from_millis(1000);
+
+pub struct Display;
+
+impl Display {
+    /// Returns the common header of every packet.
+    fn header() -> [u8; 64] {
+        let mut data: [u8; 64] = [0; 64];
+        data[0] = 16;
+        data[1] = 104;
+        data[2] = 1;
+        data[3] = 7;
+
+        data
+    }
+}
+
+impl DeviceDriver for Display {
+    fn init(&self) -> Vec<[u8; 64]> {
+        let mut init_data = Self::header();
+        init_data[4] = 2;
+        init_data[5] = 3;
+        init_data[6] = 1;
+        terminate_frame(&mut init_data, 7);
+
+        vec![init_data]
+    }
+
+    fn build_status_packet(&mut self, status: &Status) -> [u8; 64] {
+        let mut status_data = Self::header();
+        status_data[4] = 5;
+        status_data[5] = 1;
+
+        // Temperature
+        let temp = (status.temp as u16).to_be_bytes();
+        status_data[6] = if status.fahrenheit { 1 } else { 0 };
+        status_data[7] = temp[0];
+        status_data[8] = temp[1];
+
+        // Load
+        status_data[9] = status.usage;
+
+        // Checksum & termination byte
+        terminate_frame(&mut status_data, 10);
+
+        status_data
+    }
+
+    fn brightness_packet(&self, level: u8) -> Option<[u8; 64]> {
+        let mut data = Self::header();
+        data[4] = 2;
+        data[5] = 4;
+        data[6] = level.min(100);
+        terminate_frame(&mut data, 7);
+
+        Some(data)
+    }
+
+    fn version_query(&self) -> Option<[u8; 64]> {
+        Some(version_query_frame(Self::header()))
+    }
+
+    fn parse_version(&self, reply: &[u8]) -> Option<String> {
+        parse_version_frame(reply)
+    }
+
+    fn describe(&self, packet: &[u8; 64]) -> Vec<PacketField> {
+        describe_frame(packet, || {
+            vec![
+                PacketField::new(6..7, "unit", if packet[6] == 1 { "˚F" } else { "˚C" }),
+                PacketField::new(7..9, "temp", u16::from_be_bytes([packet[7], packet[8]])),
+                PacketField::new(9..10, "load", format!("{}%", packet[9])),
+            ]
+        })
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            display_modes: false,
+            alarm: Alarm::Unsupported,
+            power: false,
+            polling_rate: POLLING_RATE,
+        }
+    }
+
+    fn check_response(&self, response: &[u8]) -> Result<(), String> {
+        check_frame(response)
+    }
+}
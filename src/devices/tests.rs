@@ -0,0 +1,491 @@
+//! End-to-end tests of the drivers, the packets are written to a `MockDevice`.
+
+use super::{mock::MockDevice, *};
+use crate::monitor::{expr::Expr, Computed, MetricProvider};
+
+/// Returns options with their own flags, so the tests do not affect each other.
+fn options() -> RunOptions {
+    RunOptions {
+        cpu_temp_sensor: String::new(),
+        fahrenheit: Box::leak(Box::new(AtomicBool::new(false))),
+        next_mode: Box::leak(Box::new(AtomicU32::new(0))),
+        settings: Arc::new(DeviceSettings::new("temp", Some(Duration::from_millis(1)), None)),
+        heartbeat: Arc::new(Heartbeat::new()),
+        max_retries: 0,
+        write_timeout: Duration::from_secs(1),
+        alarm_temp: 85,
+        alarm_clear: 85,
+        stop: Box::leak(Box::new(AtomicBool::new(false))),
+        brightness: Box::leak(Box::new(AtomicU8::new(100))),
+        blank: Box::leak(Box::new(AtomicBool::new(false))),
+        status: Box::leak(Box::new(Mutex::new(None))),
+        devices: Box::leak(Box::new(Mutex::new(Vec::new()))),
+        counters: Box::leak(Box::new(Counters {
+            write_errors: AtomicU64::new(0),
+            reconnects: AtomicU64::new(0),
+        })),
+        capture: None,
+        adaptive_poll: false,
+        sample_window: None,
+        metrics: Arc::new(Metrics::default()),
+        readings: Box::leak(Box::new(Mutex::new(Vec::new()))),
+    }
+}
+
+fn status(temp: u8, usage: u8) -> Status {
+    Status {
+        temp,
+        fahrenheit: false,
+        usage,
+        power: 120,
+        overheat: false,
+    }
+}
+
+#[test]
+fn show_sends_init_then_status() {
+    for protocol in PROTOCOLS {
+        let mut driver = driver_for(protocol, "temp", false).unwrap();
+        let mut expected: Vec<Vec<u8>> = driver.init().iter().map(|packet| packet.to_vec()).collect();
+        expected.push(driver.build_status_packet(&status(45, 50)).to_vec());
+        let length = driver.report_length();
+        for packet in &mut expected {
+            packet.truncate(length);
+        }
+
+        let mut driver = driver_for(protocol, "temp", false).unwrap();
+        let device = MockDevice::new();
+        show(device.clone(), driver.as_mut(), &status(45, 50), &options()).unwrap();
+        assert_eq!(device.frames(), expected, "{protocol}");
+    }
+}
+
+#[test]
+fn status_bar_of_any_value() {
+    // `show --value` passes values above 100 on as the usage
+    for protocol in PROTOCOLS {
+        for mode in ["temp", "usage"] {
+            let mut driver = driver_for(protocol, mode, false).unwrap();
+            driver.build_status_packet(&status(255, 255));
+        }
+    }
+    let mut driver = driver_for("ak620", "temp", false).unwrap();
+    assert_eq!(driver.build_status_packet(&status(45, 255))[2], 10);
+}
+
+#[test]
+fn ak620_status_packet() {
+    let mut driver = driver_for("ak620", "temp", true).unwrap();
+    let device = MockDevice::new();
+    let overheat = Status {
+        overheat: true,
+        ..status(91, 47)
+    };
+    show(device.clone(), driver.as_mut(), &overheat, &options()).unwrap();
+
+    let frames = device.frames();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0][..2], [16, 170]);
+    assert_eq!(frames[1][..7], [16, 19, 5, 0, 9, 1, 1]);
+}
+
+#[test]
+fn ak400_status_packet() {
+    let mut driver = driver_for("ak400", "temp", true).unwrap();
+    let device = MockDevice::new();
+    let overheat = Status {
+        overheat: true,
+        ..status(91, 47)
+    };
+    show(device.clone(), driver.as_mut(), &overheat, &options()).unwrap();
+
+    let frames = device.frames();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[0][..2], [16, 170]);
+    assert_eq!(frames[1][..8], [16, 19, 5, 0, 9, 1, 1, 0]);
+    assert!(frames[1][8..].iter().all(|&byte| byte == 0));
+
+    // The alarm stays off when it is not enabled
+    let mut driver = driver_for("ak400", "usage", false).unwrap();
+    assert_eq!(driver.build_status_packet(&overheat)[..7], [16, 76, 5, 0, 4, 7, 0]);
+
+    // The mode button of the display works like on the other AK series coolers
+    let mut driver = driver_for(protocol_for(1).unwrap(), "temp", false).unwrap();
+    let device = MockDevice::new();
+    device.reply(&[16, 85]);
+    show(device.clone(), driver.as_mut(), &status(45, 50), &options()).unwrap();
+    assert_eq!(device.frames()[1][..6], [16, 76, 5, 0, 5, 0]);
+}
+
+#[test]
+fn ak500_sends_its_handshake() {
+    let protocol = protocol_for(3).unwrap();
+    let mut driver = driver_for(protocol, "temp", false).unwrap();
+    let device = MockDevice::new();
+    show(device.clone(), driver.as_mut(), &status(45, 50), &options()).unwrap();
+
+    let frames = device.frames();
+    assert_eq!(frames.len(), 3);
+    // Wake packet, then the status bar animation and the status, all with the display enabled
+    assert_eq!(frames[0][..8], [16, 0, 0, 0, 0, 0, 0, 1]);
+    assert_eq!(frames[1][..8], [16, 170, 0, 0, 0, 0, 0, 1]);
+    assert_eq!(frames[2][..8], [16, 19, 5, 0, 4, 5, 0, 1]);
+}
+
+#[test]
+fn ld_status_frame() {
+    let mut driver = driver_for("ld", "temp", false).unwrap();
+    let device = MockDevice::new();
+    show(device.clone(), driver.as_mut(), &status(45, 50), &options()).unwrap();
+
+    let frames = device.frames();
+    assert_eq!(frames.len(), 3);
+    for frame in &frames {
+        check_frame(frame).unwrap();
+    }
+    assert_eq!(frames[2][..10], [16, 104, 1, 1, 11, 1, 2, 5, 0, 120]);
+    assert_eq!(frames[2][11..15], 45f32.to_be_bytes());
+    assert_eq!(frames[2][15], 50);
+}
+
+#[test]
+fn ak620_pro_sends_valid_frames() {
+    let mut driver = driver_for("ak620-pro", "temp", true).unwrap();
+    let device = MockDevice::new();
+    let overheat = Status {
+        overheat: true,
+        ..status(91, 47)
+    };
+    show(device.clone(), driver.as_mut(), &overheat, &options()).unwrap();
+
+    let mut frames = device.frames();
+    assert_eq!(frames.len(), 2);
+    frames.push(driver.brightness_packet(40).unwrap().to_vec());
+    frames.push(driver.version_query().unwrap().to_vec());
+    for frame in &frames {
+        check_frame(frame).unwrap();
+    }
+    assert_eq!(frames[1][..15], [16, 104, 1, 6, 8, 1, 0, 0, 120, 0, 91, 47, 1, 0x7b, 22]);
+}
+
+#[test]
+fn ak620_pro_status_packet_layout() {
+    // Written out by hand from the layout: header, length 8, command 1, unit, power and temperature in big-endian,
+    // utilization, alarm, then the checksum of bytes 1 to 12 and the termination byte
+    let packet = ak620_pro::StatusPacket {
+        temp: 45,
+        fahrenheit: false,
+        power: 120,
+        utilization: 50,
+        alarm: false,
+    };
+    let report = packet.to_report();
+    assert_eq!(report[..16], [16, 104, 1, 6, 8, 1, 0, 0, 120, 0, 45, 50, 0, 0x4f, 22, 0]);
+    check_frame(&report).unwrap();
+
+    let overheat = ak620_pro::StatusPacket {
+        temp: 203,
+        fahrenheit: true,
+        power: 300,
+        utilization: 100,
+        alarm: true,
+    };
+    let report = overheat.to_report();
+    assert_eq!(report[..16], [16, 104, 1, 6, 8, 1, 1, 1, 44, 0, 203, 100, 1, 0xd6, 22, 0]);
+    assert!(report[16..].iter().all(|&byte| byte == 0));
+    check_frame(&report).unwrap();
+
+    // The hexdump finds the checksum where the length puts it
+    let driver = driver_for("ak620-pro", "temp", true).unwrap();
+    let dump = hexdump(&report, &driver.describe(&report));
+    assert!(dump.contains("  000d  d6                       checksum     ok"), "{dump}");
+
+    // The driver only raises the alarm when it is enabled
+    let status = Status {
+        overheat: true,
+        ..status(45, 50)
+    };
+    let mut driver = driver_for("ak620-pro", "temp", false).unwrap();
+    assert_eq!(driver.build_status_packet(&status), packet.to_report());
+}
+
+#[test]
+fn mode_button_switches_mode() {
+    let mut driver = driver_for("ak620", "temp", false).unwrap();
+    let device = MockDevice::new();
+    // Pressed while the init packet is sent, so the status is shown in the next mode
+    device.reply(&[16, 85]);
+    show(device.clone(), driver.as_mut(), &status(45, 50), &options()).unwrap();
+
+    assert_eq!(device.frames()[1][..6], [16, 76, 5, 0, 5, 0]);
+}
+
+#[test]
+fn run_blanks_display_on_stop() {
+    let mut driver = driver_for("ak400", "temp", false).unwrap();
+    let device = MockDevice::new();
+    let options = options();
+    options.stop.store(true, Ordering::Relaxed);
+    run(device.clone(), driver.as_mut(), &options).unwrap();
+
+    let mut expected: Vec<Vec<u8>> = driver.init().iter().map(|packet| packet.to_vec()).collect();
+    expected.extend(driver.blank().iter().map(|packet| packet.to_vec()));
+    assert_eq!(device.frames(), expected);
+}
+
+#[test]
+fn firmware_version_from_reply() {
+    let mut driver = driver_for("ld", "temp", false).unwrap();
+    let device = MockDevice::new();
+    let mut reply = [0; 64];
+    reply[..7].copy_from_slice(&[16, 104, 1, 1, 3, 5, 2]);
+    reply[7] = 1;
+    terminate_frame(&mut reply, 8);
+    device.reply(&reply);
+
+    let version = firmware_version(device.clone(), driver.as_mut(), &options()).unwrap();
+    assert_eq!(version.as_deref(), Some("2.1"));
+    assert_eq!(device.frames().len(), 1);
+}
+
+#[test]
+fn unknown_devices_have_no_version() {
+    let mut driver = driver_for("ak400", "temp", false).unwrap();
+    let device = MockDevice::new();
+
+    let version = firmware_version(device.clone(), driver.as_mut(), &options()).unwrap();
+    assert_eq!(version, None);
+    assert!(device.frames().is_empty());
+}
+
+#[test]
+fn hexdump_names_fields() {
+    let mut driver = driver_for("ak620", "temp", true).unwrap();
+    let packet = driver.build_status_packet(&status(91, 47));
+    let dump = hexdump(&packet, &driver.describe(&packet));
+
+    let lines: Vec<&str> = dump.lines().collect();
+    assert_eq!(lines.len(), 6);
+    assert_eq!(lines[1], "  0001  13                       mode         temp ˚C");
+    assert_eq!(lines[3], "  0003  00 09 01                 digits       091");
+    assert_eq!(lines[5], "  0007  00 × 57");
+}
+
+#[test]
+fn hexdump_checks_frames() {
+    let mut driver = driver_for("morpheus", "temp", false).unwrap();
+    let mut packet = driver.build_status_packet(&status(45, 50));
+    packet[10] ^= 1;
+    let dump = hexdump(&packet[..20], &driver.describe(&packet));
+
+    assert!(dump.contains("load         50%"));
+    assert!(dump.contains("checksum     expected"));
+    assert!(dump.ends_with("  000c  00 00 00 00 00 00 00 00"));
+}
+
+#[test]
+fn replay_sends_recorded_packets() {
+    let path = std::env::temp_dir().join(format!("deepcool-capture-{}.json", std::process::id()));
+    let recording = RunOptions {
+        capture: Some(Arc::new(Capture::create(&path).unwrap())),
+        ..options()
+    };
+    let mut driver = driver_for("ak620", "temp", false).unwrap();
+    let recorded = MockDevice::new();
+    recorded.reply(&[16, 85]);
+    show(recorded.clone(), driver.as_mut(), &status(45, 50), &recording).unwrap();
+
+    let frames = crate::capture::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(frames.len(), 3);
+    assert!(frames[1].direction == Direction::In && frames[1].data == [16, 85]);
+    assert!(frames.iter().all(|frame| frame.device == "Mock"));
+
+    let sent: Vec<&Frame> = frames
+        .iter()
+        .filter(|frame| frame.direction == Direction::Out)
+        .collect();
+    let replayed = MockDevice::new();
+    replay(replayed.clone(), &sent, &options()).unwrap();
+    assert_eq!(replayed.frames(), recorded.frames());
+}
+
+#[test]
+fn once_sends_a_single_status() {
+    let mut driver = driver_for("ls720", "temp", false).unwrap();
+    let device = MockDevice::new();
+    let sensor = std::env::temp_dir().join(format!("deepcool-sensor-{}", std::process::id()));
+    std::fs::write(&sensor, "45000\n").unwrap();
+    let options = RunOptions {
+        cpu_temp_sensor: sensor.to_string_lossy().into_owned(),
+        ..options()
+    };
+    once(device.clone(), driver.as_mut(), &options).unwrap();
+    std::fs::remove_file(&sensor).unwrap();
+
+    let frames = device.frames();
+    assert_eq!(frames.len(), 3);
+    assert_eq!(frames[1][5..7], [4, 100]);
+    assert_eq!(frames[2][5..9], [1, 1, 0, 45]);
+    assert_eq!(options.status.lock().unwrap().map(|status| status.temp), Some(45));
+}
+
+#[test]
+fn once_reports_unreadable_sensor() {
+    let mut driver = driver_for("ls720", "temp", false).unwrap();
+    let device = MockDevice::new();
+    let options = RunOptions {
+        cpu_temp_sensor: "/nonexistent/temp1_input".to_string(),
+        ..options()
+    };
+    let result = once(device.clone(), driver.as_mut(), &options);
+
+    assert!(matches!(
+        result,
+        Err(Error::Read {
+            sensor: "CPU temperature",
+            ..
+        })
+    ));
+    assert_eq!(device.frames().len(), 2);
+    assert!(options.status.lock().unwrap().is_none());
+}
+
+/// Metric with a fixed value.
+struct Fixed(&'static str, Unit, f64);
+
+impl MetricProvider for Fixed {
+    fn name(&self) -> &str {
+        self.0
+    }
+
+    fn unit(&self) -> Unit {
+        self.1
+    }
+
+    fn sample(&mut self) -> Result<f64, Error> {
+        Ok(self.2)
+    }
+}
+
+#[test]
+fn selected_metrics_replace_the_cpu_readings() {
+    let reading = |name: &str, unit, value| Reading {
+        name: name.to_string(),
+        unit,
+        value,
+    };
+    let cpu = [
+        reading("cpu_temp", Unit::Celsius, 45.4),
+        reading("cpu_usage", Unit::Percent, 12.6),
+    ];
+    let selected = RunOptions {
+        metrics: Arc::new(Metrics::new(
+            vec![
+                Box::new(Fixed("gpu_temp", Unit::Celsius, 71.0)),
+                Box::new(Fixed("gpu_power", Unit::Watts, 180.0)),
+            ],
+            Vec::new(),
+            vec!["gpu_temp".to_string(), "gpu_power".to_string()],
+        )),
+        ..options()
+    };
+    let mut readings = cpu.to_vec();
+    selected.metrics.sample(&mut readings);
+    assert_eq!(readings.len(), 4);
+
+    let status = build_status(&readings, &selected, false, true).unwrap();
+    assert_eq!((status.temp, status.usage, status.power), (71, 13, 180));
+    // The power is left out by the devices that do not show it
+    assert_eq!(build_status(&readings, &selected, false, false).unwrap().power, 0);
+
+    // A selected metric that cannot be read skips the update, rather than showing the CPU reading
+    assert!(matches!(
+        build_status(&cpu, &selected, false, true),
+        Err(Error::Unavailable { metric }) if metric == "gpu_temp"
+    ));
+    let status = build_status(&cpu, &options(), false, true).unwrap();
+    assert_eq!((status.temp, status.usage, status.power), (45, 13, 0));
+}
+
+#[test]
+fn computed_metrics_combine_readings() {
+    let computed = |name: &str, unit, expr| Computed {
+        name: name.to_string(),
+        unit,
+        expr: Expr::parse(expr).unwrap(),
+    };
+    let options = RunOptions {
+        metrics: Arc::new(Metrics::new(
+            vec![Box::new(Fixed("loop_temp", Unit::Celsius, 38.0))],
+            vec![
+                computed("hottest", Unit::Celsius, "max(temp, loop_temp + 10)"),
+                computed("budget", Unit::Percent, "cpu_power / 105 * 100"),
+                computed("delta", Unit::Celsius, "-(hottest - avg(cpu_temp, 2 * 25)) * 2"),
+            ],
+            vec!["hottest".to_string(), "budget".to_string()],
+        )),
+        ..options()
+    };
+    let mut readings = vec![
+        Reading {
+            name: "cpu_temp".to_string(),
+            unit: Unit::Celsius,
+            value: 45.0,
+        },
+        Reading {
+            name: "cpu_power".to_string(),
+            unit: Unit::Watts,
+            value: 42.0,
+        },
+    ];
+    options.metrics.sample(&mut readings);
+    let values: Vec<(&str, f64)> = readings
+        .iter()
+        .map(|reading| (reading.name.as_str(), reading.value))
+        .collect();
+    assert_eq!(
+        values[2..],
+        [
+            ("loop_temp", 38.0),
+            ("hottest", 48.0),
+            ("budget", 40.0),
+            ("delta", -1.0)
+        ]
+    );
+
+    let status = build_status(&readings, &options, false, true).unwrap();
+    assert_eq!((status.temp, status.usage, status.power), (48, 40, 42));
+}
+
+#[test]
+fn adaptive_poll_slows_down_when_idle() {
+    let ms = Duration::from_millis;
+    assert_eq!(adaptive_interval(ms(750), 0), ms(750));
+    assert_eq!(adaptive_interval(ms(750), 2), ms(3000));
+    assert_eq!(adaptive_interval(ms(750), MAX_SLOWDOWN), MAX_ADAPTIVE_POLL);
+    assert_eq!(adaptive_interval(ms(5000), MAX_SLOWDOWN), ms(5000));
+
+    assert!(idle_and_stable(&status(45, 3), &status(46, 5)));
+    assert!(!idle_and_stable(&status(45, 3), &status(48, 3)));
+    assert!(!idle_and_stable(&status(45, 3), &status(45, 9)));
+    assert!(!idle_and_stable(&status(45, 30), &status(45, 30)));
+}
+
+#[test]
+fn sample_window_keeps_the_readings_it_spans() {
+    let now = Instant::now();
+    let sample = |age: u64| Sample {
+        time: now - Duration::from_millis(age),
+        ..Sample::take(false).unwrap()
+    };
+    let mut samples: VecDeque<Sample> = [1250, 1000, 750, 500, 250, 0].map(sample).into();
+    prune(&mut samples, Duration::from_millis(1000));
+    assert_eq!(samples.len(), 5);
+    assert!(samples[0].time <= now - Duration::from_millis(1000));
+
+    // Until the window is filled, the oldest reading is used
+    let mut samples: VecDeque<Sample> = [500, 250].map(sample).into();
+    prune(&mut samples, Duration::from_millis(1000));
+    assert_eq!(samples.len(), 2);
+}
@@ -0,0 +1,181 @@
+//! Lookup of the DeepCool devices, and selection of their driver and HID interface.
+
+use crate::{
+    cli::Args,
+    devices::{self, Alarm, DeviceDriver},
+    lock,
+    log::{info, warning},
+    state::BRIGHTNESS,
+};
+use hidapi::{DeviceInfo, HidApi, HidDevice, HidError, HidResult};
+use std::{ffi::CStr, sync::atomic::Ordering};
+
+/// Reason a device could not be used.
+pub enum ConnectError {
+    /// No driver or HID interface matches the device
+    Unsupported,
+    /// The HID interface could not be opened
+    Open(HidError),
+    /// The driver uses another HID interface of the device
+    Interface,
+    /// Another instance is driving the device
+    Busy(String),
+}
+
+/// HID interface of a device opened with its driver, the device is locked as long as the lock is kept.
+pub type Connection<'a> = (&'a DeviceInfo, HidDevice, Box<dyn DeviceDriver + Send>, lock::DeviceLock);
+
+/// Selects the driver and the HID interface of a device, then opens it.
+pub fn connect<'a>(
+    api: &HidApi,
+    entries: &'a [DeviceInfo],
+    args: &Args,
+    definitions: &[devices::generic::Definition],
+) -> Result<Connection<'a>, ConnectError> {
+    let info = &entries[0];
+    info!("Device found: {}", info.product_string().unwrap_or("unknown"));
+    if let Some(serial) = info.serial_number().filter(|serial| !serial.is_empty()) {
+        info!("SERIAL:     {}", serial);
+    }
+    let Some(driver) = select_driver(info.product_id(), args, definitions) else {
+        print_unsupported(info);
+        return Err(ConnectError::Unsupported);
+    };
+    let Some(info) = driver.interface().select(entries) else {
+        warning!("None of the HID interfaces match {:?}", driver.interface());
+        info!("-----");
+        return Err(ConnectError::Interface);
+    };
+    let key = devices::usb_device_of(info.path())
+        .and_then(|device| Some(device.file_name()?.to_string_lossy().into_owned()))
+        .or_else(|| {
+            info.serial_number()
+                .filter(|serial| !serial.is_empty())
+                .map(str::to_string)
+        })
+        .unwrap_or_else(|| info.path().to_string_lossy().into_owned());
+    let lock = lock::acquire(&key).map_err(|pid| {
+        let name = info.product_string().unwrap_or("The device");
+        ConnectError::Busy(match pid {
+            Some(pid) => format!("{name} is already used by another instance (PID {pid})"),
+            None => format!("{name} is already used by another instance"),
+        })
+    })?;
+    let device = info.open_device(api).map_err(|err| {
+        permission_hint(info.path());
+        ConnectError::Open(err)
+    })?;
+
+    Ok((info, device, driver, lock))
+}
+
+/// Returns the devices to use, either the one given by `--device` or the ones found by `find_devices`.
+///
+/// With `verbose`, the reason `--device` cannot be opened is printed.
+pub fn lookup(api: &HidApi, args: &Args, verbose: bool) -> Vec<Vec<DeviceInfo>> {
+    match &args.device {
+        Some(path) => match device_at(api, path) {
+            Ok(info) => vec![vec![info]],
+            Err(err) => {
+                if verbose {
+                    warning!("Failed to open {}: {err}", path.to_string_lossy());
+                    permission_hint(path);
+                }
+                Vec::new()
+            }
+        },
+        None => find_devices(api, args),
+    }
+}
+
+/// Explains how to get access to a hidraw node when the user is not allowed to open it.
+fn permission_hint(path: &CStr) {
+    if unsafe { libc::access(path.as_ptr(), libc::R_OK | libc::W_OK) } != 0
+        && std::io::Error::last_os_error().kind() == std::io::ErrorKind::PermissionDenied
+    {
+        warning!(
+            "No permission to open {}. Run the program as root or install the udev rules with install-udev-rules.",
+            path.to_string_lossy()
+        );
+    }
+}
+
+/// Returns the DeepCool devices matching the `--pid` and `--serial` options.
+///
+/// The HID entries are grouped by the USB device they belong to, so composite devices that expose
+/// several interfaces are only used once.
+fn find_devices(api: &HidApi, args: &Args) -> Vec<Vec<DeviceInfo>> {
+    devices::find(api, args.pid, args.serial.as_deref())
+}
+
+/// Opens the given hidraw node and returns its details.
+fn device_at(api: &HidApi, path: &CStr) -> HidResult<DeviceInfo> {
+    api.open_path(path).and_then(|device| device.get_device_info())
+}
+
+/// Selects the driver of the device and prints its settings, returns `None` if the device is not supported.
+///
+/// A forced protocol comes first, then the definition files, then the built-in protocols.
+pub fn select_driver(
+    product_id: u16,
+    args: &Args,
+    definitions: &[devices::generic::Definition],
+) -> Option<Box<dyn DeviceDriver + Send>> {
+    let mode = args.mode.as_str();
+    let definition = definitions
+        .iter()
+        .find(|definition| definition.product_id == product_id);
+    let driver: Box<dyn DeviceDriver + Send> = match (&args.protocol, definition) {
+        (None, Some(definition)) => {
+            info!("DEFINITION: {}", definition.name);
+            Box::new(devices::generic::Display::new(definition.clone(), args.alarm))
+        }
+        _ => {
+            if let Some(forced) = &args.protocol {
+                info!("PROTOCOL:   {} (forced)", forced);
+            }
+            let protocol = args.protocol.as_deref().or(devices::protocol_for(product_id))?;
+            devices::driver_for(protocol, mode, args.alarm)?
+        }
+    };
+
+    // Write info
+    let capabilities = driver.capabilities();
+    if capabilities.display_modes {
+        info!("DISP. MODE: {}", mode);
+    } else {
+        info!("DISP. MODE: not supported");
+    }
+    if mode != "usage" || !capabilities.display_modes {
+        info!("TEMP. UNIT: {}", if args.fahrenheit { "˚F" } else { "˚C" });
+    }
+    match capabilities.alarm {
+        Alarm::Configurable if args.alarm => match args.alarm_clear {
+            Some(clear) => info!("ALARM:      on ({}˚C, clears at {}˚C)", args.alarm_temp, clear),
+            None => info!("ALARM:      on ({}˚C)", args.alarm_temp),
+        },
+        Alarm::Configurable => info!("ALARM:      off"),
+        Alarm::BuiltIn => info!("ALARM:      built-in (85˚C | 185˚F)"),
+        Alarm::Unsupported => info!("ALARM:      not supported"),
+    }
+    let brightness = BRIGHTNESS.load(Ordering::Relaxed);
+    if driver.brightness_packet(brightness).is_some() {
+        info!("BRIGHTNESS: {}%", brightness);
+    }
+    info!("Update interval: {:?}", args.poll.unwrap_or(capabilities.polling_rate));
+    info!("-----");
+
+    Some(driver)
+}
+
+/// Prints the details of a device that is not supported yet.
+fn print_unsupported(info: &DeviceInfo) {
+    info!("Device not yet supported!");
+    info!("\nPlease create an issue on GitHub providing your device name and the following information:");
+    info!("Vendor ID: {}", info.vendor_id());
+    info!("Device ID: {}", info.product_id());
+    info!("Vendor name: {}", info.manufacturer_string().unwrap_or("unknown"));
+    info!("Device name: {}", info.product_string().unwrap_or("unknown"));
+    info!("\nThe output of the \"probe\" command helps a lot with adding support for the device.");
+    info!("-----");
+}
@@ -0,0 +1,46 @@
+//! Errors of the readings and of the devices.
+//!
+//! The display loops get over a failed reading by skipping the update, only the devices that cannot be written
+//! anymore end them.
+
+use hidapi::HidError;
+use std::{fmt, io};
+
+#[derive(Debug)]
+pub enum Error {
+    /// A sensor could not be read
+    Read { sensor: &'static str, source: io::Error },
+    /// A sensor returned something else than a number
+    Parse { sensor: &'static str, value: String },
+    /// No reading of a metric shown by the display
+    Unavailable { metric: String },
+    /// The device could not be written or read
+    Device(HidError),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Read { sensor, source } => write!(f, "Failed to read the {sensor}: {source}"),
+            Error::Parse { sensor, value } => write!(f, "Invalid {sensor} \"{value}\""),
+            Error::Unavailable { metric } => write!(f, "The {metric} metric is unavailable"),
+            Error::Device(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Read { source, .. } => Some(source),
+            Error::Parse { .. } | Error::Unavailable { .. } => None,
+            Error::Device(err) => Some(err),
+        }
+    }
+}
+
+impl From<HidError> for Error {
+    fn from(err: HidError) -> Self {
+        Error::Device(err)
+    }
+}
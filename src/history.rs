@@ -0,0 +1,64 @@
+//! Appends the readings to a CSV file, to graph them later.
+//!
+//! ```text
+//! time,temp,unit,usage,power,alarm
+//! 2024-05-01T21:30:00+02:00,54,C,12,35,0
+//! ```
+
+use crate::{
+    devices::RunOptions,
+    log::{local_timestamp, warning},
+};
+use std::{
+    fs::{create_dir_all, OpenOptions},
+    io::{self, Write},
+    path::Path,
+    thread::{self, sleep},
+    time::Duration,
+};
+
+/// Time between two samples
+const INTERVAL: Duration = Duration::from_secs(1);
+
+/// Appends a sample every second on a separate thread, the header is written when the file is new.
+pub fn start(path: &Path, options: RunOptions) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if file.metadata()?.len() == 0 {
+        writeln!(file, "time,temp,unit,usage,power,alarm")?;
+    }
+
+    let path = path.to_owned();
+    thread::spawn(move || {
+        // Only the first error in a row is reported, e.g. while the disk is full
+        let mut failing = false;
+        loop {
+            let status = *options.status.lock().unwrap();
+            if let Some(status) = status {
+                let result = writeln!(
+                    file,
+                    "{},{},{},{},{},{}",
+                    local_timestamp(),
+                    status.temp,
+                    if status.fahrenheit { "F" } else { "C" },
+                    status.usage,
+                    status.power,
+                    status.overheat as u8
+                );
+                match result {
+                    Ok(()) => failing = false,
+                    Err(err) if !failing => {
+                        warning!("Failed to write to {}: {err}", path.display());
+                        failing = true;
+                    }
+                    Err(_) => (),
+                }
+            }
+            sleep(INTERVAL);
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,93 @@
+//! Commands run when a reading crosses a limit.
+//!
+//! ```toml
+//! [[hooks]]
+//! metric = "temp"        # a metric, e.g. temp (˚C), usage (%) or power (W) of the CPU
+//! above = 90             # the command runs once the reading goes above this
+//! clear = 85             # optional, the reading has to drop to this to run the hook again
+//! command = "systemctl start fan-max.service"
+//! on_clear = "systemctl stop fan-max.service"   # optional
+//! ```
+//!
+//! The commands run with `sh -c`, the reading is passed in the `DEEPCOOL_METRIC` and `DEEPCOOL_VALUE`
+//! environment variables. `temp`, `usage` and `power` are the `cpu_temp`, `cpu_usage` and `cpu_power` metrics.
+
+use crate::{
+    log::warning,
+    monitor::metric_name,
+    toml::{get_int, get_str, Table},
+};
+use std::{process::Command, thread};
+
+/// Keys of a `[[hooks]]` entry
+pub const KEYS: &[&str] = &["metric", "above", "clear", "command", "on_clear"];
+
+pub struct Hook {
+    /// Name the metric is known by
+    pub metric: String,
+    /// Name of the metric in the settings, passed to the commands
+    name: String,
+    above: u64,
+    clear: u64,
+    command: String,
+    on_clear: Option<String>,
+    /// The reading is above the limit
+    active: bool,
+}
+
+impl Hook {
+    /// Parses and validates a `[[hooks]]` entry.
+    pub fn parse(table: &Table) -> Result<Self, String> {
+        let name = get_str(table, "metric")?.ok_or("missing \"hooks.metric\"")?;
+        let above = get_int(table, "above", u16::MAX as i64)?.ok_or("missing \"hooks.above\"")? as u64;
+        let clear = get_int(table, "clear", above as i64)?.map_or(above, |clear| clear as u64);
+        let command = get_str(table, "command")?
+            .ok_or("missing \"hooks.command\"")?
+            .to_string();
+        let on_clear = get_str(table, "on_clear")?.map(str::to_string);
+
+        Ok(Hook {
+            metric: metric_name(name),
+            name: name.to_string(),
+            above,
+            clear,
+            command,
+            on_clear,
+            active: false,
+        })
+    }
+
+    /// Runs the command of the hook when the reading crosses its limit.
+    pub fn check(&mut self, value: f64) {
+        if !self.active && value > self.above as f64 {
+            self.active = true;
+            run(&self.command, &self.name, value);
+        } else if self.active && value <= self.clear as f64 {
+            self.active = false;
+            if let Some(command) = &self.on_clear {
+                run(command, &self.name, value);
+            }
+        }
+    }
+}
+
+/// Starts the command in the background, its failures are only logged.
+fn run(command: &str, metric: &str, value: f64) {
+    let child = Command::new("sh")
+        .args(["-c", command])
+        .env("DEEPCOOL_METRIC", metric)
+        .env("DEEPCOOL_VALUE", format!("{value:.0}"))
+        .spawn();
+    let command = command.to_string();
+    match child {
+        // Wait on a separate thread to reap the process without blocking the readings
+        Ok(mut child) => {
+            thread::spawn(move || match child.wait() {
+                Ok(status) if !status.success() => warning!(hook = command; "Hook \"{command}\" failed: {status}"),
+                Err(err) => warning!(hook = command; "Hook \"{command}\" failed: {err}"),
+                _ => (),
+            });
+        }
+        Err(err) => warning!(hook = command; "Failed to run hook \"{command}\": {err}"),
+    }
+}
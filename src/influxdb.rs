@@ -0,0 +1,230 @@
+//! Writes the readings to an InfluxDB v2 bucket in the line protocol.
+//!
+//! ```toml
+//! [influxdb]
+//! url = "http://192.168.1.10:8086"
+//! org = "home"
+//! bucket = "deepcool"
+//! token = "..."
+//! measurement = "deepcool"      # optional
+//! interval = 1000               # optional, milliseconds between two samples
+//! ```
+//!
+//! Each sample is written as e.g. `deepcool,host=desktop temp=54i,unit="C",usage=12i,power=35i,alarm=false`
+//! with a timestamp in seconds, followed by a field per metric, e.g. `cpu_temp=54.25`. Only plain HTTP is supported.
+
+use crate::{
+    devices::{RunOptions, Status},
+    log::warning,
+    monitor::Reading,
+    toml::{get_int, get_str, Table},
+};
+use std::{
+    fs::read_to_string,
+    io::{self, Read, Write},
+    net::TcpStream,
+    thread::{self, sleep},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Time a write may take before the sample is dropped
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Keys of the `[influxdb]` table
+pub const KEYS: &[&str] = &["url", "org", "bucket", "token", "measurement", "interval"];
+
+#[derive(Clone)]
+pub struct Settings {
+    /// `host:port` of the server
+    address: String,
+    /// Path of the write endpoint with the query string
+    path: String,
+    token: String,
+    measurement: String,
+    interval: Duration,
+}
+
+impl Settings {
+    /// Parses and validates the `[influxdb]` table.
+    pub fn parse(table: &Table) -> Result<Self, String> {
+        let url = get_str(table, "url")?.ok_or("missing \"influxdb.url\"")?;
+        let rest = url
+            .strip_prefix("http://")
+            .ok_or(format!("unsupported URL \"{url}\", expected \"http://host:port\""))?;
+        let (host, base) = match rest.split_once('/') {
+            Some((host, base)) => (host, format!("/{}", base.trim_end_matches('/'))),
+            None => (rest, String::new()),
+        };
+        let address = if host.contains(':') {
+            host.to_string()
+        } else {
+            format!("{host}:8086")
+        };
+        let org = get_str(table, "org")?.ok_or("missing \"influxdb.org\"")?;
+        let bucket = get_str(table, "bucket")?.ok_or("missing \"influxdb.bucket\"")?;
+
+        Ok(Settings {
+            address,
+            path: format!("{base}/api/v2/write?org={}&bucket={}&precision=s", encode(org), encode(bucket)),
+            token: get_str(table, "token")?
+                .ok_or("missing \"influxdb.token\"")?
+                .to_string(),
+            measurement: get_str(table, "measurement")?.unwrap_or("deepcool").to_string(),
+            interval: Duration::from_millis(get_int(table, "interval", 3_600_000)?.unwrap_or(1000) as u64),
+        })
+    }
+}
+
+/// Writes the last status on a separate thread, samples that fail to be written are dropped.
+pub fn start(settings: Settings, options: RunOptions) {
+    let host = read_to_string("/proc/sys/kernel/hostname").unwrap_or_default();
+    let host = escape(host.trim());
+    thread::spawn(move || {
+        // Only the first error in a row is reported, to not flood the log while the server is down
+        let mut failing = false;
+        loop {
+            let status = *options.status.lock().unwrap();
+            if let Some(status) = status {
+                let readings = options.readings.lock().unwrap().clone();
+                let timestamp = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs();
+                let line = line(&settings.measurement, &host, &status, &readings, timestamp);
+                match write(&settings, &line) {
+                    Ok(()) => failing = false,
+                    Err(err) if !failing => {
+                        warning!("Failed to write to InfluxDB at {}: {err}", settings.address);
+                        failing = true;
+                    }
+                    Err(_) => (),
+                }
+            }
+            sleep(settings.interval);
+        }
+    });
+}
+
+/// Formats a sample in the line protocol, the metrics that are not a finite number are left out.
+fn line(measurement: &str, host: &str, status: &Status, readings: &[Reading], timestamp: u64) -> String {
+    let tags = if host.is_empty() {
+        String::new()
+    } else {
+        format!(",host={host}")
+    };
+    let metrics: String = readings
+        .iter()
+        .filter(|reading| reading.value.is_finite())
+        .map(|reading| format!(",{}={}", escape(&reading.name), reading.value))
+        .collect();
+    format!(
+        "{}{tags} temp={}i,unit=\"{}\",usage={}i,power={}i,alarm={}{metrics} {timestamp}\n",
+        escape(measurement),
+        status.temp,
+        if status.fahrenheit { "F" } else { "C" },
+        status.usage,
+        status.power,
+        status.overheat
+    )
+}
+
+/// Posts the lines to the write endpoint.
+fn write(settings: &Settings, lines: &str) -> io::Result<()> {
+    let mut stream = TcpStream::connect(&settings.address)?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    write!(
+        stream,
+        "POST {} HTTP/1.1\r\nHost: {}\r\nAuthorization: Token {}\r\nContent-Type: text/plain; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{lines}",
+        settings.path,
+        settings.address,
+        settings.token,
+        lines.len()
+    )?;
+
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    let status = response.split_whitespace().nth(1).unwrap_or_default();
+    if status.starts_with('2') {
+        Ok(())
+    } else {
+        let reason = response.lines().next().unwrap_or("no response");
+        let body = response
+            .split_once("\r\n\r\n")
+            .map(|(_, body)| body.trim())
+            .unwrap_or_default();
+        Err(io::Error::other(format!("{reason} {body}")))
+    }
+}
+
+/// Escapes the commas, spaces and equal signs of a measurement name or tag value.
+fn escape(value: &str) -> String {
+    value.replace(',', "\\,").replace(' ', "\\ ").replace('=', "\\=")
+}
+
+/// Percent-encodes a query string value.
+fn encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monitor::Unit;
+
+    const STATUS: Status = Status {
+        temp: 54,
+        fahrenheit: false,
+        usage: 12,
+        power: 35,
+        overheat: false,
+    };
+
+    fn reading(name: &str, value: f64) -> Reading {
+        Reading {
+            name: name.to_string(),
+            unit: Unit::Celsius,
+            value,
+        }
+    }
+
+    #[test]
+    fn escapes_names() {
+        assert_eq!(escape("desktop"), "desktop");
+        assert_eq!(escape("my desk,top=1"), "my\\ desk\\,top\\=1");
+    }
+
+    #[test]
+    fn full_line() {
+        let readings = [reading("cpu_temp", 54.25), reading("loop temp", 31.0)];
+        assert_eq!(
+            line("deepcool", "desktop", &STATUS, &readings, 1700000000),
+            "deepcool,host=desktop temp=54i,unit=\"C\",usage=12i,power=35i,alarm=false,cpu_temp=54.25,loop\\ temp=31 \
+             1700000000\n"
+        );
+        assert_eq!(
+            line("deep cool", "", &STATUS, &[], 1),
+            "deep\\ cool temp=54i,unit=\"C\",usage=12i,power=35i,alarm=false 1\n"
+        );
+    }
+
+    #[test]
+    fn skips_values_that_are_not_finite() {
+        let readings = [
+            reading("nan", f64::NAN),
+            reading("cpu_temp", 54.25),
+            reading("inf", f64::INFINITY),
+            reading("minus_inf", f64::NEG_INFINITY),
+        ];
+        assert_eq!(
+            line("deepcool", "desktop", &STATUS, &readings, 1),
+            "deepcool,host=desktop temp=54i,unit=\"C\",usage=12i,power=35i,alarm=false,cpu_temp=54.25 1\n"
+        );
+    }
+}
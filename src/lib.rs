@@ -0,0 +1,36 @@
+//! Protocols of the DeepCool displays, with the lookup of the devices and the readers of the CPU sensors.
+//!
+//! The `deepcool-digital-linux` program is built on it, other programs can drive the displays the same way:
+//! ```no_run
+//! use deepcool_digital::{devices, monitor::cpu};
+//!
+//! let api = hidapi::HidApi::new().unwrap();
+//! for entries in devices::find(&api, None, None) {
+//!     let info = devices::Interface::Any.select(&entries).unwrap();
+//!     let Some(protocol) = devices::protocol_for(info.product_id()) else { continue };
+//!     let mut driver = devices::driver_for(protocol, "temp", false).unwrap();
+//!     let status = devices::Status {
+//!         temp: cpu::get_temp(&cpu::lookup_temp_sensor().unwrap(), false).unwrap(),
+//!         fahrenheit: false,
+//!         usage: cpu::get_usage(cpu::read_instant().unwrap()).unwrap(),
+//!         power: 0,
+//!         overheat: false,
+//!     };
+//!
+//!     let device = info.open_device(&api).unwrap();
+//!     let length = driver.report_length();
+//!     for packet in driver.init() {
+//!         device.write(&packet[..length]).unwrap();
+//!     }
+//!     device.write(&driver.build_status_packet(&status)[..length]).unwrap();
+//! }
+//! ```
+
+pub mod capture;
+pub mod devices;
+pub mod error;
+pub mod json;
+pub mod log;
+pub mod monitor;
+pub mod timer;
+pub mod toml;
@@ -0,0 +1,67 @@
+//! Locks preventing two instances from driving the same device, their packets would mix up on the display.
+//!
+//! The lock is a `flock` on a file named after the USB port of the device, or its serial number, in `/run/lock`
+//! (`/tmp` when it is not writable). It is released when the instance stops, even if it crashes.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{Read, Seek, Write},
+    os::{
+        fd::AsRawFd,
+        unix::fs::{OpenOptionsExt, PermissionsExt},
+    },
+    path::Path,
+    process,
+};
+
+/// Folders the lock files are created in, the first writable one is used
+const LOCK_DIRS: [&str; 2] = ["/run/lock", "/tmp"];
+
+/// Lock of a device, released when dropped.
+pub struct DeviceLock {
+    _file: Option<File>,
+}
+
+/// Locks the device identified by `key`, e.g. its USB port "3-2".
+///
+/// Returns the PID of the instance holding the lock if there is one, `None` if it could not be read.
+pub fn acquire(key: &str) -> Result<DeviceLock, Option<u32>> {
+    let name = format!(
+        "deepcool-digital-{}.lock",
+        key.replace(|c: char| !c.is_ascii_alphanumeric() && c != '-' && c != '.', "_")
+    );
+    let Some(mut file) = LOCK_DIRS.iter().find_map(|dir| open(&Path::new(dir).join(&name))) else {
+        // Without a place for the lock, the device is still used
+        return Ok(DeviceLock { _file: None });
+    };
+
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+        let mut pid = String::new();
+        let _ = file.read_to_string(&mut pid);
+        return Err(pid.trim().parse().ok());
+    }
+
+    // Tell the other instances who holds the lock
+    let _ = file.set_len(0);
+    let _ = file.rewind();
+    let _ = write!(file, "{}", process::id());
+
+    Ok(DeviceLock { _file: Some(file) })
+}
+
+/// Opens or creates a lock file, it can be locked by other users too.
+fn open(path: &Path) -> Option<File> {
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(0o666)
+        .open(path)
+        .or_else(|_| File::open(path))
+        .ok()?;
+    // The mode is reduced by the umask when the file is created
+    let _ = fs::set_permissions(path, fs::Permissions::from_mode(0o666));
+
+    Some(file)
+}
@@ -0,0 +1,227 @@
+//! Logging of the runtime events.
+//!
+//! Events are written to stdout (info and below) or stderr (warnings and errors) as plain text, or as one JSON object
+//! per line with the time, the level and the fields. When the program runs as a systemd service and the text format
+//! is used, they are sent to the journal instead, with their fields attached so they can be filtered,
+//! e.g. `journalctl -u deepcool-digital DEVICE="AK620 DIGITAL"`.
+//!
+//! ```ignore
+//! info!(device = name; "Display mode: {mode}");
+//! ```
+
+use crate::json;
+use std::{
+    env,
+    fmt::{Arguments, Display},
+    io::Write,
+    os::unix::{fs::MetadataExt, net::UnixDatagram},
+    sync::{
+        atomic::{AtomicBool, AtomicU8, Ordering},
+        OnceLock,
+    },
+};
+
+/// Socket of the native journal protocol
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+/// Identifier of the program in the journal
+const IDENTIFIER: &str = "deepcool-digital";
+
+/// Severity of an event, from the most to the least important.
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum Level {
+    Error,
+    Warn,
+    /// Changes of the state, e.g. a device was connected
+    Info,
+    /// The readings sent at every update
+    Debug,
+    /// Every packet written and the replies of the devices
+    Trace,
+}
+
+impl Level {
+    /// Name of the level in the JSON events.
+    fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warning",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    /// Syslog priority of the level.
+    fn priority(self) -> u8 {
+        match self {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug | Level::Trace => 7,
+        }
+    }
+}
+
+/// Most detailed level that is logged
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+/// Events are written as JSON
+static JSON: AtomicBool = AtomicBool::new(false);
+
+/// Writes the events as one JSON object per line instead of plain text.
+pub fn set_json(json: bool) {
+    JSON.store(json, Ordering::Relaxed);
+}
+
+/// Sets the most detailed level that is logged.
+pub fn set_level(level: Level) {
+    MAX_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+/// Returns whether events of the level are logged, to skip preparing the ones that are not.
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Journal socket, if stderr is connected to the journal.
+fn journal() -> Option<&'static UnixDatagram> {
+    static JOURNAL: OnceLock<Option<UnixDatagram>> = OnceLock::new();
+    JOURNAL
+        .get_or_init(|| {
+            // systemd sets JOURNAL_STREAM to the device & inode of the stream it connected
+            let stream = env::var("JOURNAL_STREAM").ok()?;
+            let stderr = std::fs::metadata("/proc/self/fd/2").ok()?;
+            if stream != format!("{}:{}", stderr.dev(), stderr.ino()) {
+                return None;
+            }
+            UnixDatagram::unbound().ok()
+        })
+        .as_ref()
+}
+
+/// Logs an event with its fields, use the `error!`, `warning!`, `info!`, `debug!` and `trace!` macros instead.
+pub fn write(level: Level, fields: &[(&str, &dyn Display)], message: Arguments) {
+    if !enabled(level) {
+        return;
+    }
+
+    let json = JSON.load(Ordering::Relaxed);
+    if json {
+        let mut line = format!(
+            r#"{{"timestamp":"{}","level":"{}","message":"{}""#,
+            local_timestamp(),
+            level.name(),
+            json::escape(&message.to_string())
+        );
+        for (name, value) in fields {
+            line.push_str(&format!(r#","{name}":"{}""#, json::escape(&value.to_string())));
+        }
+        line.push('}');
+        print_line(level, &line);
+        return;
+    }
+
+    if let Some(journal) = journal() {
+        let mut entry = Vec::new();
+        append_field(&mut entry, "MESSAGE", &message.to_string());
+        append_field(&mut entry, "PRIORITY", &level.priority().to_string());
+        append_field(&mut entry, "SYSLOG_IDENTIFIER", IDENTIFIER);
+        for (name, value) in fields {
+            append_field(&mut entry, &name.to_uppercase(), &value.to_string());
+        }
+        if journal.send_to(&entry, JOURNAL_SOCKET).is_ok() {
+            return;
+        }
+    }
+
+    print_line(level, &message.to_string());
+}
+
+/// Writes a line to stderr for warnings and errors, to stdout otherwise.
+fn print_line(level: Level, line: &str) {
+    if level <= Level::Warn {
+        let _ = writeln!(std::io::stderr(), "{line}");
+    } else {
+        let _ = writeln!(std::io::stdout(), "{line}");
+    }
+}
+
+/// Appends a field in the journal export format, values spanning several lines are prefixed with their length.
+fn append_field(entry: &mut Vec<u8>, name: &str, value: &str) {
+    entry.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+/// Formats the current local time in RFC 3339, e.g. `2024-05-01T21:30:00+02:00`.
+pub fn local_timestamp() -> String {
+    let time = local_time();
+    let offset = time.tm_gmtoff / 60;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}{}{:02}:{:02}",
+        time.tm_year + 1900,
+        time.tm_mon + 1,
+        time.tm_mday,
+        time.tm_hour,
+        time.tm_min,
+        time.tm_sec,
+        if offset < 0 { '-' } else { '+' },
+        offset.abs() / 60,
+        offset.abs() % 60
+    )
+}
+
+/// Returns the current date and time in the local time zone.
+pub fn local_time() -> libc::tm {
+    let mut time: libc::tm = unsafe { std::mem::zeroed() };
+    unsafe {
+        let now = libc::time(std::ptr::null_mut());
+        libc::localtime_r(&now, &mut time);
+    }
+
+    time
+}
+
+/// Logs an event at the given level, with optional `name = value` fields before a `;`.
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($name:ident = $value:expr),+; $($arg:tt)+) => {
+        $crate::log::write($level, &[$((stringify!($name), &$value as &dyn std::fmt::Display)),+], format_args!($($arg)+))
+    };
+    ($level:expr, $($arg:tt)+) => {
+        $crate::log::write($level, &[], format_args!($($arg)+))
+    };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => { $crate::log::log!($crate::log::Level::Error, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! warning {
+    ($($arg:tt)+) => { $crate::log::log!($crate::log::Level::Warn, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => { $crate::log::log!($crate::log::Level::Info, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => { $crate::log::log!($crate::log::Level::Debug, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => { $crate::log::log!($crate::log::Level::Trace, $($arg)+) };
+}
+
+pub use crate::{debug, error, info, log, trace, warning};
@@ -1,105 +1,168 @@
-mod devices;
-mod monitor;
+mod cli;
+mod commands;
+mod completions;
+mod config;
+mod control;
+mod daemon;
+mod dbus;
+mod discovery;
+mod history;
+mod hooks;
+mod influxdb;
+mod lock;
+mod manual;
+mod metrics;
+mod mqtt;
+mod privileges;
+mod service;
+mod sources;
+mod state;
+mod supervisor;
+mod systemd;
+mod udev;
 
-use clap::Parser;
+use deepcool_digital::{capture, devices, json, log, monitor, timer, toml};
+
+use clap::{parser::ValueSource, CommandFactory, FromArgMatches};
+use cli::{load_config, resolve_args, Args, Commands, ConfigAction};
+use devices::Status;
 use hidapi::HidApi;
-use libc::geteuid;
-use monitor::cpu::find_temp_sensor;
+use log::error;
 use std::process::exit;
 
-const VENDOR: u16 = 0x3633;
-
-#[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
-struct Args {
-    /// Change the display mode between "temp, usage, auto"
-    #[arg(short, long, default_value_t = String::from("temp"))]
-    mode: String,
-
-    /// Change temperature unit to Fahrenheit
-    #[arg(short, long)]
-    fahrenheit: bool,
-
-    /// Enable the alarm (85˚C | 185˚F)
-    #[arg(short, long)]
-    alarm: bool,
-}
-
 fn main() {
-    // Check root
-    unsafe {
-        if geteuid() != 0 {
-            eprintln!("Try to run the program as root if it couldn't find or open the device.");
+    // Read args
+    let matches = Args::command().get_matches();
+    let mut args = Args::from_arg_matches(&matches).unwrap_or_else(|err| err.exit());
+    // The daemon runs from /, the relative paths are resolved before
+    if args.daemon {
+        for path in [
+            &mut args.config,
+            &mut args.sensor,
+            &mut args.socket,
+            &mut args.log_csv,
+            &mut args.record,
+            &mut args.pidfile,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            if let Ok(absolute) = std::path::absolute(&path) {
+                *path = absolute;
+            }
         }
     }
-
-    // Read args
-    let args = Args::parse();
-    if !["temp", "usage", "auto"].contains(&args.mode.as_str()) {
-        eprintln!("Invalid mode!");
-        exit(1);
+    // The mode of the settings file is used unless one is given on the command line or in the environment
+    let cli_mode = matches!(
+        matches.value_source("mode"),
+        Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+    )
+    .then(|| args.mode.clone());
+    log::set_json(args.log_format == "json");
+    if args.quiet {
+        log::set_level(log::Level::Error);
+    } else if args.debug {
+        log::set_level(log::Level::Trace);
+    } else if args.verbose {
+        log::set_level(log::Level::Debug);
     }
 
-    // Find device
-    let api = HidApi::new().expect("Failed to initialize HID API");
-    let mut product_id = 0;
-    for device in api.device_list() {
-        if device.vendor_id() == VENDOR {
-            product_id = device.product_id();
-            println!("Device found: {}", device.product_string().unwrap());
-            println!("-----");
-            break;
+    match &args.command {
+        Some(Commands::Config {
+            action: ConfigAction::Check,
+        }) => return commands::config_check(&args),
+        Some(Commands::Config {
+            action: ConfigAction::Show,
+        }) => return commands::config_show(&args, &matches, cli_mode.as_deref()),
+        Some(Commands::InstallUdevRules { group, path, print }) => {
+            return commands::install_udev_rules(group, path, *print)
         }
+        Some(Commands::Completions { shell }) => {
+            return print!("{}", completions::generate(shell, &mut Args::command()))
+        }
+        Some(Commands::Man) => return print!("{}", manual::generate(&mut Args::command())),
+        _ => (),
     }
-    if product_id == 0 {
-        eprintln!("No DeepCool device found!");
+
+    let api = HidApi::new().unwrap_or_else(|err| {
+        error!("Failed to initialize the HID API: {err}");
+        exit(1);
+    });
+    match args.command {
+        Some(Commands::ListDevices) => return commands::list_devices(&api),
+        Some(Commands::Probe) => return commands::probe(&api),
+        Some(
+            Commands::Off
+            | Commands::Info
+            | Commands::Test
+            | Commands::Show { .. }
+            | Commands::Replay { .. }
+            | Commands::InstallService { .. }
+            | Commands::InstallUdevRules { .. }
+            | Commands::Completions { .. }
+            | Commands::Man
+            | Commands::Config { .. },
+        )
+        | None => (),
+    }
+    if !devices::valid_mode(&args.mode) {
+        error!("Invalid mode!");
+        exit(1);
+    }
+    if args.dry_run && args.protocol.is_none() && args.pid.is_none() {
+        error!("--dry-run needs --protocol or --pid to pick the packet format!");
+        exit(1);
+    }
+    if args.alarm_clear.is_some_and(|clear| clear > args.alarm_temp) {
+        error!("The alarm has to clear at or below the temperature it goes off at!");
         exit(1);
     }
 
-    // Find CPU temp. sensor
-    let cpu_hwmon_path = find_temp_sensor();
-
-    // Connect to device and send datastream
-    match product_id {
-        1..=4 => {
-            // Write info
-            println!("DISP. MODE: {}", args.mode);
-            if args.mode != "usage" {
-                println!("TEMP. UNIT: {}", if args.fahrenheit { "˚F" } else { "˚C" });
-            }
-            println!("ALARM:      {}", if args.alarm { "on" } else { "off" });
-            println!("-----");
-            println!("Update interval: 750ms");
-            println!("\nPress Ctrl + C to terminate");
+    // Load the settings, the default file is optional
+    let config = load_config(&args).unwrap_or_else(|err| {
+        error!("Invalid settings file: {err}");
+        exit(1);
+    });
+    // The devices are connected with their own settings, the others use the ones shared by every device
+    let cli_args = args;
+    let (args, _) = resolve_args(&cli_args, cli_mode.as_deref(), &config, None);
+    if let Some(Commands::InstallService { path, enable, print }) = &args.command {
+        return commands::install_service(&args, &config, path, *enable, *print);
+    }
 
-            // Display loop
-            let ak_device = devices::ak_series::Display::new(product_id, args.fahrenheit, args.alarm);
-            ak_device.run(&api, &args.mode, &cpu_hwmon_path);
-        }
-        10 => {
-            // Write info
-            println!("DISP. MODE: not supported");
-            if args.mode != "usage" {
-                println!("TEMP. UNIT: {}", if args.fahrenheit { "˚F" } else { "˚C" });
-            }
-            println!("ALARM:      built-in (85˚C | 185˚F)");
-            println!("-----");
-            println!("Update interval: 1 second");
-            println!("\nPress Ctrl + C to terminate");
+    // Load the device definition files
+    let definitions = devices::generic::load_definitions(devices::generic::DEFINITIONS_DIR);
 
-            // Display loop
-            let ld_device = devices::ld_series::Display::new(product_id, args.fahrenheit);
-            ld_device.run(&api, &cpu_hwmon_path);
+    let options = supervisor::options(&args, &config);
+    match args.command {
+        Some(Commands::Off) => return commands::turn_off(&api, &args, &definitions, &options),
+        Some(Commands::Info) => return commands::info(&api, &args, &definitions, &options),
+        Some(Commands::Test) => return commands::self_test(&api, &args, &definitions, &options),
+        Some(Commands::Replay { ref capture }) => {
+            return commands::replay(&api, &args, &definitions, &options, capture)
         }
-        _ => {
-            println!("Device not yet supported!");
-            println!("\nPlease create an issue on GitHub providing your device name and the following information:");
-            let device = api.open(VENDOR, product_id).unwrap();
-            let info = device.get_device_info().unwrap();
-            println!("Vendor ID: {}", info.vendor_id());
-            println!("Device ID: {}", info.product_id());
-            println!("Vendor name: {}", info.manufacturer_string().unwrap());
-            println!("Device name: {}", info.product_string().unwrap());
+        Some(Commands::Show { value, bar }) => {
+            let status = Status {
+                temp: value,
+                usage: if args.mode == "usage" {
+                    value
+                } else {
+                    bar.unwrap_or(value)
+                },
+                fahrenheit: args.fahrenheit,
+                power: 0,
+                overheat: false,
+            };
+            return commands::show(&api, &args, &definitions, &options, &status);
         }
+        _ => (),
     }
+    if args.dry_run {
+        return commands::dry_run(&args, &definitions, &options);
+    }
+    if args.once {
+        return commands::update_once(&api, &args, &definitions, &options);
+    }
+
+    supervisor::run(api, cli_args, cli_mode, args, config, definitions, options);
 }
@@ -0,0 +1,159 @@
+//! Manual page in roff, generated from the definitions of the options and the commands.
+//!
+//! ```bash
+//! deepcool-digital-linux man > /usr/share/man/man1/deepcool-digital-linux.1
+//! ```
+
+use crate::{config::CONFIG_PATH, devices::generic::DEFINITIONS_DIR};
+use clap::{Arg, Command};
+
+/// Returns the manual page of `command`.
+pub fn generate(command: &mut Command) -> String {
+    command.build();
+    let name = command.get_name().to_string();
+    let version = command.get_version().unwrap_or_default();
+
+    let mut page = format!(
+        ".TH {} 1 \"\" \"{name} {version}\" \"User Commands\"\n",
+        escape(&name.to_uppercase())
+    );
+    page.push_str(&format!(
+        ".SH NAME\n{} \\- {}\n",
+        escape(&name),
+        escape(&text(command.get_about()))
+    ));
+    page.push_str(&format!(
+        ".SH SYNOPSIS\n\\fB{}\\fR [\\fIOPTIONS\\fR] [\\fICOMMAND\\fR]\n",
+        escape(&name)
+    ));
+    if let Some(description) = command.get_long_about() {
+        page.push_str(&format!(".SH DESCRIPTION\n{}\n", escape(&description.to_string())));
+    }
+
+    page.push_str(".SH OPTIONS\n");
+    for arg in options(command) {
+        page.push_str(&option(arg));
+    }
+
+    page.push_str(".SH COMMANDS\n");
+    for subcommand in command.get_subcommands() {
+        page.push_str(&subcommand_section(subcommand, &escape(subcommand.get_name())));
+    }
+
+    page.push_str(&format!(
+        ".SH FILES\n.TP\n\\fI{}\\fR\nSettings file, see \\fBconfig show\\fR for the settings in effect.\n\
+         .TP\n\\fI~/.config/deepcool\\-digital/config.toml\\fR\nSettings of the user, they override the ones above.\n\
+         .TP\n\\fI{}\\fR\nDevice definition files.\n",
+        escape(CONFIG_PATH),
+        escape(DEFINITIONS_DIR)
+    ));
+    page.push_str(
+        ".SH SIGNALS\n.TP\n\\fBSIGUSR1\\fR\nSwitch to the next display mode.\n.TP\n\\fBSIGUSR2\\fR\n\
+         Toggle the temperature unit.\n.TP\n\\fBSIGHUP\\fR\nRead the settings files again.\n\
+         .TP\n\\fBSIGINT\\fR, \\fBSIGTERM\\fR\nTurn the displays off and exit.\n",
+    );
+
+    let environment: Vec<&Arg> = options(command).filter(|arg| arg.get_env().is_some()).collect();
+    if !environment.is_empty() {
+        page.push_str(".SH ENVIRONMENT\n");
+        for arg in environment {
+            page.push_str(&format!(
+                ".TP\n\\fB{}\\fR\nSame as \\fB\\-\\-{}\\fR.\n",
+                escape(&arg.get_env().unwrap_or_default().to_string_lossy()),
+                escape(arg.get_long().unwrap_or_default())
+            ));
+        }
+    }
+
+    page
+}
+
+/// Returns the section of a command, with its options and commands indented below it.
+fn subcommand_section(command: &Command, name: &str) -> String {
+    let mut section = format!(".TP\n\\fB{name}\\fR\n{}\n", escape(&text(command.get_about())));
+    let arguments: Vec<&Arg> = options(command).filter(|arg| arg.get_long() != Some("help")).collect();
+    if !arguments.is_empty() {
+        section.push_str(".RS\n");
+        for arg in arguments {
+            section.push_str(&option(arg));
+        }
+        section.push_str(".RE\n");
+    }
+    for subcommand in command.get_subcommands().filter(|command| command.get_name() != "help") {
+        section.push_str(&subcommand_section(
+            subcommand,
+            &format!("{name} {}", escape(subcommand.get_name())),
+        ));
+    }
+
+    section
+}
+
+/// Returns the options and positional arguments of a command that are not hidden.
+fn options(command: &Command) -> impl Iterator<Item = &Arg> {
+    command.get_arguments().filter(|arg| !arg.is_hide_set())
+}
+
+/// Returns the entry of an option, with its default, its environment variable and its values.
+fn option(arg: &Arg) -> String {
+    let value = arg
+        .get_value_names()
+        .and_then(|names| names.first())
+        .map_or_else(|| arg.get_id().to_string().to_uppercase(), |name| name.to_string());
+    let takes_value = arg.get_num_args().is_some_and(|num| num.takes_values());
+
+    let mut forms = Vec::new();
+    if let Some(short) = arg.get_short() {
+        forms.push(format!("\\fB\\-{}\\fR", escape(&short.to_string())));
+    }
+    if let Some(long) = arg.get_long() {
+        forms.push(format!("\\fB\\-\\-{}\\fR", escape(long)));
+    }
+    let mut entry = match (forms.is_empty(), takes_value) {
+        (true, _) => format!(".TP\n\\fI{}\\fR\n", escape(&value)),
+        (false, true) => format!(".TP\n{} \\fI{}\\fR\n", forms.join(", "), escape(&value)),
+        (false, false) => format!(".TP\n{}\n", forms.join(", ")),
+    };
+    entry.push_str(&escape(&text(arg.get_long_help().or(arg.get_help()))));
+
+    let defaults: Vec<String> = arg
+        .get_default_values()
+        .iter()
+        .map(|value| value.to_string_lossy().into_owned())
+        .filter(|_| takes_value)
+        .collect();
+    if !defaults.is_empty() {
+        entry.push_str(&format!(" [default: {}]", escape(&defaults.join(", "))));
+    }
+    let values: Vec<String> = arg
+        .get_possible_values()
+        .iter()
+        .filter(|value| !value.is_hide_set())
+        .map(|value| value.get_name().to_string())
+        .filter(|_| takes_value)
+        .collect();
+    if !values.is_empty() {
+        entry.push_str(&format!(" [possible values: {}]", escape(&values.join(", "))));
+    }
+    entry.push('\n');
+
+    entry
+}
+
+/// Returns the text of a help message.
+fn text(text: Option<&clap::builder::StyledStr>) -> String {
+    text.map(ToString::to_string).unwrap_or_default()
+}
+
+/// Escapes text for roff, lines starting with a control character are protected.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\e")
+        .replace('-', "\\-")
+        .lines()
+        .map(|line| match line.starts_with(['.', '\'']) {
+            true => format!("\\&{line}"),
+            false => line.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,324 @@
+//! HTTP endpoint, serves the last readings and the connection counters to Prometheus on `/metrics`, and
+//! as JSON on `/status` for scripts and health checks.
+//!
+//! ```text
+//! GET /metrics
+//! deepcool_cpu_temperature_celsius 54
+//! deepcool_cpu_usage_percent 12
+//! deepcool_cpu_power_watts 35
+//! deepcool_alarm 0
+//! deepcool_write_errors_total 0
+//! deepcool_reconnects_total 0
+//! deepcool_metric{name="cpu_temp",unit="C"} 54.25
+//!
+//! GET /status
+//! {"temp":54,"unit":"C","usage":12,"power":35,"alarm":false,"metrics":{"cpu_temp":54.25,"cpu_usage":11.8},
+//!  "devices":["AK620 DIGITAL"],"health":{"status":"ok","uptime":3600,"write_errors":0,"reconnects":0}}
+//! ```
+
+use crate::{
+    devices::{Counters, RunOptions, Status},
+    json,
+    log::warning,
+    monitor::Reading,
+};
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    sync::atomic::Ordering,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Time a client may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Listens for scrapes on a separate thread.
+pub fn serve(address: &str, options: RunOptions) -> io::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    let started = Instant::now();
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(err) = handle(stream, &options, started) {
+                        warning!("Metrics request failed: {err}");
+                    }
+                }
+                Err(err) => warning!("Metrics listener failed: {err}"),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Answers a single HTTP request.
+fn handle(stream: TcpStream, options: &RunOptions, started: Instant) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // Skip the headers
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let text = "text/plain; version=0.0.4";
+    let (status, content_type, body) = match request.split_whitespace().take(2).collect::<Vec<_>>()[..] {
+        ["GET", "/metrics"] => {
+            let status = *options.status.lock().unwrap();
+            let readings = options.readings.lock().unwrap().clone();
+            ("200 OK", text, render(status, &readings, options.counters))
+        }
+        ["GET", "/status"] => {
+            let status = *options.status.lock().unwrap();
+            let readings = options.readings.lock().unwrap().clone();
+            let devices = options.devices.lock().unwrap().clone();
+            let uptime = started.elapsed().as_secs();
+            let json = status_json(status, &readings, &devices, options.counters, uptime);
+            ("200 OK", "application/json", json)
+        }
+        ["GET", _] => ("404 Not Found", text, "Not found, try /metrics or /status\n".to_string()),
+        _ => ("405 Method Not Allowed", text, String::new()),
+    };
+    write!(
+        writer,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
+
+/// Formats the readings in the Prometheus text format.
+fn render(status: Option<Status>, readings: &[Reading], counters: &Counters) -> String {
+    let mut text = String::new();
+    let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+        let _ = write!(text, "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n");
+    };
+
+    // The readings are left out until the first update, rather than reported as zeros
+    if let Some(status) = status {
+        let celsius = if status.fahrenheit {
+            (status.temp as u64).saturating_sub(32) * 5 / 9
+        } else {
+            status.temp as u64
+        };
+        metric("deepcool_cpu_temperature_celsius", "gauge", "CPU temperature.", celsius);
+        metric("deepcool_cpu_usage_percent", "gauge", "CPU utilization.", status.usage as u64);
+        metric(
+            "deepcool_cpu_power_watts",
+            "gauge",
+            "CPU power consumption.",
+            status.power as u64,
+        );
+        metric(
+            "deepcool_alarm",
+            "gauge",
+            "Whether the temperature is over the alarm limit.",
+            status.overheat as u64,
+        );
+    }
+    metric(
+        "deepcool_write_errors_total",
+        "counter",
+        "Writes to the devices that failed or timed out.",
+        counters.write_errors.load(Ordering::Relaxed),
+    );
+    metric(
+        "deepcool_reconnects_total",
+        "counter",
+        "Connections lost to a device.",
+        counters.reconnects.load(Ordering::Relaxed),
+    );
+    // One series per metric, including the ones that are not shown
+    if !readings.is_empty() {
+        let _ = write!(
+            text,
+            "# HELP deepcool_metric Reading of every metric.\n# TYPE deepcool_metric gauge\n"
+        );
+        for reading in readings {
+            let value = match reading.value {
+                value if value.is_nan() => "NaN".to_string(),
+                f64::INFINITY => "+Inf".to_string(),
+                f64::NEG_INFINITY => "-Inf".to_string(),
+                value => value.to_string(),
+            };
+            let _ = writeln!(
+                text,
+                "deepcool_metric{{name=\"{}\",unit=\"{}\"}} {value}",
+                escape(&reading.name),
+                reading.unit.symbol()
+            );
+        }
+    }
+
+    text
+}
+
+/// Escapes the backslashes, quotes and line feeds of a label value.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Formats the readings, the connected devices and the health of the program as JSON.
+///
+/// The readings are `null` until the first update, and a metric is `null` while its value is not a finite number. The
+/// status is `ok` while at least one device is driven.
+fn status_json(
+    status: Option<Status>,
+    readings: &[Reading],
+    devices: &[String],
+    counters: &Counters,
+    uptime: u64,
+) -> String {
+    let fields = match status {
+        Some(status) => format!(
+            r#""temp":{},"unit":"{}","usage":{},"power":{},"alarm":{}"#,
+            status.temp,
+            if status.fahrenheit { "F" } else { "C" },
+            status.usage,
+            status.power,
+            status.overheat
+        ),
+        None => r#""temp":null,"unit":null,"usage":null,"power":null,"alarm":null"#.to_string(),
+    };
+    let metrics = readings
+        .iter()
+        .map(|reading| {
+            // JSON has no NaN or infinity
+            let value = match reading.value.is_finite() {
+                true => reading.value.to_string(),
+                false => "null".to_string(),
+            };
+            format!("\"{}\":{value}", json::escape(&reading.name))
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+    let names = devices
+        .iter()
+        .map(|name| format!("\"{}\"", json::escape(name)))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let mut json = format!(
+        r#"{{{fields},"metrics":{{{metrics}}},"devices":[{names}],"health":{{"status":"{}","uptime":{uptime},"write_errors":{},"reconnects":{}}}}}"#,
+        if devices.is_empty() { "no device" } else { "ok" },
+        counters.write_errors.load(Ordering::Relaxed),
+        counters.reconnects.load(Ordering::Relaxed)
+    );
+    json.push('\n');
+
+    json
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monitor::Unit;
+    use std::sync::atomic::AtomicU64;
+
+    fn counters(write_errors: u64, reconnects: u64) -> Counters {
+        Counters {
+            write_errors: AtomicU64::new(write_errors),
+            reconnects: AtomicU64::new(reconnects),
+        }
+    }
+
+    fn reading(name: &str, value: f64) -> Reading {
+        Reading {
+            name: name.to_string(),
+            unit: Unit::Celsius,
+            value,
+        }
+    }
+
+    #[test]
+    fn metrics_before_the_first_update() {
+        assert_eq!(
+            render(None, &[], &counters(1, 2)),
+            concat!(
+                "# HELP deepcool_write_errors_total Writes to the devices that failed or timed out.\n",
+                "# TYPE deepcool_write_errors_total counter\n",
+                "deepcool_write_errors_total 1\n",
+                "# HELP deepcool_reconnects_total Connections lost to a device.\n",
+                "# TYPE deepcool_reconnects_total counter\n",
+                "deepcool_reconnects_total 2\n",
+            )
+        );
+    }
+
+    #[test]
+    fn metrics_with_escaped_labels() {
+        let readings = [
+            reading("cpu_temp", 54.25),
+            reading("a\\b \"c\"\nd", 1.0),
+            reading("nan", f64::NAN),
+            reading("inf", f64::INFINITY),
+            reading("minus_inf", f64::NEG_INFINITY),
+        ];
+        let text = render(None, &readings, &counters(0, 0));
+        let series = text
+            .lines()
+            .filter(|line| line.starts_with("deepcool_metric"))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            series,
+            [
+                r#"deepcool_metric{name="cpu_temp",unit="C"} 54.25"#,
+                r#"deepcool_metric{name="a\\b \"c\"\nd",unit="C"} 1"#,
+                r#"deepcool_metric{name="nan",unit="C"} NaN"#,
+                r#"deepcool_metric{name="inf",unit="C"} +Inf"#,
+                r#"deepcool_metric{name="minus_inf",unit="C"} -Inf"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn status_before_the_first_update() {
+        assert_eq!(
+            status_json(None, &[], &[], &counters(0, 0), 5),
+            concat!(
+                r#"{"temp":null,"unit":null,"usage":null,"power":null,"alarm":null,"metrics":{},"devices":[],"#,
+                r#""health":{"status":"no device","uptime":5,"write_errors":0,"reconnects":0}}"#,
+                "\n"
+            )
+        );
+    }
+
+    #[test]
+    fn status_of_a_device() {
+        let status = Status {
+            temp: 54,
+            fahrenheit: false,
+            usage: 12,
+            power: 35,
+            overheat: false,
+        };
+        let readings = [reading("cpu_temp", 54.25), reading("loop \"temp\"", 31.0)];
+        assert_eq!(
+            status_json(Some(status), &readings, &["AK620 DIGITAL".to_string()], &counters(2, 1), 3600),
+            concat!(
+                r#"{"temp":54,"unit":"C","usage":12,"power":35,"alarm":false,"#,
+                r#""metrics":{"cpu_temp":54.25,"loop \"temp\"":31},"devices":["AK620 DIGITAL"],"#,
+                r#""health":{"status":"ok","uptime":3600,"write_errors":2,"reconnects":1}}"#,
+                "\n"
+            )
+        );
+    }
+
+    #[test]
+    fn status_without_values_that_are_not_finite() {
+        let readings = [
+            reading("nan", f64::NAN),
+            reading("inf", f64::INFINITY),
+            reading("minus_inf", f64::NEG_INFINITY),
+        ];
+        let json = status_json(None, &readings, &[], &counters(0, 0), 0);
+        assert!(json.contains(r#""metrics":{"nan":null,"inf":null,"minus_inf":null}"#), "{json}");
+    }
+}
@@ -1,67 +1,211 @@
 //! Reads live CPU data from the Linux kernel.
 
+use super::{MetricProvider, Unit};
+use crate::error::Error;
 use cpu_monitor::CpuInstant;
-use std::{fs::read_to_string, process::exit};
+use std::{
+    fs::{read_to_string, File},
+    io,
+    os::unix::fs::FileExt,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
 
-/// Looks for the appropriate CPU temperature sensor datastream in the hwmon folder.
-pub fn find_temp_sensor() -> String {
+/// Returns the path of the CPU temperature sensor in the hwmon folder, if there is one.
+pub fn lookup_temp_sensor() -> Option<String> {
     let mut i = 0;
     loop {
-        match read_to_string(format!("/sys/class/hwmon/hwmon{i}/name")) {
-            Ok(data) => {
-                let hwname = data.trim_end();
-                if ["coretemp", "k10temp", "zenpower"].contains(&hwname) {
-                    return format!("/sys/class/hwmon/hwmon{i}/temp1_input");
-                }
-            }
-            Err(_) => {
-                println!("CPU temperature sensor not found!");
-                exit(1);
-            }
+        let hwname = read_to_string(format!("/sys/class/hwmon/hwmon{i}/name")).ok()?;
+        if ["coretemp", "k10temp", "zenpower"].contains(&hwname.trim_end()) {
+            return Some(format!("/sys/class/hwmon/hwmon{i}/temp1_input"));
         }
         i += 1;
     }
 }
 
 /// Reads the value of the CPU temperature sensor and calculates it to be `˚C` or `˚F`.
-pub fn get_temp(temp_sensor: &str, fahrenheit: bool) -> u8 {
-    // Read sensor data
-    let data = read_to_string(temp_sensor).expect("CPU temperature cannot be read!");
-
-    // Calculate temperature
-    let mut temp = data.trim_end().parse::<u32>().unwrap();
+pub fn get_temp(temp_sensor: &str, fahrenheit: bool) -> Result<u8, Error> {
+    let mut temp = read_temp(temp_sensor)?;
     if fahrenheit {
-        temp = temp * 9 / 5 + 32000
+        temp = temp * 9.0 / 5.0 + 32.0
     }
 
-    (temp as f32 / 1000.0).round() as u8
+    Ok(temp.round() as u8)
+}
+
+/// Reads the value of the CPU temperature sensor in `˚C`.
+pub fn read_temp(temp_sensor: &str) -> Result<f64, Error> {
+    const SENSOR: &str = "CPU temperature";
+
+    // Read sensor data
+    let data = read_to_string(temp_sensor).map_err(|source| Error::Read { sensor: SENSOR, source })?;
+
+    Ok(parse(SENSOR, &data)? as f64 / 1000.0)
+}
+
+/// Energy counter of the CPU, only readable by root by default
+const ENERGY_PATH: &str = "/sys/class/powercap/intel-rapl/intel-rapl:0/energy_uj";
+
+/// How to get access to the energy counter
+pub const ENERGY_HINT: &str = "Run the program as root or install the udev rules with install-udev-rules.";
+
+/// Opens the energy counter of the CPU if it is not open yet, it stays open so it can still be read after the
+/// privileges are dropped.
+pub fn open_energy() -> Option<&'static File> {
+    static ENERGY: OnceLock<Option<File>> = OnceLock::new();
+    ENERGY.get_or_init(|| File::open(ENERGY_PATH).ok()).as_ref()
 }
 
 /// Reads the energy consumption of the CPU in microjoules.
-pub fn read_energy() -> u64 {
-    let data = read_to_string("/sys/class/powercap/intel-rapl/intel-rapl:0/energy_uj")
-        .expect("CPU energy consumption cannot be read!");
+pub fn read_energy() -> Result<u64, Error> {
+    const SENSOR: &str = "CPU energy counter";
+
+    // sysfs files are read again from the start to get the current value
+    let mut data = [0; 32];
+    let length = open_energy()
+        .ok_or_else(|| io::Error::from(io::ErrorKind::PermissionDenied))
+        .and_then(|file| file.read_at(&mut data, 0))
+        .map_err(|source| Error::Read { sensor: SENSOR, source })?;
 
-    data.trim_end().parse::<u64>().unwrap()
+    parse(SENSOR, &String::from_utf8_lossy(&data[..length]))
 }
 
 /// Reads the energy consumption one more time and calculates the CPU power by using the inital energy and the delta time.
 ///
-/// Formula: `W = ΔμJ / (Δms * 1000)`
-pub fn get_power(initial_energy: u64, delta_millisec: u64) -> u16 {
-    let delta_energy = read_energy() - initial_energy;
+/// Formula: `W = ΔμJ / (Δs * 1000000)`
+pub fn get_power(initial_energy: u64, elapsed: Duration) -> Result<u16, Error> {
+    Ok(read_power(initial_energy, elapsed)?.round() as u16)
+}
+
+/// Calculates the CPU power like `get_power`, in watts without rounding.
+pub fn read_power(initial_energy: u64, elapsed: Duration) -> Result<f64, Error> {
+    // The counter wraps around, the reading is skipped then
+    let delta_energy = read_energy()?.saturating_sub(initial_energy);
 
-    (delta_energy as f64 / (delta_millisec * 1000) as f64).round() as u16
+    Ok(delta_energy as f64 / (elapsed.as_secs_f64().max(0.001) * 1_000_000.0))
 }
 
 /// Reads the CPU instant and provides usage statistics.
-pub fn read_instant() -> CpuInstant {
-    CpuInstant::now().expect("CPU time cannot be read!")
+pub fn read_instant() -> Result<CpuInstant, Error> {
+    CpuInstant::now().map_err(|source| Error::Read {
+        sensor: "CPU time",
+        source,
+    })
 }
 
 /// Reads the CPU instant one more time and calculates the utilization as a `0-100` number.
-pub fn get_usage(initial_instant: CpuInstant) -> u8 {
-    let usage = (read_instant() - initial_instant).non_idle() * 100.0;
+pub fn get_usage(initial_instant: CpuInstant) -> Result<u8, Error> {
+    Ok(read_usage(initial_instant)?.round() as u8)
+}
+
+/// Calculates the utilization like `get_usage`, without rounding.
+pub fn read_usage(initial_instant: CpuInstant) -> Result<f64, Error> {
+    Ok((read_instant()? - initial_instant).non_idle() * 100.0)
+}
+
+// Providers
+
+/// Temperature of the CPU sensor, `cpu_temp`.
+pub struct CpuTemp {
+    pub sensor: String,
+}
+
+impl MetricProvider for CpuTemp {
+    fn name(&self) -> &str {
+        "cpu_temp"
+    }
+
+    fn unit(&self) -> Unit {
+        Unit::Celsius
+    }
+
+    fn sample(&mut self) -> Result<f64, Error> {
+        read_temp(&self.sensor)
+    }
+}
+
+/// Utilization of the CPU since the previous sample, `cpu_usage`.
+pub struct CpuUsage {
+    last: Option<CpuInstant>,
+}
+
+impl CpuUsage {
+    pub fn new() -> Self {
+        CpuUsage {
+            last: read_instant().ok(),
+        }
+    }
+}
+
+impl Default for CpuUsage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MetricProvider for CpuUsage {
+    fn name(&self) -> &str {
+        "cpu_usage"
+    }
+
+    fn unit(&self) -> Unit {
+        Unit::Percent
+    }
+
+    fn sample(&mut self) -> Result<f64, Error> {
+        let instant = read_instant()?;
+        // The first sample has nothing to compare with, it is taken again at the next one
+        let last = self.last.replace(instant).ok_or(Error::Unavailable {
+            metric: self.name().to_string(),
+        })?;
+
+        Ok((instant - last).non_idle() * 100.0)
+    }
+}
+
+/// Power consumption of the CPU since the previous sample, `cpu_power`.
+pub struct CpuPower {
+    last: Option<(Instant, u64)>,
+}
+
+impl CpuPower {
+    pub fn new() -> Self {
+        CpuPower {
+            last: read_energy().ok().map(|energy| (Instant::now(), energy)),
+        }
+    }
+}
+
+impl Default for CpuPower {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MetricProvider for CpuPower {
+    fn name(&self) -> &str {
+        "cpu_power"
+    }
+
+    fn unit(&self) -> Unit {
+        Unit::Watts
+    }
+
+    fn sample(&mut self) -> Result<f64, Error> {
+        let energy = read_energy()?;
+        let (time, last) = self.last.replace((Instant::now(), energy)).ok_or(Error::Unavailable {
+            metric: self.name().to_string(),
+        })?;
+
+        // The counter wraps around, the reading is skipped then
+        Ok(energy.saturating_sub(last) as f64 / (time.elapsed().as_secs_f64().max(0.001) * 1_000_000.0))
+    }
+}
 
-    (usage).round() as u8
+/// Parses the number a sensor file holds.
+fn parse(sensor: &'static str, data: &str) -> Result<u64, Error> {
+    data.trim_end().parse().map_err(|_| Error::Parse {
+        sensor,
+        value: data.trim_end().to_string(),
+    })
 }
@@ -0,0 +1,132 @@
+//! Drives with a temperature sensor, the NVMe ones through the hwmon chip of the `nvme` driver, the SATA ones through
+//! the chip of the `drivetemp` driver, which is only loaded on request (`modprobe drivetemp`).
+//!
+//! The chip of an NVMe drive labels its main temperature `Composite`, the other inputs are single sensors of some
+//! drives. The chip of a SATA drive has a single input, without a label.
+
+use super::{
+    hwmon::{chips, temp_input, Input},
+    MetricProvider, Unit,
+};
+use std::{
+    fs::{canonicalize, read_dir, read_to_string},
+    path::PathBuf,
+};
+
+/// Names and units of the drive metrics
+pub const DRIVE_METRICS: [(&str, Unit); 1] = [("drive_temp", Unit::Celsius)];
+
+/// Drive with a hwmon chip.
+struct Drive {
+    chip: PathBuf,
+    /// Name of the NVMe controller, e.g. `nvme0`, or of the block device of a SATA drive, e.g. `sda`
+    name: String,
+    model: String,
+}
+
+impl Drive {
+    /// Tells whether the drive is the one selected by name, with or without the namespace (`nvme0n1`), or by a part of
+    /// its model.
+    fn matches(&self, selected: &str) -> bool {
+        let namespace = selected
+            .strip_prefix(&self.name)
+            .and_then(|rest| rest.strip_prefix('n'))
+            .is_some_and(|rest| !rest.is_empty() && rest.chars().all(|c| c.is_ascii_digit()));
+        selected == self.name || namespace || self.model.to_lowercase().contains(&selected.to_lowercase())
+    }
+}
+
+/// Returns the NVMe drives, then the SATA ones, in the order of their chips.
+fn drives() -> Vec<Drive> {
+    let drive = |chip: PathBuf, name: Option<String>| {
+        let device = canonicalize(chip.join("device")).ok()?;
+        Some(Drive {
+            name: name.or_else(|| Some(device.file_name()?.to_string_lossy().into_owned()))?,
+            model: read_to_string(device.join("model"))
+                .map(|model| model.trim().to_string())
+                .unwrap_or_default(),
+            chip,
+        })
+    };
+    let nvme = chips("nvme").into_iter().filter_map(|chip| drive(chip, None));
+    // The device of the chip is the SCSI device, its block device is named after the drive
+    let sata = chips("drivetemp").into_iter().filter_map(|chip| {
+        let block = read_dir(chip.join("device/block")).ok()?.flatten().next()?;
+        drive(chip, Some(block.file_name().to_string_lossy().into_owned()))
+    });
+
+    nvme.chain(sata).collect()
+}
+
+/// Returns the provider of a drive metric, from the selected drive or the first one, NVMe first.
+pub fn provider(name: &str, selected: Option<&str>) -> Result<Box<dyn MetricProvider>, String> {
+    if name != "drive_temp" {
+        return Err(format!("unknown drive metric \"{name}\""));
+    }
+    let drives = drives();
+    let drive = match selected {
+        Some(selected) => drives.iter().find(|drive| drive.matches(selected)).ok_or_else(|| {
+            let found: Vec<String> = drives
+                .iter()
+                .map(|drive| format!("{} ({})", drive.name, drive.model))
+                .collect();
+            match found.is_empty() {
+                true => format!("no drive \"{selected}\", no drive with a temperature sensor was found"),
+                false => format!("no drive \"{selected}\", expected one of {}", found.join(", ")),
+            }
+        })?,
+        None => drives
+            .first()
+            .ok_or("no drive with a temperature sensor, the SATA drives need the drivetemp module")?,
+    };
+    let path = temp_input(&drive.chip, "Composite", true).ok_or(format!("{} has no temperature sensor", drive.name))?;
+
+    Ok(Box::new(Input {
+        name: "drive_temp",
+        unit: Unit::Celsius,
+        sensor: "drive temperature",
+        path,
+        scale: 1000.0,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn drive(name: &str, model: &str) -> Drive {
+        Drive {
+            chip: PathBuf::from("/sys/class/hwmon/hwmon1"),
+            name: name.to_string(),
+            model: model.to_string(),
+        }
+    }
+
+    #[test]
+    fn selected_by_name() {
+        let nvme = drive("nvme1", "Samsung SSD 990 PRO 2TB");
+        assert!(nvme.matches("nvme1"));
+        assert!(nvme.matches("nvme1n1"));
+        assert!(nvme.matches("nvme1n12"));
+        assert!(!nvme.matches("nvme1n"));
+        assert!(!nvme.matches("nvme1n1p2"));
+        assert!(!nvme.matches("nvme10"));
+        assert!(!nvme.matches("nvme"));
+        assert!(!drive("nvme10", "").matches("nvme1"));
+        assert!(!drive("nvme1", "").matches("nvme10n1"));
+
+        let sata = drive("sda", "WDC WD40EFRX-68N");
+        assert!(sata.matches("sda"));
+        assert!(!sata.matches("sda1"));
+        assert!(!sata.matches("sdb"));
+    }
+
+    #[test]
+    fn selected_by_model() {
+        let nvme = drive("nvme0", "Samsung SSD 990 PRO 2TB");
+        assert!(nvme.matches("990 pro"));
+        assert!(nvme.matches("SAMSUNG"));
+        assert!(!nvme.matches("980"));
+        assert!(!drive("nvme0", "").matches("Samsung"));
+    }
+}
@@ -0,0 +1,214 @@
+//! Metrics read from the output of a command, for sensors the program does not know about.
+//!
+//! The command runs on its own thread, so a slow one does not hold up the other metrics: each reading starts the next
+//! run and shows the result of the last one that finished. Only the first reading waits for the command.
+
+use super::{MetricProvider, Unit};
+use crate::error::Error;
+use std::{
+    io::{self, Read},
+    process::{Command, Stdio},
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread::{self, sleep},
+    time::{Duration, Instant},
+};
+
+/// Time between two checks of whether the command has exited
+const WAIT_STEP: Duration = Duration::from_millis(10);
+
+const SENSOR: &str = "output of the command";
+
+/// Metric printed by a command, e.g. a script reading a sensor of a custom loop.
+pub struct Exec {
+    name: String,
+    unit: Unit,
+    /// Command run with `sh -c`, it prints the reading as a number
+    command: String,
+    /// Time the command may take before it is killed and the reading skipped
+    timeout: Duration,
+    /// Result of the run in progress
+    running: Option<Receiver<Result<f64, Error>>>,
+    /// Last value read, `None` until the first run finished
+    last: Option<f64>,
+}
+
+impl Exec {
+    pub fn new(name: &str, unit: Unit, command: &str, timeout: Duration) -> Self {
+        Exec {
+            name: name.to_string(),
+            unit,
+            command: command.to_string(),
+            timeout,
+            running: None,
+            last: None,
+        }
+    }
+
+    /// Starts running the command on a separate thread.
+    fn start(&self) -> Receiver<Result<f64, Error>> {
+        let (sender, receiver) = mpsc::channel();
+        let (command, timeout) = (self.command.clone(), self.timeout);
+        thread::spawn(move || {
+            let _ = sender.send(read(&command, timeout));
+        });
+
+        receiver
+    }
+}
+
+impl MetricProvider for Exec {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn unit(&self) -> Unit {
+        self.unit
+    }
+
+    fn sample(&mut self) -> Result<f64, Error> {
+        let result = match self.running.take() {
+            // The first reading waits for the command, the margin covers the start of the thread
+            None => self.start().recv_timeout(self.timeout + Duration::from_secs(1)).ok(),
+            Some(running) => match running.try_recv() {
+                Ok(result) => Some(result),
+                Err(TryRecvError::Empty) => {
+                    self.running = Some(running);
+                    None
+                }
+                Err(TryRecvError::Disconnected) => None,
+            },
+        };
+        if self.running.is_none() {
+            self.running = Some(self.start());
+        }
+
+        match result {
+            Some(Ok(value)) => self.last = Some(value),
+            Some(Err(err)) => {
+                self.last = None;
+                return Err(err);
+            }
+            // Still running, the last value is kept
+            None => (),
+        }
+        self.last.ok_or_else(|| Error::Unavailable {
+            metric: self.name.clone(),
+        })
+    }
+}
+
+/// Runs a command and parses what it printed as a finite number.
+fn read(command: &str, timeout: Duration) -> Result<f64, Error> {
+    let output = run(command, timeout).map_err(|source| Error::Read { sensor: SENSOR, source })?;
+    let value = output.trim();
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|value| value.is_finite())
+        .ok_or_else(|| Error::Parse {
+            sensor: SENSOR,
+            value: value.to_string(),
+        })
+}
+
+/// Runs a command and returns what it printed, it is killed if it does not exit within `timeout`.
+///
+/// The output is read while the command runs, so it does not block once the pipe is full.
+pub fn run(command: &str, timeout: Duration) -> io::Result<String> {
+    let mut child = Command::new("sh")
+        .args(["-c", command])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    let (sender, output) = mpsc::channel();
+    if let Some(mut stdout) = child.stdout.take() {
+        thread::spawn(move || {
+            let mut output = String::new();
+            let _ = sender.send(stdout.read_to_string(&mut output).map(|_| output));
+        });
+    }
+
+    let deadline = Instant::now() + timeout;
+    let timed_out = || {
+        io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("\"{command}\" did not exit within {timeout:?}"),
+        )
+    };
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(timed_out());
+        }
+        sleep(WAIT_STEP);
+    };
+    if !status.success() {
+        return Err(io::Error::other(format!("\"{command}\" failed: {status}")));
+    }
+
+    // A process started in the background can keep the output open after the command exited
+    match output.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+        Ok(output) => output,
+        Err(_) => Err(timed_out()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn reads_long_output() {
+        // More than the 64 KiB of a pipe
+        let output = run("seq 1 100000", TIMEOUT).unwrap();
+        assert_eq!(output.lines().count(), 100_000);
+        assert_eq!(output.lines().last(), Some("100000"));
+    }
+
+    #[test]
+    fn kills_slow_commands() {
+        let err = run("sleep 5", Duration::from_millis(50)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(run("exit 3", TIMEOUT).is_err());
+    }
+
+    #[test]
+    fn rejects_values_that_are_not_finite() {
+        assert_eq!(read("echo ' 61.5 '", TIMEOUT).unwrap(), 61.5);
+        for value in ["nan", "NaN", "inf", "-infinity", "1e999", "hot"] {
+            assert!(
+                matches!(read(&format!("echo {value}"), TIMEOUT), Err(Error::Parse { .. })),
+                "{value}"
+            );
+        }
+    }
+
+    #[test]
+    fn runs_in_the_background() {
+        let path = std::env::temp_dir().join(format!("deepcool-exec-{}", std::process::id()));
+        std::fs::write(&path, "40").unwrap();
+        let mut exec = Exec::new("loop_temp", Unit::Celsius, &format!("cat {}", path.display()), TIMEOUT);
+        // The first reading waits for the command
+        assert_eq!(exec.sample().unwrap(), 40.0);
+
+        // The next ones show the last value until the run they started finished, the file is replaced at once so the
+        // runs never read it empty
+        let next = path.with_extension("next");
+        std::fs::write(&next, "41").unwrap();
+        std::fs::rename(&next, &path).unwrap();
+        let deadline = Instant::now() + TIMEOUT;
+        let mut value = exec.sample().unwrap();
+        while value != 41.0 && Instant::now() < deadline {
+            sleep(WAIT_STEP);
+            value = exec.sample().unwrap();
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(value, 41.0);
+    }
+}
@@ -0,0 +1,274 @@
+//! Expressions combining the readings of other metrics, e.g. `max(cpu_temp, loop_temp)` or `cpu_power / 105 * 100`.
+//!
+//! They have numbers, the names of metrics, `+ - * /`, parentheses and the `min`, `max`, `avg` and `abs` functions.
+//! `temp`, `usage` and `power` are the CPU metrics, like in the rest of the settings.
+//!
+//! They are kept to expressions rather than scripts in an embedded language: an expression always ends, in a time
+//! bounded by its length, and cannot reach files, processes or the network, while it is evaluated at every update by
+//! a program that starts as root. What an expression cannot compute is left to a command metric, which runs in the
+//! background with a timeout.
+
+use super::{metric_name, Reading};
+use crate::error::Error;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    /// Reading of a metric
+    Metric(String),
+    Neg(Box<Expr>),
+    Binary(char, Box<Expr>, Box<Expr>),
+    /// Function and its arguments
+    Call(Function, Vec<Expr>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Function {
+    Min,
+    Max,
+    Avg,
+    Abs,
+}
+
+impl Expr {
+    /// Parses an expression, the error tells what is wrong and where.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            chars: text.chars().collect(),
+            pos: 0,
+        };
+        let expr = parser.sum()?;
+        parser.skip_spaces();
+        match parser.peek() {
+            Some(c) => Err(parser.error(&format!("unexpected \"{c}\""))),
+            None => Ok(expr),
+        }
+    }
+
+    /// Returns the names of the metrics the expression reads.
+    pub fn metrics(&self) -> Vec<&str> {
+        match self {
+            Expr::Number(_) => Vec::new(),
+            Expr::Metric(name) => vec![name],
+            Expr::Neg(expr) => expr.metrics(),
+            Expr::Binary(_, left, right) => [left.metrics(), right.metrics()].concat(),
+            Expr::Call(_, args) => args.iter().flat_map(Expr::metrics).collect(),
+        }
+    }
+
+    /// Calculates the value from the readings, a metric without a reading makes it unavailable.
+    pub fn eval(&self, readings: &[Reading]) -> Result<f64, Error> {
+        Ok(match self {
+            Expr::Number(value) => *value,
+            Expr::Metric(name) => readings
+                .iter()
+                .find(|reading| reading.name == *name)
+                .map(|reading| reading.value)
+                .ok_or_else(|| Error::Unavailable { metric: name.clone() })?,
+            Expr::Neg(expr) => -expr.eval(readings)?,
+            Expr::Binary(op, left, right) => {
+                let (left, right) = (left.eval(readings)?, right.eval(readings)?);
+                match op {
+                    '+' => left + right,
+                    '-' => left - right,
+                    '*' => left * right,
+                    _ => left / right,
+                }
+            }
+            Expr::Call(function, args) => {
+                let values = args
+                    .iter()
+                    .map(|arg| arg.eval(readings))
+                    .collect::<Result<Vec<_>, _>>()?;
+                match function {
+                    Function::Min => values.into_iter().fold(f64::INFINITY, f64::min),
+                    Function::Max => values.into_iter().fold(f64::NEG_INFINITY, f64::max),
+                    Function::Avg => values.iter().sum::<f64>() / values.len() as f64,
+                    Function::Abs => values[0].abs(),
+                }
+            }
+        })
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    /// Skips the spaces, then takes `c` if it is the next character.
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_spaces();
+        let found = self.peek() == Some(c);
+        if found {
+            self.pos += 1;
+        }
+
+        found
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("{message} at column {}", self.pos + 1)
+    }
+
+    /// Terms added or subtracted.
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut expr = self.product()?;
+        loop {
+            let op = match () {
+                _ if self.eat('+') => '+',
+                _ if self.eat('-') => '-',
+                _ => return Ok(expr),
+            };
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.product()?));
+        }
+    }
+
+    /// Factors multiplied or divided.
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut expr = self.factor()?;
+        loop {
+            let op = match () {
+                _ if self.eat('*') => '*',
+                _ if self.eat('/') => '/',
+                _ => return Ok(expr),
+            };
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.factor()?));
+        }
+    }
+
+    /// Number, metric, function call, negation or expression in parentheses.
+    fn factor(&mut self) -> Result<Expr, String> {
+        if self.eat('-') {
+            return Ok(Expr::Neg(Box::new(self.factor()?)));
+        }
+        if self.eat('(') {
+            let expr = self.sum()?;
+            return match self.eat(')') {
+                true => Ok(expr),
+                false => Err(self.error("missing \")\"")),
+            };
+        }
+
+        let start = self.pos;
+        match self.peek() {
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '.') {
+                    self.pos += 1;
+                }
+                let number: String = self.chars[start..self.pos].iter().collect();
+                number
+                    .parse()
+                    .map(Expr::Number)
+                    .map_err(|_| self.error(&format!("invalid number \"{number}\"")))
+            }
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_') {
+                    self.pos += 1;
+                }
+                let name: String = self.chars[start..self.pos].iter().collect();
+                if !self.eat('(') {
+                    return Ok(Expr::Metric(metric_name(&name)));
+                }
+                self.call(&name)
+            }
+            Some(c) => Err(self.error(&format!("unexpected \"{c}\""))),
+            None => Err(self.error("missing value")),
+        }
+    }
+
+    /// Arguments of a function, after the opening parenthesis.
+    fn call(&mut self, name: &str) -> Result<Expr, String> {
+        let function = match name {
+            "min" => Function::Min,
+            "max" => Function::Max,
+            "avg" => Function::Avg,
+            "abs" => Function::Abs,
+            _ => return Err(self.error(&format!("unknown function \"{name}\", expected min, max, avg or abs"))),
+        };
+        let mut args = vec![self.sum()?];
+        while self.eat(',') {
+            args.push(self.sum()?);
+        }
+        if !self.eat(')') {
+            return Err(self.error("missing \")\""));
+        }
+        if function == Function::Abs && args.len() != 1 {
+            return Err(self.error("abs takes a single value"));
+        }
+
+        Ok(Expr::Call(function, args))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::monitor::Unit;
+
+    fn eval(text: &str) -> Result<f64, Error> {
+        let readings = [("cpu_temp", 45.0), ("loop_temp", 38.0), ("gpu_power", 0.0)].map(|(name, value)| Reading {
+            name: name.to_string(),
+            unit: Unit::Celsius,
+            value,
+        });
+        Expr::parse(text).unwrap().eval(&readings)
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("2 + 3 * 4").unwrap(), 14.0);
+        assert_eq!(eval("(2 + 3) * 4").unwrap(), 20.0);
+        assert_eq!(eval("10 - 4 - 3").unwrap(), 3.0);
+        assert_eq!(eval("24 / 4 / 2").unwrap(), 3.0);
+        assert_eq!(eval("-2 * -3 - -1").unwrap(), 7.0);
+        assert_eq!(eval("-(temp - loop_temp) * 2").unwrap(), -14.0);
+        assert_eq!(eval("max(temp, loop_temp + 10) - min(1, .5)").unwrap(), 47.5);
+        assert_eq!(eval("avg(cpu_temp, 2 * 25, 40) + abs(-1)").unwrap(), 46.0);
+    }
+
+    #[test]
+    fn division_by_zero() {
+        // The metrics leave the results that are not finite out, see `Metrics::sample`
+        assert_eq!(eval("temp / gpu_power").unwrap(), f64::INFINITY);
+        assert_eq!(eval("-temp / 0").unwrap(), f64::NEG_INFINITY);
+        assert!(eval("gpu_power / 0").unwrap().is_nan());
+    }
+
+    #[test]
+    fn unknown_identifiers() {
+        assert!(matches!(
+            eval("temp + gpu_temp"),
+            Err(Error::Unavailable { metric }) if metric == "gpu_temp"
+        ));
+        assert_eq!(
+            Expr::parse("sqrt(cpu_temp)").unwrap_err(),
+            "unknown function \"sqrt\", expected min, max, avg or abs at column 6"
+        );
+        let expr = Expr::parse("max(temp, loop_temp) / usage").unwrap();
+        assert_eq!(expr.metrics(), ["cpu_temp", "loop_temp", "cpu_usage"]);
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!(Expr::parse("max(cpu_temp,").unwrap_err(), "missing value at column 14");
+        assert_eq!(Expr::parse("cpu_temp cpu_usage").unwrap_err(), "unexpected \"c\" at column 10");
+        assert_eq!(Expr::parse("(1 + 2").unwrap_err(), "missing \")\" at column 7");
+        assert_eq!(Expr::parse("min(1, 2").unwrap_err(), "missing \")\" at column 9");
+        assert_eq!(Expr::parse("abs(1, 2)").unwrap_err(), "abs takes a single value at column 10");
+        assert_eq!(Expr::parse("1.2.3").unwrap_err(), "invalid number \"1.2.3\" at column 6");
+        assert_eq!(Expr::parse("2 % 3").unwrap_err(), "unexpected \"%\" at column 3");
+        assert_eq!(Expr::parse("").unwrap_err(), "missing value at column 1");
+    }
+}
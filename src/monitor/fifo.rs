@@ -0,0 +1,160 @@
+//! Metrics pushed by another program, one number per line, into a named pipe or the standard input.
+//!
+//! ```bash
+//! echo 61.5 > /run/deepcool/metric
+//! ```
+
+use super::{MetricProvider, Unit};
+use crate::{error::Error, log::warning};
+use std::{
+    ffi::CString,
+    fs::{create_dir_all, metadata, set_permissions, OpenOptions, Permissions},
+    io::{self, BufRead, BufReader, Read},
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{FileTypeExt, PermissionsExt},
+    },
+    path::Path,
+    sync::{Arc, Mutex},
+    thread,
+};
+
+/// Last line written, `None` until the first one
+type Latest = Arc<Mutex<Option<Result<f64, String>>>>;
+
+/// Metric showing the last value written to a pipe.
+pub struct Fifo {
+    name: String,
+    unit: Unit,
+    latest: Latest,
+}
+
+impl Fifo {
+    /// Opens the named pipe at `path`, it is created if it does not exist, and starts reading it on a separate thread.
+    ///
+    /// The pipe is also opened for writing, so it stays open once a writer closes it and the next writers can come and
+    /// go. It is kept open, it can still be read after the privileges are dropped.
+    pub fn open(name: &str, unit: Unit, path: &Path) -> io::Result<Self> {
+        match metadata(path) {
+            Ok(file) if file.file_type().is_fifo() => (),
+            Ok(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} is not a named pipe", path.display()),
+                ))
+            }
+            Err(_) => {
+                if let Some(dir) = path.parent() {
+                    create_dir_all(dir)?;
+                }
+                let c_path = CString::new(path.as_os_str().as_bytes())?;
+                // Writable by the group, e.g. for a service running as another user of the group
+                if unsafe { libc::mkfifo(c_path.as_ptr(), 0o660) } != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                // Not restricted by the umask
+                set_permissions(path, Permissions::from_mode(0o660))?;
+            }
+        }
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+
+        Ok(Self::spawn(name, unit, file))
+    }
+
+    /// Starts reading the standard input on a separate thread, the last value is kept once it is closed.
+    pub fn stdin(name: &str, unit: Unit) -> Self {
+        Self::spawn(name, unit, io::stdin())
+    }
+
+    fn spawn(name: &str, unit: Unit, input: impl Read + Send + 'static) -> Self {
+        let latest: Latest = Arc::default();
+        let shared = latest.clone();
+        let metric = name.to_string();
+        thread::spawn(move || {
+            for line in BufReader::new(input).lines() {
+                match line {
+                    Ok(line) if line.trim().is_empty() => (),
+                    Ok(line) => *shared.lock().unwrap() = Some(parse(&line)),
+                    Err(err) => {
+                        warning!(metric = metric; "Failed to read the {metric} metric: {err}");
+                        break;
+                    }
+                }
+            }
+        });
+
+        Fifo {
+            name: name.to_string(),
+            unit,
+            latest,
+        }
+    }
+}
+
+/// Parses a line as a finite number, or returns it to tell what was written.
+fn parse(line: &str) -> Result<f64, String> {
+    let value = line.trim();
+    value
+        .parse::<f64>()
+        .ok()
+        .filter(|value| value.is_finite())
+        .ok_or_else(|| value.to_string())
+}
+
+impl MetricProvider for Fifo {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn unit(&self) -> Unit {
+        self.unit
+    }
+
+    fn sample(&mut self) -> Result<f64, Error> {
+        match self.latest.lock().unwrap().clone() {
+            Some(Ok(value)) => Ok(value),
+            Some(Err(value)) => Err(Error::Parse {
+                sensor: "value written to the pipe",
+                value,
+            }),
+            None => Err(Error::Unavailable {
+                metric: self.name.clone(),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        thread::sleep,
+        time::{Duration, Instant},
+    };
+
+    #[test]
+    fn finite_numbers_only() {
+        assert_eq!(parse(" 61.5\r"), Ok(61.5));
+        assert_eq!(parse("-3"), Ok(-3.0));
+        for line in ["nan", "NaN", "inf", "-Infinity", "1e999", "61.5 C"] {
+            assert_eq!(parse(line), Err(line.to_string()));
+        }
+    }
+
+    #[test]
+    fn keeps_the_last_line() {
+        let mut fifo = Fifo::spawn("loop_temp", Unit::Celsius, &b"40\n\n41\ninf\n"[..]);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while matches!(fifo.sample(), Err(Error::Unavailable { .. }) | Ok(_)) && Instant::now() < deadline {
+            sleep(Duration::from_millis(10));
+        }
+        assert!(matches!(fifo.sample(), Err(Error::Parse { value, .. }) if value == "inf"));
+
+        let mut fifo = Fifo::spawn("loop_temp", Unit::Celsius, &b"40\n\n41\n"[..]);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while fifo.sample().ok() != Some(41.0) && Instant::now() < deadline {
+            sleep(Duration::from_millis(10));
+        }
+        assert_eq!(fifo.sample().unwrap(), 41.0);
+    }
+}
@@ -0,0 +1,123 @@
+//! Cards whose driver registers a hwmon chip, `amdgpu` for AMD, `xe` and `i915` for Intel.
+//!
+//! The chip of a card has one input per sensor, `temp1_input`, `temp2_input`, ..., in millidegrees. RDNA cards label
+//! them `edge`, `junction` and `mem`, older ones only have the edge temperature, sometimes without a label. `xe` labels
+//! the temperature of the whole GPU `pkg` and the one of the memory `vram`, `i915` has no temperature on most kernels
+//! and the cards are then skipped.
+//!
+//! The utilization is read from `gpu_busy_percent` of the PCI device of the chip and the power from `power1_average`,
+//! or `power1_input` on the newer kernels, in microwatts. Only `amdgpu` has them.
+
+use super::{
+    super::{
+        hwmon::{chips, temp_input, Input},
+        Unit,
+    },
+    Card, PciAddress, TempSensor,
+};
+use std::{
+    fs::canonicalize,
+    path::{Path, PathBuf},
+};
+
+/// Drivers with the labels of their GPU temperatures, edge, junction and memory, in the order they are looked up
+const TEMP_INPUTS: [(&str, [Option<&str>; 3]); 3] = [
+    ("amdgpu", [Some("edge"), Some("junction"), Some("mem")]),
+    ("xe", [Some("pkg"), None, Some("vram")]),
+    ("i915", [Some("pkg"), None, None]),
+];
+
+/// Returns the first temperature input in the `column` of `TEMP_INPUTS`, the edge one can be an input without a label.
+fn find_temp(column: usize, card: Option<Card>) -> Option<PathBuf> {
+    TEMP_INPUTS
+        .iter()
+        .filter_map(|(driver, labels)| Some((driver, labels[column]?)))
+        .find_map(|(driver, label)| {
+            cards(driver, card)
+                .iter()
+                .find_map(|chip| temp_input(chip, label, column == 0))
+        })
+}
+
+/// Returns the chips of `driver` that can be the selected card, all of them when no card is selected.
+fn cards(driver: &str, card: Option<Card>) -> Vec<PathBuf> {
+    let chips = chips(driver);
+    match card {
+        None => chips,
+        Some(Card::Index(index)) => chips.into_iter().nth(index as usize).into_iter().collect(),
+        Some(Card::Pci(address)) => chips
+            .into_iter()
+            .filter(|chip| pci_address(chip) == Some(address))
+            .collect(),
+    }
+}
+
+/// Returns the address of the PCI device of a chip, its `device` link points to the folder of the device.
+fn pci_address(chip: &Path) -> Option<PciAddress> {
+    PciAddress::parse(canonicalize(chip.join("device")).ok()?.file_name()?.to_str()?)
+}
+
+/// Returns the temperature of the selected or the first AMD or Intel card that has the sensor, the inputs without
+/// a label are taken for the edge temperature.
+pub fn temp(card: Option<Card>, sensor: TempSensor) -> Result<Input, String> {
+    let column = match sensor {
+        TempSensor::Edge => 0,
+        TempSensor::Junction => 1,
+    };
+    let path = find_temp(column, card).ok_or(format!("no AMD or Intel card with a {} temperature", sensor.name()))?;
+
+    Ok(Input {
+        name: "gpu_temp",
+        unit: Unit::Celsius,
+        sensor: "GPU temperature",
+        path,
+        scale: 1000.0,
+    })
+}
+
+/// Returns the memory temperature of the selected or the first AMD or Intel card that has one.
+pub fn mem_temp(card: Option<Card>) -> Result<Input, String> {
+    let path = find_temp(2, card).ok_or("no AMD or Intel card with a memory temperature")?;
+
+    Ok(Input {
+        name: "gpu_mem_temp",
+        unit: Unit::Celsius,
+        sensor: "GPU memory temperature",
+        path,
+        scale: 1000.0,
+    })
+}
+
+/// Returns the utilization of the selected or the first AMD card.
+pub fn usage(card: Option<Card>) -> Result<Input, String> {
+    let path = cards("amdgpu", card)
+        .into_iter()
+        .map(|chip| chip.join("device/gpu_busy_percent"))
+        .find(|path| path.exists())
+        .ok_or("no AMD card with a utilization")?;
+
+    Ok(Input {
+        name: "gpu_usage",
+        unit: Unit::Percent,
+        sensor: "GPU utilization",
+        path,
+        scale: 1.0,
+    })
+}
+
+/// Returns the power draw of the selected or the first AMD card.
+pub fn power(card: Option<Card>) -> Result<Input, String> {
+    let path = cards("amdgpu", card)
+        .into_iter()
+        .flat_map(|chip| [chip.join("power1_average"), chip.join("power1_input")])
+        .find(|path| path.exists())
+        .ok_or("no AMD card with a power sensor")?;
+
+    Ok(Input {
+        name: "gpu_power",
+        unit: Unit::Watts,
+        sensor: "GPU power",
+        path,
+        scale: 1_000_000.0,
+    })
+}
@@ -0,0 +1,162 @@
+//! Readings of the graphics card, from the first driver that has them.
+
+pub mod hwmon;
+pub mod nvml;
+pub mod rocm;
+pub mod smi;
+
+use super::{MetricProvider, Unit};
+use std::fmt;
+
+/// Opens a metric of the cards of one driver
+type Driver = fn(&Settings) -> Result<Box<dyn MetricProvider>, String>;
+
+/// Choices of the card and the sensors the GPU metrics are read from.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Settings {
+    /// Card of the metrics, the first one that has each metric by default
+    pub card: Option<Card>,
+    /// Sensor of `gpu_temp`
+    pub temp: TempSensor,
+}
+
+/// Card selected with `--gpu-index` or `--gpu-pci`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Card {
+    /// Position among the cards of the driver, in the order of `nvidia-smi`, `rocm-smi` or of the hwmon chips
+    Index(u32),
+    Pci(PciAddress),
+}
+
+impl fmt::Display for Card {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Card::Index(index) => write!(f, "{index}"),
+            Card::Pci(address) => write!(f, "{address}"),
+        }
+    }
+}
+
+/// Address of a PCI device, e.g. `0000:03:00.0`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PciAddress {
+    pub domain: u32,
+    pub bus: u8,
+    pub device: u8,
+    pub function: u8,
+}
+
+impl PciAddress {
+    /// Parses an address in hexadecimal, `domain:bus:device.function`, the domain can be left out.
+    pub fn parse(text: &str) -> Option<Self> {
+        let (rest, function) = text.rsplit_once('.')?;
+        let mut parts: Vec<&str> = rest.split(':').collect();
+        if parts.len() == 2 {
+            parts.insert(0, "0");
+        }
+        let [domain, bus, device] = parts[..] else {
+            return None;
+        };
+        let address = PciAddress {
+            domain: u32::from_str_radix(domain, 16).ok()?,
+            bus: u8::from_str_radix(bus, 16).ok()?,
+            device: u8::from_str_radix(device, 16).ok()?,
+            function: u8::from_str_radix(function, 16).ok()?,
+        };
+        (address.device < 32 && address.function < 8).then_some(address)
+    }
+}
+
+impl fmt::Display for PciAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:04x}:{:02x}:{:02x}.{:x}",
+            self.domain, self.bus, self.device, self.function
+        )
+    }
+}
+
+/// Temperature sensor of a card, the AMD ones have several.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TempSensor {
+    /// Edge of the die, the only sensor of most cards
+    #[default]
+    Edge,
+    /// Hottest spot of the die, the one the card throttles on
+    Junction,
+}
+
+impl TempSensor {
+    /// Parses the name of a sensor, `edge` or `junction` (`hotspot`).
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "edge" => Some(TempSensor::Edge),
+            "junction" | "hotspot" => Some(TempSensor::Junction),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            TempSensor::Edge => "edge",
+            TempSensor::Junction => "junction",
+        }
+    }
+}
+
+/// Names and units of the GPU metrics
+pub const GPU_METRICS: [(&str, Unit); 4] = [
+    ("gpu_temp", Unit::Celsius),
+    ("gpu_mem_temp", Unit::Celsius),
+    ("gpu_usage", Unit::Percent),
+    ("gpu_power", Unit::Watts),
+];
+
+/// Returns the provider of a GPU metric, or why no card has it.
+///
+/// The drivers are tried in turn, NVIDIA first, through NVML or `nvidia-smi`, then the hwmon chips and ROCm SMI. A
+/// driver is skipped when its first reading fails, e.g. when the card does not have the sensor, the error lists why
+/// each of them failed.
+pub fn provider(name: &str, settings: &Settings) -> Result<Box<dyn MetricProvider>, String> {
+    let drivers: &[Driver] = match name {
+        "gpu_temp" => &[
+            |settings| Ok(Box::new(nvml::Sensor::temp(settings.card, settings.temp)?)),
+            |settings| Ok(Box::new(smi::Sensor::temp(settings.card, settings.temp)?)),
+            |settings| Ok(Box::new(hwmon::temp(settings.card, settings.temp)?)),
+            |settings| Ok(Box::new(rocm::Sensor::temp(settings.card, settings.temp)?)),
+        ],
+        "gpu_mem_temp" => &[
+            |settings| Ok(Box::new(nvml::Sensor::mem_temp(settings.card)?)),
+            |settings| Ok(Box::new(smi::Sensor::mem_temp(settings.card)?)),
+            |settings| Ok(Box::new(hwmon::mem_temp(settings.card)?)),
+            |settings| Ok(Box::new(rocm::Sensor::mem_temp(settings.card)?)),
+        ],
+        "gpu_usage" => &[
+            |settings| Ok(Box::new(nvml::Sensor::usage(settings.card)?)),
+            |settings| Ok(Box::new(smi::Sensor::usage(settings.card)?)),
+            |settings| Ok(Box::new(hwmon::usage(settings.card)?)),
+            |settings| Ok(Box::new(rocm::Sensor::usage(settings.card)?)),
+        ],
+        "gpu_power" => &[
+            |settings| Ok(Box::new(nvml::Sensor::power(settings.card)?)),
+            |settings| Ok(Box::new(smi::Sensor::power(settings.card)?)),
+            |settings| Ok(Box::new(hwmon::power(settings.card)?)),
+            |settings| Ok(Box::new(rocm::Sensor::power(settings.card)?)),
+        ],
+        _ => return Err(format!("unknown GPU metric \"{name}\"")),
+    };
+
+    let mut errors = Vec::new();
+    for driver in drivers {
+        match driver(settings) {
+            Ok(mut provider) => match provider.sample() {
+                Ok(_) => return Ok(provider),
+                Err(err) => errors.push(err.to_string()),
+            },
+            Err(err) => errors.push(err),
+        }
+    }
+
+    Err(errors.join("; "))
+}
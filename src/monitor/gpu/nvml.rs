@@ -0,0 +1,272 @@
+//! NVIDIA cards, read through the NVML library of the driver.
+//!
+//! The library is loaded at runtime, so the program runs on systems without the NVIDIA driver, the GPU metrics are
+//! then unavailable.
+
+use super::{
+    super::{MetricProvider, Unit},
+    Card, TempSensor,
+};
+use crate::error::Error;
+use std::{
+    ffi::{c_char, c_int, c_uint, c_void, CStr},
+    io,
+    mem::transmute,
+    sync::OnceLock,
+};
+
+/// Library of the driver, without the development symlink
+const LIBRARY: &CStr = c"libnvidia-ml.so.1";
+
+const SUCCESS: c_int = 0;
+/// Sensor of `nvmlDeviceGetTemperature`, the GPU die
+const TEMPERATURE_GPU: c_int = 0;
+/// Field of `nvmlDeviceGetFieldValues`, the memory temperature, only on the data center cards
+const FI_DEV_MEMORY_TEMP: c_uint = 82;
+
+/// Handle of a device, owned by the library
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+struct Device(*mut c_void);
+
+// The handles stay valid as long as the library is loaded, which is until the program exits, and NVML is thread safe
+unsafe impl Send for Device {}
+
+type Init = unsafe extern "C" fn() -> c_int;
+type ErrorString = unsafe extern "C" fn(c_int) -> *const c_char;
+type HandleByIndex = unsafe extern "C" fn(c_uint, *mut Device) -> c_int;
+type HandleByPciBusId = unsafe extern "C" fn(*const c_char, *mut Device) -> c_int;
+type Temperature = unsafe extern "C" fn(Device, c_int, *mut c_uint) -> c_int;
+type PowerUsage = unsafe extern "C" fn(Device, *mut c_uint) -> c_int;
+type FieldValues = unsafe extern "C" fn(Device, c_int, *mut FieldValue) -> c_int;
+type UtilizationRates = unsafe extern "C" fn(Device, *mut Utilization) -> c_int;
+
+/// Percentages of the last sample period the GPU and the memory were busy, `nvmlUtilization_t`
+#[derive(Default)]
+#[repr(C)]
+struct Utilization {
+    gpu: c_uint,
+    memory: c_uint,
+}
+
+/// Value of a field, `nvmlFieldValue_t`
+#[derive(Default)]
+#[repr(C)]
+struct FieldValue {
+    field_id: c_uint,
+    scope_id: c_uint,
+    timestamp: i64,
+    latency_usec: i64,
+    /// Type of `value`, `nvmlValueType_t`
+    value_type: c_int,
+    nvml_return: c_int,
+    /// `nvmlValue_t` union, read according to `value_type`
+    value: [u8; 8],
+}
+
+impl FieldValue {
+    /// Returns the value, whatever its type.
+    fn get(&self) -> f64 {
+        let bytes = self.value;
+        let [a, b, c, d, ..] = bytes;
+        match self.value_type {
+            0 => f64::from_ne_bytes(bytes),
+            1 => u32::from_ne_bytes([a, b, c, d]) as f64,
+            2 | 3 => u64::from_ne_bytes(bytes) as f64,
+            4 => i64::from_ne_bytes(bytes) as f64,
+            5 => i32::from_ne_bytes([a, b, c, d]) as f64,
+            _ => u16::from_ne_bytes([a, b]) as f64,
+        }
+    }
+}
+
+/// Functions of the library.
+struct Nvml {
+    error_string: ErrorString,
+    handle_by_index: HandleByIndex,
+    handle_by_pci_bus_id: HandleByPciBusId,
+    temperature: Temperature,
+    utilization_rates: UtilizationRates,
+    power_usage: PowerUsage,
+    field_values: FieldValues,
+}
+
+// Function pointers only
+unsafe impl Send for Nvml {}
+unsafe impl Sync for Nvml {}
+
+impl Nvml {
+    /// Returns the library, loaded and initialized on the first call.
+    fn get() -> Result<&'static Nvml, String> {
+        static NVML: OnceLock<Result<Nvml, String>> = OnceLock::new();
+        NVML.get_or_init(|| unsafe { Self::load() })
+            .as_ref()
+            .map_err(Clone::clone)
+    }
+
+    unsafe fn load() -> Result<Nvml, String> {
+        let library = libc::dlopen(LIBRARY.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL);
+        if library.is_null() {
+            return Err(format!(
+                "{} not found, the NVIDIA driver is not installed",
+                LIBRARY.to_string_lossy()
+            ));
+        }
+        let symbol = |name: &CStr| {
+            let symbol = libc::dlsym(library, name.as_ptr());
+            match symbol.is_null() {
+                true => Err(format!("{} has no {}", LIBRARY.to_string_lossy(), name.to_string_lossy())),
+                false => Ok(symbol),
+            }
+        };
+
+        let init = transmute::<*mut c_void, Init>(symbol(c"nvmlInit_v2")?);
+        let nvml = Nvml {
+            error_string: transmute::<*mut c_void, ErrorString>(symbol(c"nvmlErrorString")?),
+            handle_by_index: transmute::<*mut c_void, HandleByIndex>(symbol(c"nvmlDeviceGetHandleByIndex_v2")?),
+            handle_by_pci_bus_id: transmute::<*mut c_void, HandleByPciBusId>(symbol(
+                c"nvmlDeviceGetHandleByPciBusId_v2",
+            )?),
+            temperature: transmute::<*mut c_void, Temperature>(symbol(c"nvmlDeviceGetTemperature")?),
+            utilization_rates: transmute::<*mut c_void, UtilizationRates>(symbol(c"nvmlDeviceGetUtilizationRates")?),
+            power_usage: transmute::<*mut c_void, PowerUsage>(symbol(c"nvmlDeviceGetPowerUsage")?),
+            field_values: transmute::<*mut c_void, FieldValues>(symbol(c"nvmlDeviceGetFieldValues")?),
+        };
+        match init() {
+            SUCCESS => Ok(nvml),
+            code => Err(format!("failed to initialize NVML: {}", nvml.describe(code))),
+        }
+    }
+
+    /// Returns the message of an error code.
+    fn describe(&self, code: c_int) -> String {
+        let message = unsafe { (self.error_string)(code) };
+        match message.is_null() {
+            true => format!("error {code}"),
+            false => unsafe { CStr::from_ptr(message) }.to_string_lossy().into_owned(),
+        }
+    }
+
+    /// Turns an error code into an error reading `sensor`.
+    fn check(&self, sensor: &'static str, code: c_int) -> Result<(), Error> {
+        match code {
+            SUCCESS => Ok(()),
+            code => Err(Error::Read {
+                sensor,
+                source: io::Error::other(self.describe(code)),
+            }),
+        }
+    }
+}
+
+/// Reading of an NVIDIA card.
+#[derive(Clone, Copy)]
+enum Kind {
+    Temp,
+    MemTemp,
+    Usage,
+    Power,
+}
+
+/// Metric of an NVIDIA card.
+pub struct Sensor {
+    nvml: &'static Nvml,
+    name: &'static str,
+    unit: Unit,
+    kind: Kind,
+    device: Device,
+}
+
+impl Sensor {
+    /// Returns the temperature of a card, the first one by default. NVML only has the edge one.
+    pub fn temp(card: Option<Card>, sensor: TempSensor) -> Result<Self, String> {
+        let metric = Self::open("gpu_temp", Unit::Celsius, Kind::Temp, card)?;
+        match sensor {
+            TempSensor::Edge => Ok(metric),
+            _ => Err(format!("NVML has no {} temperature", sensor.name())),
+        }
+    }
+
+    /// Returns the memory temperature of a card.
+    pub fn mem_temp(card: Option<Card>) -> Result<Self, String> {
+        Self::open("gpu_mem_temp", Unit::Celsius, Kind::MemTemp, card)
+    }
+
+    /// Returns the utilization of a card.
+    pub fn usage(card: Option<Card>) -> Result<Self, String> {
+        Self::open("gpu_usage", Unit::Percent, Kind::Usage, card)
+    }
+
+    /// Returns the power draw of the whole board of a card.
+    pub fn power(card: Option<Card>) -> Result<Self, String> {
+        Self::open("gpu_power", Unit::Watts, Kind::Power, card)
+    }
+
+    fn open(name: &'static str, unit: Unit, kind: Kind, card: Option<Card>) -> Result<Self, String> {
+        let nvml = Nvml::get()?;
+        let card = card.unwrap_or(Card::Index(0));
+        let mut device = Device(std::ptr::null_mut());
+        let code = match card {
+            Card::Index(index) => unsafe { (nvml.handle_by_index)(index, &mut device) },
+            Card::Pci(address) => {
+                let bus_id = format!("{address}\0");
+                unsafe { (nvml.handle_by_pci_bus_id)(bus_id.as_ptr().cast(), &mut device) }
+            }
+        };
+        match code {
+            SUCCESS => Ok(Sensor {
+                nvml,
+                name,
+                unit,
+                kind,
+                device,
+            }),
+            code => Err(format!("no NVIDIA card {card}: {}", nvml.describe(code))),
+        }
+    }
+}
+
+impl MetricProvider for Sensor {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn unit(&self) -> Unit {
+        self.unit
+    }
+
+    fn sample(&mut self) -> Result<f64, Error> {
+        let nvml = self.nvml;
+        match self.kind {
+            Kind::Temp => {
+                let mut temp = 0;
+                nvml.check("GPU temperature", unsafe {
+                    (nvml.temperature)(self.device, TEMPERATURE_GPU, &mut temp)
+                })?;
+                Ok(temp as f64)
+            }
+            Kind::MemTemp => {
+                const SENSOR: &str = "GPU memory temperature";
+                let mut field = FieldValue {
+                    field_id: FI_DEV_MEMORY_TEMP,
+                    ..FieldValue::default()
+                };
+                nvml.check(SENSOR, unsafe { (nvml.field_values)(self.device, 1, &mut field) })?;
+                nvml.check(SENSOR, field.nvml_return)?;
+                Ok(field.get())
+            }
+            Kind::Usage => {
+                let mut utilization = Utilization::default();
+                nvml.check("GPU utilization", unsafe {
+                    (nvml.utilization_rates)(self.device, &mut utilization)
+                })?;
+                Ok(utilization.gpu as f64)
+            }
+            Kind::Power => {
+                let mut milliwatts = 0;
+                nvml.check("GPU power", unsafe { (nvml.power_usage)(self.device, &mut milliwatts) })?;
+                Ok(milliwatts as f64 / 1000.0)
+            }
+        }
+    }
+}
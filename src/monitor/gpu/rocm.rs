@@ -0,0 +1,225 @@
+//! AMD data center cards, read through the ROCm SMI library, for the Instinct cards whose hwmon chip does not have the
+//! inputs of the consumer ones.
+//!
+//! The library is loaded at runtime like NVML, from the library path or from `/opt/rocm/lib`.
+
+use super::{
+    super::{MetricProvider, Unit},
+    Card, PciAddress, TempSensor,
+};
+use crate::error::Error;
+use std::{
+    ffi::{c_char, c_int, c_void, CStr},
+    io,
+    mem::transmute,
+    ptr,
+    sync::OnceLock,
+};
+
+/// Names the library is looked up by, the versions of the recent ROCm releases first
+const LIBRARIES: [&CStr; 4] = [
+    c"librocm_smi64.so.7",
+    c"librocm_smi64.so.6",
+    c"librocm_smi64.so.5",
+    c"/opt/rocm/lib/librocm_smi64.so",
+];
+
+const SUCCESS: c_int = 0;
+/// Sensors of `rsmi_dev_temp_metric_get`
+const TEMP_TYPE_EDGE: u32 = 0;
+const TEMP_TYPE_JUNCTION: u32 = 1;
+const TEMP_TYPE_MEMORY: u32 = 2;
+/// Current value of a temperature sensor
+const TEMP_CURRENT: c_int = 0;
+
+type Init = unsafe extern "C" fn(u64) -> c_int;
+type StatusString = unsafe extern "C" fn(c_int, *mut *const c_char) -> c_int;
+type NumMonitorDevices = unsafe extern "C" fn(*mut u32) -> c_int;
+type PciId = unsafe extern "C" fn(u32, *mut u64) -> c_int;
+type TempMetric = unsafe extern "C" fn(u32, u32, c_int, *mut i64) -> c_int;
+type BusyPercent = unsafe extern "C" fn(u32, *mut u32) -> c_int;
+type PowerAverage = unsafe extern "C" fn(u32, u32, *mut u64) -> c_int;
+
+/// Functions of the library.
+struct Rocm {
+    status_string: StatusString,
+    num_monitor_devices: NumMonitorDevices,
+    pci_id: PciId,
+    temp_metric: TempMetric,
+    busy_percent: BusyPercent,
+    power_average: PowerAverage,
+}
+
+impl Rocm {
+    /// Returns the library, loaded and initialized on the first call.
+    fn get() -> Result<&'static Rocm, String> {
+        static ROCM: OnceLock<Result<Rocm, String>> = OnceLock::new();
+        ROCM.get_or_init(|| unsafe { Self::load() })
+            .as_ref()
+            .map_err(Clone::clone)
+    }
+
+    unsafe fn load() -> Result<Rocm, String> {
+        let library = LIBRARIES
+            .iter()
+            .map(|name| libc::dlopen(name.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL))
+            .find(|library| !library.is_null())
+            .ok_or("librocm_smi64.so not found, ROCm is not installed")?;
+        let symbol = |name: &CStr| {
+            let symbol = libc::dlsym(library, name.as_ptr());
+            match symbol.is_null() {
+                true => Err(format!("librocm_smi64.so has no {}", name.to_string_lossy())),
+                false => Ok(symbol),
+            }
+        };
+
+        let init = transmute::<*mut c_void, Init>(symbol(c"rsmi_init")?);
+        let rocm = Rocm {
+            status_string: transmute::<*mut c_void, StatusString>(symbol(c"rsmi_status_string")?),
+            num_monitor_devices: transmute::<*mut c_void, NumMonitorDevices>(symbol(c"rsmi_num_monitor_devices")?),
+            pci_id: transmute::<*mut c_void, PciId>(symbol(c"rsmi_dev_pci_id_get")?),
+            temp_metric: transmute::<*mut c_void, TempMetric>(symbol(c"rsmi_dev_temp_metric_get")?),
+            busy_percent: transmute::<*mut c_void, BusyPercent>(symbol(c"rsmi_dev_busy_percent_get")?),
+            power_average: transmute::<*mut c_void, PowerAverage>(symbol(c"rsmi_dev_power_ave_get")?),
+        };
+        match init(0) {
+            SUCCESS => Ok(rocm),
+            code => Err(format!("failed to initialize ROCm SMI: {}", rocm.describe(code))),
+        }
+    }
+
+    /// Returns the message of an error code.
+    fn describe(&self, code: c_int) -> String {
+        let mut message = ptr::null();
+        match unsafe { (self.status_string)(code, &mut message) } {
+            SUCCESS if !message.is_null() => unsafe { CStr::from_ptr(message) }.to_string_lossy().into_owned(),
+            _ => format!("error {code}"),
+        }
+    }
+
+    /// Returns the PCI address of a device, from its `(domain << 32) | (bus << 8) | (device << 3) | function` ID.
+    fn pci_address(&self, device: u32) -> Option<PciAddress> {
+        let mut id = 0;
+        match unsafe { (self.pci_id)(device, &mut id) } {
+            SUCCESS => Some(PciAddress {
+                domain: (id >> 32) as u32,
+                bus: (id >> 8) as u8,
+                device: (id >> 3) as u8 & 0x1f,
+                function: id as u8 & 0x7,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Turns an error code into an error reading `sensor`.
+    fn check(&self, sensor: &'static str, code: c_int) -> Result<(), Error> {
+        match code {
+            SUCCESS => Ok(()),
+            code => Err(Error::Read {
+                sensor,
+                source: io::Error::other(self.describe(code)),
+            }),
+        }
+    }
+}
+
+/// Reading of an AMD card, with the sensor of the temperatures.
+#[derive(Clone, Copy)]
+enum Kind {
+    Temp(u32),
+    Usage,
+    Power,
+}
+
+/// Metric of an AMD card.
+pub struct Sensor {
+    rocm: &'static Rocm,
+    name: &'static str,
+    unit: Unit,
+    kind: Kind,
+    device: u32,
+}
+
+impl Sensor {
+    /// Returns the temperature of a card, the first one by default.
+    pub fn temp(card: Option<Card>, sensor: TempSensor) -> Result<Self, String> {
+        let kind = match sensor {
+            TempSensor::Edge => Kind::Temp(TEMP_TYPE_EDGE),
+            TempSensor::Junction => Kind::Temp(TEMP_TYPE_JUNCTION),
+        };
+        Self::open("gpu_temp", Unit::Celsius, kind, card)
+    }
+
+    /// Returns the memory temperature of a card.
+    pub fn mem_temp(card: Option<Card>) -> Result<Self, String> {
+        Self::open("gpu_mem_temp", Unit::Celsius, Kind::Temp(TEMP_TYPE_MEMORY), card)
+    }
+
+    /// Returns the utilization of a card.
+    pub fn usage(card: Option<Card>) -> Result<Self, String> {
+        Self::open("gpu_usage", Unit::Percent, Kind::Usage, card)
+    }
+
+    /// Returns the power draw of a card.
+    pub fn power(card: Option<Card>) -> Result<Self, String> {
+        Self::open("gpu_power", Unit::Watts, Kind::Power, card)
+    }
+
+    fn open(name: &'static str, unit: Unit, kind: Kind, card: Option<Card>) -> Result<Self, String> {
+        let rocm = Rocm::get()?;
+        let card = card.unwrap_or(Card::Index(0));
+        let mut count = 0;
+        match unsafe { (rocm.num_monitor_devices)(&mut count) } {
+            SUCCESS => (),
+            code => return Err(format!("failed to list the ROCm cards: {}", rocm.describe(code))),
+        }
+        let device = match card {
+            Card::Index(index) => Some(index).filter(|&index| index < count),
+            Card::Pci(address) => (0..count).find(|&device| rocm.pci_address(device) == Some(address)),
+        };
+
+        match device {
+            Some(device) => Ok(Sensor {
+                rocm,
+                name,
+                unit,
+                kind,
+                device,
+            }),
+            None => Err(format!("no ROCm card {card}")),
+        }
+    }
+}
+
+impl MetricProvider for Sensor {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn unit(&self) -> Unit {
+        self.unit
+    }
+
+    fn sample(&mut self) -> Result<f64, Error> {
+        let rocm = self.rocm;
+        match self.kind {
+            Kind::Temp(sensor) => {
+                let mut millidegrees = 0;
+                rocm.check("GPU temperature", unsafe {
+                    (rocm.temp_metric)(self.device, sensor, TEMP_CURRENT, &mut millidegrees)
+                })?;
+                Ok(millidegrees as f64 / 1000.0)
+            }
+            Kind::Usage => {
+                let mut percent = 0;
+                rocm.check("GPU utilization", unsafe { (rocm.busy_percent)(self.device, &mut percent) })?;
+                Ok(percent as f64)
+            }
+            Kind::Power => {
+                let mut microwatts = 0;
+                rocm.check("GPU power", unsafe { (rocm.power_average)(self.device, 0, &mut microwatts) })?;
+                Ok(microwatts as f64 / 1_000_000.0)
+            }
+        }
+    }
+}
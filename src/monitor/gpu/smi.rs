@@ -0,0 +1,86 @@
+//! NVIDIA cards, read from the output of `nvidia-smi` when NVML cannot be loaded, e.g. in a container that only has
+//! the command.
+
+use super::{
+    super::{exec, MetricProvider, Unit},
+    Card, TempSensor,
+};
+use crate::error::Error;
+use std::{env, path::Path, time::Duration};
+
+/// Time `nvidia-smi` may take, it is slow on the first call while the driver wakes the card up
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Metric of an NVIDIA card, a field of `nvidia-smi --query-gpu`.
+pub struct Sensor {
+    name: &'static str,
+    unit: Unit,
+    /// Field of `--query-gpu`, e.g. `temperature.gpu`
+    field: &'static str,
+    /// Index or PCI address given to `-i`
+    card: Card,
+}
+
+impl Sensor {
+    /// Returns the temperature of a card, the first one by default. `nvidia-smi` only has the edge one.
+    pub fn temp(card: Option<Card>, sensor: TempSensor) -> Result<Self, String> {
+        match sensor {
+            TempSensor::Edge => Self::open("gpu_temp", Unit::Celsius, "temperature.gpu", card),
+            _ => Err(format!("nvidia-smi has no {} temperature", sensor.name())),
+        }
+    }
+
+    /// Returns the memory temperature of a card.
+    pub fn mem_temp(card: Option<Card>) -> Result<Self, String> {
+        Self::open("gpu_mem_temp", Unit::Celsius, "temperature.memory", card)
+    }
+
+    /// Returns the utilization of a card.
+    pub fn usage(card: Option<Card>) -> Result<Self, String> {
+        Self::open("gpu_usage", Unit::Percent, "utilization.gpu", card)
+    }
+
+    /// Returns the power draw of a card.
+    pub fn power(card: Option<Card>) -> Result<Self, String> {
+        Self::open("gpu_power", Unit::Watts, "power.draw", card)
+    }
+
+    fn open(name: &'static str, unit: Unit, field: &'static str, card: Option<Card>) -> Result<Self, String> {
+        let found = env::var_os("PATH")
+            .is_some_and(|path| env::split_paths(&path).any(|dir| Path::new(&dir).join("nvidia-smi").is_file()));
+        match found {
+            true => Ok(Sensor {
+                name,
+                unit,
+                field,
+                card: card.unwrap_or(Card::Index(0)),
+            }),
+            false => Err("nvidia-smi not found".to_string()),
+        }
+    }
+}
+
+impl MetricProvider for Sensor {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn unit(&self) -> Unit {
+        self.unit
+    }
+
+    fn sample(&mut self) -> Result<f64, Error> {
+        const SENSOR: &str = "output of nvidia-smi";
+
+        let command = format!(
+            "nvidia-smi --query-gpu={} --format=csv,noheader,nounits -i {}",
+            self.field, self.card
+        );
+        let output = exec::run(&command, TIMEOUT).map_err(|source| Error::Read { sensor: SENSOR, source })?;
+        // "[N/A]" or "[Not Supported]" when the card does not have the field
+        output.trim().parse().map_err(|_| Error::Parse {
+            sensor: SENSOR,
+            value: output.trim().to_string(),
+        })
+    }
+}
@@ -0,0 +1,82 @@
+//! Sensors of the hwmon chips, `/sys/class/hwmon/hwmon*`, each one registered by the driver of a device.
+
+use super::{MetricProvider, Unit};
+use crate::error::Error;
+use std::{
+    fs::{read_dir, read_to_string},
+    path::{Path, PathBuf},
+};
+
+const HWMON: &str = "/sys/class/hwmon";
+
+/// Returns the hwmon folders of the chips registered by `driver`, in the order of their numbers.
+pub fn chips(driver: &str) -> Vec<PathBuf> {
+    let mut chips: Vec<PathBuf> = read_dir(HWMON)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|chip| read_to_string(chip.join("name")).is_ok_and(|name| name.trim_end() == driver))
+        .collect();
+    // hwmon10 after hwmon9
+    chips.sort_by_key(|chip| {
+        let name = chip.file_name().unwrap_or_default().to_string_lossy();
+        (name.len(), name.into_owned())
+    });
+
+    chips
+}
+
+/// Returns the temperature input labeled `label`, or the first one without a label if `unlabeled` is set.
+pub fn temp_input(chip: &Path, label: &str, unlabeled: bool) -> Option<PathBuf> {
+    let mut first = None;
+    for i in 1.. {
+        let input = chip.join(format!("temp{i}_input"));
+        if !input.exists() {
+            break;
+        }
+        match read_to_string(chip.join(format!("temp{i}_label"))) {
+            Ok(name) if name.trim_end() == label => return Some(input),
+            Ok(_) => (),
+            Err(_) => {
+                first.get_or_insert(input);
+            }
+        }
+    }
+
+    first.filter(|_| unlabeled)
+}
+
+/// Metric read from an input of a hwmon chip.
+pub struct Input {
+    pub name: &'static str,
+    pub unit: Unit,
+    /// Description used in the errors
+    pub sensor: &'static str,
+    pub path: PathBuf,
+    /// Units of the input per unit of the metric, e.g. 1000 for millidegrees
+    pub scale: f64,
+}
+
+impl MetricProvider for Input {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn unit(&self) -> Unit {
+        self.unit
+    }
+
+    fn sample(&mut self) -> Result<f64, Error> {
+        let data = read_to_string(&self.path).map_err(|source| Error::Read {
+            sensor: self.sensor,
+            source,
+        })?;
+        let value: f64 = data.trim_end().parse().map_err(|_| Error::Parse {
+            sensor: self.sensor,
+            value: data.trim_end().to_string(),
+        })?;
+
+        Ok(value / self.scale)
+    }
+}
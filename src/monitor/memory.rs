@@ -0,0 +1,144 @@
+//! Memory of the system, read from `/proc/meminfo`.
+
+use super::{MetricProvider, Unit};
+use crate::error::Error;
+use std::fs::read_to_string;
+
+const MEMINFO: &str = "/proc/meminfo";
+
+/// Names and units of the memory metrics
+pub const MEMORY_METRICS: [(&str, Unit); 2] = [("ram_usage", Unit::Percent), ("swap_usage", Unit::Percent)];
+
+/// Returns the provider of a memory metric, or why it cannot be read, e.g. when there is no swap space.
+pub fn provider(name: &str) -> Result<Box<dyn MetricProvider>, String> {
+    let mut usage = Usage::of(name).ok_or(format!("unknown memory metric \"{name}\""))?;
+    match usage.sample() {
+        Ok(_) => Ok(Box::new(usage)),
+        // No swap space
+        Err(Error::Unavailable { .. }) => Err(format!("{} is 0 kB", usage.total)),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Share of a memory in use, from its total size and the part that is free.
+struct Usage {
+    name: &'static str,
+    /// Description used in the errors
+    sensor: &'static str,
+    /// Keys of `/proc/meminfo`
+    total: &'static str,
+    free: &'static str,
+}
+
+impl Usage {
+    /// Returns the usage of a memory metric.
+    fn of(name: &str) -> Option<Self> {
+        match name {
+            "ram_usage" => Some(Usage {
+                name: "ram_usage",
+                sensor: "memory usage",
+                total: "MemTotal",
+                free: "MemAvailable",
+            }),
+            "swap_usage" => Some(Usage {
+                name: "swap_usage",
+                sensor: "swap usage",
+                total: "SwapTotal",
+                free: "SwapFree",
+            }),
+            _ => None,
+        }
+    }
+
+    /// Calculates the share in use from the contents of `/proc/meminfo`.
+    fn parse(&self, meminfo: &str) -> Result<f64, Error> {
+        let value = |key: &str| -> Result<f64, Error> {
+            let line = meminfo
+                .lines()
+                .find_map(|line| line.strip_prefix(key)?.strip_prefix(':'))
+                .unwrap_or_default();
+            // e.g. "16314580 kB"
+            line.split_whitespace()
+                .next()
+                .and_then(|kilobytes| kilobytes.parse().ok())
+                .ok_or_else(|| Error::Parse {
+                    sensor: self.sensor,
+                    value: format!("{key}:{line}"),
+                })
+        };
+        let total = value(self.total)?;
+        let free = value(self.free)?;
+        if total == 0.0 {
+            return Err(Error::Unavailable {
+                metric: self.name.to_string(),
+            });
+        }
+
+        Ok((total - free) / total * 100.0)
+    }
+}
+
+impl MetricProvider for Usage {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn unit(&self) -> Unit {
+        Unit::Percent
+    }
+
+    fn sample(&mut self) -> Result<f64, Error> {
+        let meminfo = read_to_string(MEMINFO).map_err(|source| Error::Read {
+            sensor: self.sensor,
+            source,
+        })?;
+        self.parse(&meminfo)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "\
+MemTotal:       16000000 kB
+MemFree:         2000000 kB
+MemAvailable:    4000000 kB
+SwapCached:            0 kB
+SwapTotal:             0 kB
+SwapFree:              0 kB
+";
+
+    fn usage(name: &str) -> Usage {
+        Usage::of(name).unwrap()
+    }
+
+    #[test]
+    fn usage_from_meminfo() {
+        assert_eq!(usage("ram_usage").parse(SAMPLE).unwrap(), 75.0);
+        // No swap space
+        assert!(matches!(
+            usage("swap_usage").parse(SAMPLE),
+            Err(Error::Unavailable { metric }) if metric == "swap_usage"
+        ));
+        let swap = SAMPLE.replace("SwapTotal:             0", "SwapTotal:       1000000");
+        let swap = swap.replace("SwapFree:              0", "SwapFree:         250000");
+        assert_eq!(usage("swap_usage").parse(&swap).unwrap(), 75.0);
+    }
+
+    #[test]
+    fn invalid_meminfo() {
+        // "SwapCached" is not taken for "SwapTotal", the key has to be followed by the colon
+        let missing = SAMPLE.replace("MemAvailable", "Available");
+        assert!(matches!(
+            usage("ram_usage").parse(&missing),
+            Err(Error::Parse { value, .. }) if value == "MemAvailable:"
+        ));
+        let garbled = SAMPLE.replace("16000000 kB", "lots");
+        assert!(matches!(
+            usage("ram_usage").parse(&garbled),
+            Err(Error::Parse { value, .. }) if value == "MemTotal:       lots"
+        ));
+        assert!(usage("ram_usage").parse("").is_err());
+    }
+}
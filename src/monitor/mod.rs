@@ -1 +1,253 @@
+//! Readings of the system, shown on the displays and published by the exporters.
+//!
+//! Every source of readings implements `MetricProvider`. The display loops read the CPU themselves, over the
+//! sampling window, and take the other metrics from the `Metrics` they are given: each field of the display shows the
+//! CPU reading of its unit, unless a metric with that unit is selected to replace it. Computed metrics combine the
+//! readings of the others with an expression, see `expr`.
+
 pub mod cpu;
+pub mod drive;
+pub mod exec;
+pub mod expr;
+pub mod fifo;
+pub mod gpu;
+pub mod hwmon;
+pub mod memory;
+pub mod session;
+
+use crate::{error::Error, log::warning};
+use drive::DRIVE_METRICS;
+use expr::Expr;
+use memory::MEMORY_METRICS;
+use std::sync::Mutex;
+
+/// Names of the CPU metrics read by the display loops
+pub const CPU_METRICS: [&str; 3] = ["cpu_temp", "cpu_usage", "cpu_power"];
+
+/// Choices of the hardware the built-in metrics are read from.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Settings {
+    pub gpu: gpu::Settings,
+    /// Drive of `drive_temp`, by name or model, the first one by default
+    pub drive: Option<String>,
+}
+
+/// Returns the names and units of the other built-in metrics, they are only read when they are used.
+pub fn builtin_metrics() -> Vec<(&'static str, Unit)> {
+    [
+        gpu::GPU_METRICS.as_slice(),
+        MEMORY_METRICS.as_slice(),
+        DRIVE_METRICS.as_slice(),
+    ]
+    .concat()
+}
+
+/// Returns the provider of a built-in metric, or why the system does not have it.
+pub fn builtin_provider(name: &str, settings: &Settings) -> Result<Box<dyn MetricProvider>, String> {
+    let is = |metrics: &[(&str, Unit)]| metrics.iter().any(|&(metric, _)| metric == name);
+    match () {
+        _ if is(&MEMORY_METRICS) => memory::provider(name),
+        _ if is(&DRIVE_METRICS) => drive::provider(name, settings.drive.as_deref()),
+        _ => gpu::provider(name, &settings.gpu),
+    }
+}
+
+/// Unit of a metric, it decides the field of the display that can show it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Unit {
+    /// Temperature in ˚C, shown by the temperature field
+    Celsius,
+    /// Share of a resource in use, shown by the utilization field
+    Percent,
+    /// Power consumption, shown by the power field
+    Watts,
+}
+
+impl Unit {
+    /// Parses the symbol of a unit, `C`, `%` or `W`.
+    pub fn parse(symbol: &str) -> Option<Self> {
+        match symbol {
+            "C" | "˚C" | "°C" => Some(Unit::Celsius),
+            "%" => Some(Unit::Percent),
+            "W" => Some(Unit::Watts),
+            _ => None,
+        }
+    }
+
+    pub fn symbol(self) -> &'static str {
+        match self {
+            Unit::Celsius => "C",
+            Unit::Percent => "%",
+            Unit::Watts => "W",
+        }
+    }
+}
+
+/// Source of a metric, e.g. a sensor or a counter.
+pub trait MetricProvider: Send {
+    /// Name of the metric in the settings and the exporters, e.g. `cpu_temp`
+    fn name(&self) -> &str;
+
+    fn unit(&self) -> Unit;
+
+    /// Reads the current value, counters return their average since the previous call.
+    fn sample(&mut self) -> Result<f64, Error>;
+}
+
+/// Value of a metric at an update.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Reading {
+    pub name: String,
+    pub unit: Unit,
+    pub value: f64,
+}
+
+/// Returns the name a metric is known by: dashes become underscores, and the names of the display fields the ones of
+/// the CPU metrics they show by default.
+pub fn metric_name(name: &str) -> String {
+    match name.replace('-', "_").as_str() {
+        "temp" => "cpu_temp".to_string(),
+        "usage" => "cpu_usage".to_string(),
+        "power" => "cpu_power".to_string(),
+        name => name.to_string(),
+    }
+}
+
+/// Metric calculated from the readings of the others.
+pub struct Computed {
+    pub name: String,
+    pub unit: Unit,
+    pub expr: Expr,
+}
+
+/// Metrics read besides the CPU, and the ones the displays show instead of the CPU readings.
+#[derive(Default)]
+pub struct Metrics {
+    /// Providers, with whether their last reading failed
+    providers: Mutex<Vec<(Box<dyn MetricProvider>, bool)>>,
+    /// Computed metrics in the order they are calculated, with whether their last calculation failed
+    computed: Mutex<Vec<(Computed, bool)>>,
+    /// Names of the metrics replacing the CPU reading of their unit
+    shown: Vec<String>,
+}
+
+impl Metrics {
+    pub fn new(providers: Vec<Box<dyn MetricProvider>>, computed: Vec<Computed>, shown: Vec<String>) -> Self {
+        Metrics {
+            providers: Mutex::new(providers.into_iter().map(|provider| (provider, false)).collect()),
+            computed: Mutex::new(computed.into_iter().map(|computed| (computed, false)).collect()),
+            shown,
+        }
+    }
+
+    /// Returns the names and the units of the metrics, the CPU ones first and the computed ones last.
+    pub fn available(&self) -> Vec<(String, Unit)> {
+        let cpu = CPU_METRICS
+            .into_iter()
+            .zip([Unit::Celsius, Unit::Percent, Unit::Watts])
+            .map(|(name, unit)| (name.to_string(), unit));
+        let providers = self.providers.lock().unwrap();
+        let providers = providers
+            .iter()
+            .map(|(provider, _)| (provider.name().to_string(), provider.unit()));
+        let computed = self.computed.lock().unwrap();
+        let computed = computed
+            .iter()
+            .map(|(computed, _)| (computed.name.clone(), computed.unit));
+
+        cpu.chain(providers).chain(computed).collect()
+    }
+
+    /// Reads every provider, then calculates the computed metrics from them and from the readings already taken, e.g.
+    /// of the CPU, the readings are appended to `readings`.
+    ///
+    /// The metrics that fail are left out, with a warning when they start failing.
+    pub fn sample(&self, readings: &mut Vec<Reading>) {
+        for (provider, failing) in self.providers.lock().unwrap().iter_mut() {
+            let value = provider.sample();
+            push(readings, provider.name(), provider.unit(), value, failing);
+        }
+        for (computed, failing) in self.computed.lock().unwrap().iter_mut() {
+            let value = computed.expr.eval(readings).and_then(|value| match value.is_finite() {
+                true => Ok(value),
+                false => Err(Error::Parse {
+                    sensor: "result of the expression",
+                    value: value.to_string(),
+                }),
+            });
+            push(readings, &computed.name, computed.unit, value, failing);
+        }
+    }
+
+    /// Returns the name of the metric shown by the field of `unit`: the selected metric with that unit, or `default`.
+    pub fn shown(&self, unit: Unit, default: &str) -> String {
+        let available = self.available();
+        self.shown
+            .iter()
+            .find(|name| available.contains(&(name.to_string(), unit)))
+            .map_or(default, String::as_str)
+            .to_string()
+    }
+}
+
+/// Appends the reading of a metric, or warns when it starts failing.
+fn push(readings: &mut Vec<Reading>, name: &str, unit: Unit, value: Result<f64, Error>, failing: &mut bool) {
+    match value {
+        Ok(value) => {
+            *failing = false;
+            readings.push(Reading {
+                name: name.to_string(),
+                unit,
+                value,
+            });
+        }
+        Err(err) if !*failing => {
+            match &err {
+                Error::Unavailable { metric } if metric == name => {
+                    warning!(metric = name; "{err}, it is left out for now")
+                }
+                _ => warning!(metric = name; "{err}, the {name} metric is left out for now"),
+            }
+            *failing = true;
+        }
+        Err(_) => (),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn computed_metrics_leave_out_division_by_zero() {
+        let computed = |name: &str, expr| Computed {
+            name: name.to_string(),
+            unit: Unit::Percent,
+            expr: expr::Expr::parse(expr).unwrap(),
+        };
+        let metrics = Metrics::new(
+            Vec::new(),
+            vec![
+                computed("share", "cpu_usage / cpu_power * 100"),
+                computed("ratio", "cpu_power / 50"),
+            ],
+            Vec::new(),
+        );
+        let mut readings = vec![
+            Reading {
+                name: "cpu_usage".to_string(),
+                unit: Unit::Percent,
+                value: 12.0,
+            },
+            Reading {
+                name: "cpu_power".to_string(),
+                unit: Unit::Watts,
+                value: 0.0,
+            },
+        ];
+        metrics.sample(&mut readings);
+
+        let names: Vec<&str> = readings.iter().map(|reading| reading.name.as_str()).collect();
+        assert_eq!(names, ["cpu_usage", "cpu_power", "ratio"]);
+    }
+}
@@ -0,0 +1,28 @@
+//! Reads the idle & lock state of the login sessions from systemd-logind.
+
+use std::process::Command;
+
+/// Returns whether every session is idle or the active session of `seat0` is locked.
+///
+/// Returns `None` if logind cannot be queried, e.g. on systems without systemd.
+pub fn idle_or_locked() -> Option<bool> {
+    if loginctl(&["show", "--property=IdleHint", "--value"])? == "yes" {
+        return Some(true);
+    }
+    let session = loginctl(&["show-seat", "seat0", "--property=ActiveSession", "--value"])?;
+    if session.is_empty() {
+        return Some(false);
+    }
+
+    Some(loginctl(&["show-session", &session, "--property=LockedHint", "--value"])? == "yes")
+}
+
+/// Runs `loginctl` and returns its trimmed output.
+fn loginctl(args: &[&str]) -> Option<String> {
+    let output = Command::new("loginctl").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
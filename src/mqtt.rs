@@ -0,0 +1,375 @@
+//! Publishes the readings to an MQTT broker.
+//!
+//! ```toml
+//! [mqtt]
+//! host = "192.168.1.10"
+//! port = 1883                   # optional
+//! username = "deepcool"         # optional
+//! password = "secret"           # optional
+//! client_id = "deepcool-digital" # optional
+//! topic = "deepcool"            # optional, prefix of the temp, usage, power, alarm & metric topics
+//! interval = 1000               # optional, milliseconds between two updates, up to 60000
+//! discovery = true              # optional, announce the sensors to Home Assistant
+//! discovery_prefix = "homeassistant" # optional
+//! ```
+//!
+//! Every metric is also published to `<topic>/metric/<name>`, e.g. `deepcool/metric/cpu_temp`.
+//!
+//! Only MQTT 3.1.1 with QoS 0 over plain TCP is supported. `<topic>/availability` is set to `online` while
+//! the program is connected, and to `offline` by the broker when the connection is lost.
+
+use crate::{
+    devices::RunOptions,
+    json,
+    log::warning,
+    toml::{get_bool, get_int, get_str, Table},
+};
+use std::{
+    io::{self, Read, Write},
+    net::TcpStream,
+    thread::{self, sleep},
+    time::{Duration, Instant},
+};
+
+/// Time to wait before connecting again after the connection is lost
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// Keep alive interval announced to the broker, in seconds
+const KEEP_ALIVE: u16 = 60;
+/// Time to wait for the replies of the broker when they are read between two updates
+const READ_TIMEOUT: Duration = Duration::from_millis(1);
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PINGREQ: u8 = 0xc0;
+
+/// Keys of the `[mqtt]` table
+pub const KEYS: &[&str] = &[
+    "host",
+    "port",
+    "username",
+    "password",
+    "client_id",
+    "topic",
+    "interval",
+    "discovery",
+    "discovery_prefix",
+];
+
+#[derive(Clone)]
+pub struct Settings {
+    host: String,
+    port: u16,
+    username: Option<String>,
+    password: Option<String>,
+    client_id: String,
+    topic: String,
+    interval: Duration,
+    /// Topic prefix of the Home Assistant discovery messages, if they are enabled
+    discovery: Option<String>,
+}
+
+impl Settings {
+    /// Parses and validates the `[mqtt]` table.
+    pub fn parse(table: &Table) -> Result<Self, String> {
+        Ok(Settings {
+            host: get_str(table, "host")?.ok_or("missing \"mqtt.host\"")?.to_string(),
+            port: get_int(table, "port", u16::MAX as i64)?.unwrap_or(1883) as u16,
+            username: get_str(table, "username")?.map(str::to_string),
+            password: get_str(table, "password")?.map(str::to_string),
+            client_id: get_str(table, "client_id")?.unwrap_or("deepcool-digital").to_string(),
+            topic: get_str(table, "topic")?
+                .unwrap_or("deepcool")
+                .trim_end_matches('/')
+                .to_string(),
+            interval: Duration::from_millis(
+                get_int(table, "interval", KEEP_ALIVE as i64 * 1000)?.unwrap_or(1000) as u64
+            ),
+            discovery: match get_bool(table, "discovery")? {
+                Some(true) => Some(
+                    get_str(table, "discovery_prefix")?
+                        .unwrap_or("homeassistant")
+                        .trim_end_matches('/')
+                        .to_string(),
+                ),
+                _ => None,
+            },
+        })
+    }
+}
+
+/// Publishes the last status on a separate thread, reconnecting when the connection is lost.
+pub fn start(settings: Settings, options: RunOptions) {
+    thread::spawn(move || loop {
+        if let Err(err) = publish_loop(&settings, &options) {
+            warning!("MQTT connection to {}:{} failed: {err}", settings.host, settings.port);
+        }
+        sleep(RECONNECT_DELAY);
+    });
+}
+
+/// Connects to the broker, then publishes the readings until an error occurs.
+fn publish_loop(settings: &Settings, options: &RunOptions) -> io::Result<()> {
+    let mut stream = TcpStream::connect((settings.host.as_str(), settings.port))?;
+    connect(&mut stream, settings)?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    publish(&mut stream, &format!("{}/availability", settings.topic), b"online", true)?;
+
+    // Unit the temperature sensor was last announced with
+    let mut announced = None;
+    // The broker closes the connection if nothing is sent within the keep alive interval
+    let keep_alive = Duration::from_secs(KEEP_ALIVE as u64);
+    let mut sent = Instant::now();
+    loop {
+        let status = *options.status.lock().unwrap();
+        if let Some(status) = status {
+            if let Some(prefix) = &settings.discovery {
+                if announced != Some(status.fahrenheit) {
+                    announce(&mut stream, settings, prefix, status.fahrenheit)?;
+                    announced = Some(status.fahrenheit);
+                }
+            }
+            let unit = if status.fahrenheit { "F" } else { "C" };
+            for (name, value) in [
+                ("temp", status.temp.to_string()),
+                ("unit", unit.to_string()),
+                ("usage", status.usage.to_string()),
+                ("power", status.power.to_string()),
+                ("alarm", if status.overheat { "ON" } else { "OFF" }.to_string()),
+            ] {
+                publish(&mut stream, &format!("{}/{name}", settings.topic), value.as_bytes(), false)?;
+            }
+            let readings = options.readings.lock().unwrap().clone();
+            for reading in readings {
+                let topic = format!("{}/metric/{}", settings.topic, reading.name);
+                publish(&mut stream, &topic, reading.value.to_string().as_bytes(), false)?;
+            }
+            sent = Instant::now();
+        }
+        // Ping while there is nothing to publish, before the next update would be too late
+        if sent.elapsed() + settings.interval >= keep_alive {
+            send(&mut stream, PINGREQ, &[])?;
+            sent = Instant::now();
+        }
+        drain(&mut stream)?;
+        sleep(settings.interval);
+    }
+}
+
+/// Publishes the Home Assistant discovery messages of the sensors.
+fn announce(stream: &mut impl Write, settings: &Settings, prefix: &str, fahrenheit: bool) -> io::Result<()> {
+    let id = json::escape(&settings.client_id);
+    let topic = json::escape(&settings.topic);
+    let device = format!(r#"{{"identifiers":["{id}"],"name":"DeepCool Digital","manufacturer":"DeepCool"}}"#);
+    let temp_unit = if fahrenheit { "°F" } else { "°C" };
+
+    for (component, metric, name, extra) in [
+        (
+            "sensor",
+            "temp",
+            "CPU temperature",
+            format!(r#""device_class":"temperature","state_class":"measurement","unit_of_measurement":"{temp_unit}""#),
+        ),
+        (
+            "sensor",
+            "usage",
+            "CPU utilization",
+            r#""state_class":"measurement","unit_of_measurement":"%","icon":"mdi:cpu-64-bit""#.to_string(),
+        ),
+        (
+            "sensor",
+            "power",
+            "CPU power",
+            r#""device_class":"power","state_class":"measurement","unit_of_measurement":"W""#.to_string(),
+        ),
+        ("binary_sensor", "alarm", "CPU overheat", r#""device_class":"heat""#.to_string()),
+    ] {
+        let config = format!(
+            r#"{{"name":"{name}","unique_id":"{id}_{metric}","state_topic":"{topic}/{metric}","availability_topic":"{topic}/availability",{extra},"device":{device}}}"#
+        );
+        let node = settings
+            .client_id
+            .replace(|c: char| !c.is_ascii_alphanumeric() && c != '-', "_");
+        publish(
+            stream,
+            &format!("{prefix}/{component}/{node}/{metric}/config"),
+            config.as_bytes(),
+            true,
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Sends the CONNECT packet and waits for the broker to accept it.
+fn connect(stream: &mut TcpStream, settings: &Settings) -> io::Result<()> {
+    send(stream, CONNECT, &connect_body(settings))?;
+
+    let mut connack = [0; 4];
+    stream.read_exact(&mut connack)?;
+    match connack {
+        [CONNACK, 2, _, 0] => Ok(()),
+        [CONNACK, 2, _, code] => Err(io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            format!("broker refused the connection (code {code})"),
+        )),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "unexpected reply to CONNECT")),
+    }
+}
+
+/// Builds the variable header and the payload of the CONNECT packet.
+fn connect_body(settings: &Settings) -> Vec<u8> {
+    let mut flags = 0x02 | 0x04 | 0x20; // clean session, retained will message
+    let mut payload = Vec::new();
+    string(&mut payload, &settings.client_id);
+    string(&mut payload, &format!("{}/availability", settings.topic));
+    string(&mut payload, "offline");
+    if let Some(username) = &settings.username {
+        flags |= 0x80;
+        string(&mut payload, username);
+    }
+    if let Some(password) = &settings.password {
+        flags |= 0x40;
+        string(&mut payload, password);
+    }
+
+    let mut body = Vec::new();
+    string(&mut body, "MQTT");
+    body.push(4); // protocol level 3.1.1
+    body.push(flags);
+    body.extend_from_slice(&KEEP_ALIVE.to_be_bytes());
+    body.extend_from_slice(&payload);
+
+    body
+}
+
+/// Reads and drops the replies of the broker, e.g. to PINGREQ, so they do not fill the socket.
+fn drain(stream: &mut impl Read) -> io::Result<()> {
+    let mut buffer = [0; 64];
+    loop {
+        match stream.read(&mut buffer) {
+            Ok(0) => return Err(io::Error::new(io::ErrorKind::ConnectionAborted, "broker closed the connection")),
+            Ok(_) => (),
+            Err(err) if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => return Ok(()),
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Publishes a message with QoS 0.
+fn publish(stream: &mut impl Write, topic: &str, payload: &[u8], retain: bool) -> io::Result<()> {
+    let mut body = Vec::new();
+    string(&mut body, topic);
+    body.extend_from_slice(payload);
+    send(stream, PUBLISH | retain as u8, &body)
+}
+
+/// Sends a packet with the given fixed header byte.
+fn send(stream: &mut impl Write, header: u8, body: &[u8]) -> io::Result<()> {
+    let mut packet = vec![header];
+    remaining_length(&mut packet, body.len());
+    packet.extend_from_slice(body);
+
+    stream.write_all(&packet)
+}
+
+/// Appends the remaining length of a packet, 7 bits per byte with the high bit set on all but the last one.
+fn remaining_length(data: &mut Vec<u8>, mut length: usize) {
+    loop {
+        let byte = (length % 128) as u8;
+        length /= 128;
+        data.push(if length > 0 { byte | 0x80 } else { byte });
+        if length == 0 {
+            break;
+        }
+    }
+}
+
+/// Appends a length-prefixed UTF-8 string.
+fn string(data: &mut Vec<u8>, value: &str) {
+    data.extend_from_slice(&(value.len() as u16).to_be_bytes());
+    data.extend_from_slice(value.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings() -> Settings {
+        Settings {
+            host: "localhost".to_string(),
+            port: 1883,
+            username: None,
+            password: None,
+            client_id: "dc".to_string(),
+            topic: "t".to_string(),
+            interval: Duration::from_secs(1),
+            discovery: None,
+        }
+    }
+
+    fn length(length: usize) -> Vec<u8> {
+        let mut data = Vec::new();
+        remaining_length(&mut data, length);
+        data
+    }
+
+    #[test]
+    fn remaining_lengths() {
+        assert_eq!(length(0), [0x00]);
+        assert_eq!(length(127), [0x7f]);
+        assert_eq!(length(128), [0x80, 0x01]);
+        assert_eq!(length(16_383), [0xff, 0x7f]);
+        assert_eq!(length(16_384), [0x80, 0x80, 0x01]);
+        assert_eq!(length(2_097_151), [0xff, 0xff, 0x7f]);
+        assert_eq!(length(268_435_455), [0xff, 0xff, 0xff, 0x7f]);
+    }
+
+    #[test]
+    fn connect_packet() {
+        let mut packet = Vec::new();
+        send(&mut packet, CONNECT, &connect_body(&settings())).unwrap();
+        #[rustfmt::skip]
+        assert_eq!(packet, [
+            CONNECT, 39,
+            0, 4, b'M', b'Q', b'T', b'T', 4, 0x26, 0, 60,
+            0, 2, b'd', b'c',
+            0, 14, b't', b'/', b'a', b'v', b'a', b'i', b'l', b'a', b'b', b'i', b'l', b'i', b't', b'y',
+            0, 7, b'o', b'f', b'f', b'l', b'i', b'n', b'e',
+        ]);
+
+        let settings = Settings {
+            username: Some("u".to_string()),
+            password: Some("p".to_string()),
+            ..settings()
+        };
+        let body = connect_body(&settings);
+        // Username and password flags
+        assert_eq!(body[7], 0xe6);
+        assert!(body.ends_with(&[0, 1, b'u', 0, 1, b'p']));
+    }
+
+    #[test]
+    fn publish_packet() {
+        let mut packet = Vec::new();
+        publish(&mut packet, "t/temp", b"54", false).unwrap();
+        assert_eq!(packet, [PUBLISH, 10, 0, 6, b't', b'/', b't', b'e', b'm', b'p', b'5', b'4']);
+
+        let mut packet = Vec::new();
+        publish(&mut packet, "t", b"on", true).unwrap();
+        assert_eq!(packet, [PUBLISH | 1, 5, 0, 1, b't', b'o', b'n']);
+
+        // A payload over 127 bytes takes a second length byte
+        let mut packet = Vec::new();
+        publish(&mut packet, "t", &[b'x'; 200], false).unwrap();
+        assert_eq!(packet[..5], [PUBLISH, 0xcb, 0x01, 0, 1]);
+        assert_eq!(packet.len(), 3 + 203);
+    }
+
+    #[test]
+    fn ping_packet() {
+        let mut packet = Vec::new();
+        send(&mut packet, PINGREQ, &[]).unwrap();
+        assert_eq!(packet, [0xc0, 0x00]);
+    }
+}
@@ -0,0 +1,25 @@
+//! Switching to an unprivileged user once the devices are open.
+
+use std::ffi::CString;
+
+/// Switches the process to the given user and its groups, the files already open stay usable.
+pub fn drop_to(user: &str) -> Result<(), String> {
+    let name = CString::new(user).map_err(|_| format!("Invalid user name \"{user}\""))?;
+    let entry = unsafe { libc::getpwnam(name.as_ptr()) };
+    if entry.is_null() {
+        return Err(format!("Unknown user \"{user}\""));
+    }
+    let (uid, gid) = unsafe { ((*entry).pw_uid, (*entry).pw_gid) };
+
+    // The groups go first, they cannot be changed anymore once the user is
+    unsafe {
+        if libc::initgroups(name.as_ptr(), gid) != 0 || libc::setgid(gid) != 0 || libc::setuid(uid) != 0 {
+            return Err(format!(
+                "Failed to switch to user \"{user}\": {}",
+                std::io::Error::last_os_error()
+            ));
+        }
+    }
+
+    Ok(())
+}
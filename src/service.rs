@@ -0,0 +1,106 @@
+//! Systemd unit of the program, written by the `install-service` command.
+//!
+//! The unit runs the program with the options the command was given, with `--wait` so it does not fail when it
+//! starts before the device is ready, and restricts what it can access to the hidraw devices, the sensors and the
+//! paths the options need.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+/// Default location of the unit
+pub const UNIT_PATH: &str = "/etc/systemd/system/deepcool-digital.service";
+
+/// What the program needs besides the devices and the sensors.
+pub struct Access {
+    /// Folder the relative paths of the options start from
+    pub working_directory: PathBuf,
+    /// Folders written to, e.g. the one of `--log-csv`
+    pub writable: Vec<PathBuf>,
+    /// `/run/deepcool` holds the control socket
+    pub runtime_directory: bool,
+    /// The readings are sent or served over the network
+    pub network: bool,
+    /// The service is published on the system D-Bus
+    pub dbus: bool,
+}
+
+/// Returns the unit running `exe` with the given arguments.
+pub fn unit(exe: &Path, arguments: &[String], access: &Access) -> String {
+    let mut command = vec![quote(&exe.to_string_lossy())];
+    command.extend(arguments.iter().map(|arg| quote(arg)));
+    if !arguments.iter().any(|arg| arg == "--wait" || arg == "-w") {
+        command.push("--wait".to_string());
+    }
+
+    // The unit is wanted by multi-user.target, which is ordered after the units it wants, so it cannot be ordered
+    // after the target itself
+    let mut unit = String::from("[Unit]\nDescription=DeepCool Digital\nAfter=systemd-udevd.service");
+    if access.dbus {
+        unit.push_str(" dbus.service");
+    }
+    if access.network {
+        unit.push_str(" network-online.target\nWants=network-online.target");
+    }
+
+    unit.push_str(&format!(
+        "\n\n[Service]\nType=notify\nExecStart={}\nRestart=on-failure\nRestartSec=5\nWatchdogSec=30\n",
+        command.join(" ")
+    ));
+    unit.push_str(&format!(
+        "WorkingDirectory={}\n",
+        quote(&access.working_directory.to_string_lossy())
+    ));
+    if access.runtime_directory {
+        unit.push_str("RuntimeDirectory=deepcool\n");
+    }
+    for path in &access.writable {
+        unit.push_str(&format!("ReadWritePaths={}\n", quote(&format!("-{}", path.display()))));
+    }
+    unit.push_str("DevicePolicy=closed\nDeviceAllow=char-hidraw rw\n");
+    unit.push_str("ProtectSystem=strict\nProtectHome=read-only\nPrivateTmp=yes\nNoNewPrivileges=yes\n");
+    unit.push_str("ProtectKernelTunables=yes\nProtectKernelModules=yes\nProtectKernelLogs=yes\n");
+    unit.push_str("ProtectControlGroups=yes\nProtectClock=yes\nProtectHostname=yes\nRestrictNamespaces=yes\n");
+    unit.push_str("RestrictRealtime=yes\nLockPersonality=yes\nMemoryDenyWriteExecute=yes\n");
+    unit.push_str("SystemCallArchitectures=native\n");
+    unit.push_str(if access.network {
+        "RestrictAddressFamilies=AF_UNIX AF_NETLINK AF_INET AF_INET6\n"
+    } else {
+        "RestrictAddressFamilies=AF_UNIX AF_NETLINK\n"
+    });
+    unit.push_str("\n[Install]\nWantedBy=multi-user.target\n");
+
+    unit
+}
+
+/// Writes the unit, then reloads systemd and, with `enable`, enables and starts the service.
+pub fn install(path: &Path, unit: &str, enable: bool) -> Result<(), String> {
+    fs::write(path, unit).map_err(|err| format!("Failed to write {}: {err}", path.display()))?;
+    systemctl(&["daemon-reload"])?;
+    if enable {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        systemctl(&["enable", "--now", &name])?;
+    }
+
+    Ok(())
+}
+
+/// Runs `systemctl` with the given arguments.
+fn systemctl(arguments: &[&str]) -> Result<(), String> {
+    match Command::new("systemctl").args(arguments).status() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("systemctl {} failed: {status}", arguments.join(" "))),
+        Err(err) => Err(format!("Failed to run systemctl: {err}")),
+    }
+}
+
+/// Quotes an argument of `ExecStart=` if needed, `%` and `$` are escaped so systemd does not expand them.
+fn quote(arg: &str) -> String {
+    let escaped = arg.replace('%', "%%").replace('$', "$$");
+    if !escaped.is_empty() && !escaped.contains(|c: char| c.is_whitespace() || "\"'\\;".contains(c)) {
+        return escaped;
+    }
+    format!("\"{}\"", escaped.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -0,0 +1,135 @@
+//! Metrics defined in the settings file, read besides the CPU ones.
+//!
+//! ```toml
+//! [[metrics]]
+//! name = "loop_temp"
+//! unit = "C"                    # C (˚C), % or W, the display field that can show it
+//! exec = "/usr/local/bin/loop-temp.sh"   # prints the reading as a number
+//! timeout = 1000                # optional, milliseconds the command may take, up to 10000
+//!
+//! [[metrics]]
+//! name = "pump_speed"
+//! unit = "%"
+//! fifo = "/run/deepcool/metric" # last number written to this named pipe, "-" for the standard input
+//!
+//! [[metrics]]
+//! name = "hottest"
+//! unit = "C"
+//! expr = "max(cpu_temp, loop_temp)"   # calculated from the other metrics, see `monitor::expr`
+//!
+//! [display]
+//! metric = "loop_temp"          # same as --metric, shown instead of the CPU reading of its unit
+//! ```
+//!
+//! A metric is read from a command or from a pipe, or computed from the readings of the CPU, of the metrics read
+//! and of the computed metrics defined before it. The commands run with `sh -c` in the background, each update
+//! shows the output of the last run that finished, so a slow command does not delay the updates. The named pipes
+//! are created if they do not exist, other programs write one number per line to them and the last one is shown.
+//! The metrics are published by the exporters and can be followed by the hooks whether they are shown or not.
+
+use crate::{
+    monitor::{
+        builtin_metrics, exec::Exec, expr::Expr, fifo::Fifo, metric_name, Computed, MetricProvider, Unit, CPU_METRICS,
+    },
+    toml::{get_int, get_str, Table},
+};
+use std::{io, path::PathBuf, time::Duration};
+
+/// Keys of a `[[metrics]]` entry
+pub const KEYS: &[&str] = &["name", "unit", "exec", "timeout", "fifo", "expr"];
+
+/// Longest time a command may take, in milliseconds
+const MAX_TIMEOUT: i64 = 10_000;
+
+pub struct Source {
+    /// Name the metric is known by
+    pub name: String,
+    unit: Unit,
+    input: Input,
+}
+
+/// Where the readings of a metric come from.
+enum Input {
+    /// Output of a command, and the time it may take
+    Exec(String, Duration),
+    /// Named pipe, `None` for the standard input
+    Fifo(Option<PathBuf>),
+    /// Expression over the other metrics
+    Expr(Expr),
+}
+
+/// Metric of a `[[metrics]]` entry.
+pub enum Definition {
+    Provider(Box<dyn MetricProvider>),
+    Computed(Computed),
+}
+
+impl Source {
+    /// Parses and validates a `[[metrics]]` entry.
+    pub fn parse(table: &Table) -> Result<Self, String> {
+        let name = metric_name(get_str(table, "name")?.ok_or("missing \"metrics.name\"")?);
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        {
+            return Err(format!(
+                "invalid metric name \"{name}\", expected lowercase letters, digits and underscores"
+            ));
+        }
+        if CPU_METRICS.contains(&name.as_str()) || builtin_metrics().iter().any(|&(builtin, _)| builtin == name) {
+            return Err(format!("\"{name}\" is a built-in metric, it cannot be redefined"));
+        }
+        let unit = get_str(table, "unit")?.ok_or("missing \"metrics.unit\"")?;
+        let unit = Unit::parse(unit).ok_or(format!("unknown unit \"{unit}\", expected C, % or W"))?;
+        let inputs = [
+            get_str(table, "exec")?,
+            get_str(table, "fifo")?,
+            get_str(table, "expr")?,
+        ];
+        let input = match inputs {
+            [Some(command), None, None] => {
+                let timeout = get_int(table, "timeout", MAX_TIMEOUT)?.unwrap_or(1000);
+                Input::Exec(command.to_string(), Duration::from_millis(timeout as u64))
+            }
+            [None, Some("-"), None] => Input::Fifo(None),
+            [None, Some(path), None] => Input::Fifo(Some(PathBuf::from(path))),
+            [None, None, Some(expr)] => {
+                Input::Expr(Expr::parse(expr).map_err(|err| format!("metric \"{name}\": invalid expression, {err}"))?)
+            }
+            _ => return Err(format!("metric \"{name}\" needs one of \"exec\", \"fifo\" or \"expr\"")),
+        };
+
+        Ok(Source { name, unit, input })
+    }
+
+    /// Returns the names of the metrics the expression of a computed metric reads.
+    pub fn reads(&self) -> Vec<&str> {
+        match &self.input {
+            Input::Expr(expr) => expr.metrics(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn is_computed(&self) -> bool {
+        matches!(self.input, Input::Expr(_))
+    }
+
+    /// Returns the provider reading the metric, the pipes are opened, or the computed metric.
+    pub fn open(&self) -> io::Result<Definition> {
+        let provider: Box<dyn MetricProvider> = match &self.input {
+            Input::Exec(command, timeout) => Box::new(Exec::new(&self.name, self.unit, command, *timeout)),
+            Input::Fifo(Some(path)) => Box::new(Fifo::open(&self.name, self.unit, path)?),
+            Input::Fifo(None) => Box::new(Fifo::stdin(&self.name, self.unit)),
+            Input::Expr(expr) => {
+                return Ok(Definition::Computed(Computed {
+                    name: self.name.clone(),
+                    unit: self.unit,
+                    expr: expr.clone(),
+                }))
+            }
+        };
+
+        Ok(Definition::Provider(provider))
+    }
+}
@@ -0,0 +1,60 @@
+//! State shared by the display loops, the servers and the signal handlers.
+
+use crate::{
+    devices::{Counters, Status},
+    monitor::Reading,
+    timer,
+};
+use std::sync::{
+    atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicU8, Ordering},
+    Mutex,
+};
+
+/// Set by SIGINT & SIGTERM, the devices are blanked before exiting
+pub static STOP: AtomicBool = AtomicBool::new(false);
+/// Brightness shared by the display loops, changing it takes effect on the next update
+pub static BRIGHTNESS: AtomicU8 = AtomicU8::new(100);
+/// Toggled by SIGUSR2
+pub static FAHRENHEIT: AtomicBool = AtomicBool::new(false);
+/// Incremented by SIGUSR1 to switch to the next display mode
+pub static NEXT_MODE: AtomicU32 = AtomicU32::new(0);
+/// Set by SIGHUP to read the settings file again
+pub static RELOAD: AtomicBool = AtomicBool::new(false);
+/// Set while the displays have to stay blank
+pub static BLANK: AtomicBool = AtomicBool::new(false);
+/// Last status sent to a device, reported on the control socket
+pub static STATUS: Mutex<Option<Status>> = Mutex::new(None);
+/// Readings of every metric at the last update, published by the exporters
+pub static READINGS: Mutex<Vec<Reading>> = Mutex::new(Vec::new());
+/// Models of the devices being driven
+pub static DEVICES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+/// Connection problems since the start
+pub static COUNTERS: Counters = Counters {
+    write_errors: AtomicU64::new(0),
+    reconnects: AtomicU64::new(0),
+};
+
+/// Makes SIGINT & SIGTERM stop the display loops instead of killing the program, SIGUSR1 switch to the next
+/// display mode, SIGUSR2 toggle the temperature unit and SIGHUP reload the settings file.
+pub fn install_signal_handlers() {
+    extern "C" fn handle(signal: libc::c_int) {
+        match signal {
+            libc::SIGUSR1 => {
+                NEXT_MODE.fetch_add(1, Ordering::Relaxed);
+            }
+            libc::SIGUSR2 => {
+                FAHRENHEIT.fetch_xor(true, Ordering::Relaxed);
+            }
+            libc::SIGHUP => RELOAD.store(true, Ordering::Relaxed),
+            _ => STOP.store(true, Ordering::Relaxed),
+        }
+        timer::wake();
+    }
+
+    let handler = handle as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe {
+        for signal in [libc::SIGINT, libc::SIGTERM, libc::SIGUSR1, libc::SIGUSR2, libc::SIGHUP] {
+            libc::signal(signal, handler);
+        }
+    }
+}
@@ -0,0 +1,549 @@
+//! Drives the devices until the program is stopped.
+//!
+//! The devices are looked up again periodically to pick up the ones plugged in (or back in) later, each one runs its
+//! display loop on its own thread. In between, the settings file is reloaded on SIGHUP, the brightness schedule, the
+//! hooks and the session state are followed, and systemd is told the devices are still updating.
+//!
+//! Threads are used rather than an async runtime: hidapi, the sysfs and procfs files, and the GPU libraries only have
+//! blocking calls, so a runtime would still run every device and most metrics on its pool of blocking threads. One
+//! thread per device and per server, sharing the state of `RunOptions`, with every wait woken up at once by
+//! `timer::wake` for the signals, the control socket, D-Bus and the reloads, gives the same result without a runtime
+//! in a program that starts as root.
+
+use crate::{
+    capture::Capture,
+    cli::{builtin_settings, load_config, resolve_args, shown_metrics, Args},
+    config::{self, Config},
+    control, daemon, dbus,
+    devices::{self, DeviceSettings, Heartbeat, RunOptions},
+    discovery::{connect, lookup, ConnectError},
+    history, influxdb,
+    log::{error, info, warning},
+    metrics,
+    monitor::{self, builtin_metrics, builtin_provider, cpu, MetricProvider, Metrics, Unit, CPU_METRICS},
+    mqtt, privileges,
+    sources::Definition,
+    state::{self, BLANK, BRIGHTNESS, COUNTERS, DEVICES, FAHRENHEIT, NEXT_MODE, READINGS, RELOAD, STATUS, STOP},
+    systemd, timer,
+};
+use hidapi::HidApi;
+use std::{
+    ffi::{CStr, CString, OsStr},
+    fs::read_to_string,
+    os::unix::ffi::OsStrExt,
+    path::Path,
+    process::exit,
+    sync::{atomic::Ordering, Arc},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// Time between two lookups of plugged in or reconnected devices
+const RESCAN_INTERVAL: Duration = Duration::from_secs(2);
+
+/// A device driven by its own thread.
+struct Running {
+    path: CString,
+    name: String,
+    product_id: u16,
+    /// Display settings of the device, updated when the settings file is reloaded
+    settings: Arc<DeviceSettings>,
+    /// Time of the last update of the device
+    heartbeat: Arc<Heartbeat>,
+    handle: JoinHandle<()>,
+}
+
+/// Builds the options shared by the display loops, exits if the CPU temperature sensor, the capture file or a metric
+/// cannot be opened.
+pub fn options(args: &Args, config: &Config) -> RunOptions {
+    let options = RunOptions {
+        cpu_temp_sensor: match &args.sensor {
+            Some(path) => match read_to_string(path) {
+                Ok(_) => path.to_string_lossy().into_owned(),
+                Err(err) => {
+                    error!("Failed to read the CPU temperature from {}: {err}", path.display());
+                    exit(1);
+                }
+            },
+            None => cpu::lookup_temp_sensor().unwrap_or_else(|| {
+                error!("CPU temperature sensor not found!");
+                exit(1);
+            }),
+        },
+        fahrenheit: &FAHRENHEIT,
+        next_mode: &NEXT_MODE,
+        settings: Arc::new(DeviceSettings::new(&args.mode, args.poll, None)),
+        heartbeat: Arc::new(Heartbeat::new()),
+        max_retries: args.max_retries,
+        write_timeout: Duration::from_millis(args.write_timeout),
+        alarm_temp: args.alarm_temp,
+        alarm_clear: args.alarm_clear.unwrap_or(args.alarm_temp),
+        stop: &STOP,
+        brightness: &BRIGHTNESS,
+        blank: &BLANK,
+        status: &STATUS,
+        readings: &READINGS,
+        devices: &DEVICES,
+        counters: &COUNTERS,
+        capture: args.record.as_ref().map(|path| match Capture::create(path) {
+            Ok(capture) => Arc::new(capture),
+            Err(err) => {
+                error!("Failed to create {}: {err}", path.display());
+                exit(1);
+            }
+        }),
+        adaptive_poll: args.adaptive_poll,
+        sample_window: args.sample_window,
+        metrics: Arc::new(load_metrics(args, config)),
+    };
+    BRIGHTNESS.store(args.brightness, Ordering::Relaxed);
+    FAHRENHEIT.store(args.fahrenheit, Ordering::Relaxed);
+
+    options
+}
+
+/// Starts the servers and the exporters that are enabled, then drives the devices until the program is stopped.
+///
+/// `args` are the arguments resolved with the shared settings, `cli_args` the ones given on the command line, they are
+/// resolved again for every device and when the settings file is reloaded.
+pub fn run(
+    api: HidApi,
+    cli_args: Args,
+    cli_mode: Option<String>,
+    args: Args,
+    mut config: Config,
+    definitions: Vec<devices::generic::Definition>,
+    options: RunOptions,
+) {
+    // Detach from the terminal before any thread is started
+    if args.daemon {
+        if let Err(err) = daemon::daemonize(args.log_file.as_deref().unwrap_or(Path::new("/dev/null"))) {
+            error!("{err}");
+            exit(1);
+        }
+    }
+    if let Some(path) = &args.pidfile {
+        if let Err(err) = daemon::write_pidfile(path) {
+            error!("{err}");
+            exit(1);
+        }
+    }
+    state::install_signal_handlers();
+    if let Some(path) = &args.socket {
+        if let Err(err) = control::serve(path, options.clone()) {
+            warning!("Failed to open the control socket {}: {err}", path.display());
+        }
+    }
+    if args.dbus {
+        if let Err(err) = dbus::serve(options.clone()) {
+            warning!("Failed to connect to D-Bus: {err}");
+        }
+    }
+    if let Some(address) = &args.metrics_listen {
+        if let Err(err) = metrics::serve(address, options.clone()) {
+            warning!("Failed to listen for metrics on {address}: {err}");
+        }
+    }
+    if let Some(path) = &args.log_csv {
+        if let Err(err) = history::start(path, options.clone()) {
+            warning!("Failed to open {}: {err}", path.display());
+        }
+    }
+    if let Some(settings) = config.mqtt.take() {
+        mqtt::start(settings, options.clone());
+    }
+    if let Some(settings) = config.influxdb.take() {
+        influxdb::start(settings, options.clone());
+    }
+    if args.blank_when_idle && monitor::session::idle_or_locked().is_none() {
+        warning!("Failed to read the session state from systemd-logind, the display will not be blanked");
+    }
+
+    let hooked_metrics = hook_metrics(&config, &options);
+    let mut supervisor = Supervisor {
+        api,
+        cli_args,
+        cli_mode,
+        args,
+        config,
+        definitions,
+        options,
+        running: Vec::new(),
+        unsupported: Vec::new(),
+        scheduled: None,
+        hooked_metrics,
+    };
+    let mut first_scan = true;
+    let mut plugged = false;
+    let watchdog = systemd::watchdog_interval();
+    let timer = timer::Timer::new();
+    loop {
+        supervisor.running.retain(|device| !device.handle.is_finished());
+        if RELOAD.swap(false, Ordering::Relaxed) {
+            supervisor.reload();
+        }
+        supervisor.follow_schedule();
+        if !first_scan {
+            supervisor.run_hooks();
+        }
+        supervisor.follow_session();
+
+        let started = supervisor.connect_devices(first_scan);
+        plugged |= started;
+        *DEVICES.lock().unwrap() = supervisor.running.iter().map(|device| device.name.clone()).collect();
+        if first_scan && supervisor.running.is_empty() && !supervisor.args.wait {
+            exit(1);
+        }
+        if started {
+            info!("\nPress Ctrl + C to terminate");
+        }
+        if first_scan {
+            supervisor.drop_privileges();
+            // Tell systemd the devices are running, or waited for
+            systemd::notify("READY=1");
+        }
+        first_scan = false;
+        if let Some(interval) = watchdog {
+            supervisor.feed_watchdog(interval);
+        }
+
+        timer.wait(RESCAN_INTERVAL);
+        if STOP.load(Ordering::Relaxed) {
+            systemd::notify("STOPPING=1");
+            break;
+        }
+        if let Some(path) = &supervisor.args.device {
+            // The node is gone once the device is unplugged, there is nothing left to wait for
+            if plugged && !Path::new(OsStr::from_bytes(path.as_bytes())).exists() {
+                info!("{} was unplugged", path.to_string_lossy());
+                STOP.store(true, Ordering::Relaxed);
+                break;
+            }
+            continue;
+        }
+        if let Err(err) = supervisor.api.refresh_devices() {
+            warning!("Failed to look up the devices: {err}");
+        }
+    }
+
+    // Wait for the devices to be blanked
+    for device in supervisor.running {
+        let _ = device.handle.join();
+    }
+    if let Some(path) = &supervisor.args.pidfile {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+/// Devices being driven and the settings they follow.
+struct Supervisor {
+    api: HidApi,
+    /// Arguments given on the command line
+    cli_args: Args,
+    /// Display mode given on the command line or in the environment
+    cli_mode: Option<String>,
+    /// Arguments resolved with the shared settings
+    args: Args,
+    config: Config,
+    definitions: Vec<devices::generic::Definition>,
+    options: RunOptions,
+    running: Vec<Running>,
+    /// Devices without a driver or used by another instance, they are not tried again
+    unsupported: Vec<CString>,
+    /// Brightness of the schedule entry in effect
+    scheduled: Option<u8>,
+    /// CPU metrics only read for the hooks
+    hooked_metrics: Metrics,
+}
+
+impl Supervisor {
+    /// Applies the changes of the settings file, the running devices keep going.
+    fn reload(&mut self) {
+        let reloaded = match load_config(&self.args) {
+            Ok(reloaded) => reloaded,
+            Err(err) => {
+                warning!("Invalid settings file, keeping the previous settings: {err}");
+                return;
+            }
+        };
+        self.config = reloaded;
+        for device in &self.running {
+            let (args, unit) = resolve_args(
+                &self.cli_args,
+                self.cli_mode.as_deref(),
+                &self.config,
+                Some((&device.name, device.product_id)),
+            );
+            device.settings.set(&args.mode, args.poll, unit);
+        }
+        if !self.cli_args.fahrenheit {
+            FAHRENHEIT.store(self.config.display.fahrenheit.unwrap_or(false), Ordering::Relaxed);
+        }
+        self.scheduled = None;
+        self.hooked_metrics = hook_metrics(&self.config, &self.options);
+        timer::wake();
+        info!("Settings reloaded");
+    }
+
+    /// Follows the brightness schedule, changes made in between are kept until the next entry.
+    fn follow_schedule(&mut self) {
+        let level = self.config.brightness_at(config::local_minute());
+        if level != self.scheduled {
+            self.scheduled = level;
+            if let Some(level) = level {
+                BRIGHTNESS.store(level, Ordering::Relaxed);
+                timer::wake();
+            }
+        }
+    }
+
+    /// Runs the hooks of the readings that crossed their limit, they wait while their metric cannot be read.
+    fn run_hooks(&mut self) {
+        if self.config.hooks.is_empty() {
+            return;
+        }
+        let mut readings = Vec::new();
+        self.hooked_metrics.sample(&mut readings);
+        self.options.metrics.sample(&mut readings);
+        for hook in &mut self.config.hooks {
+            if let Some(reading) = readings.iter().find(|reading| reading.name == hook.metric) {
+                hook.check(reading.value);
+            }
+        }
+    }
+
+    /// Follows the idle & lock state of the session.
+    fn follow_session(&self) {
+        if !self.args.blank_when_idle {
+            return;
+        }
+        let idle = monitor::session::idle_or_locked().unwrap_or(false);
+        if BLANK.swap(idle, Ordering::Relaxed) != idle {
+            info!(
+                "{}",
+                if idle {
+                    "Session idle or locked, blanking the display"
+                } else {
+                    "Session active again"
+                }
+            );
+        }
+    }
+
+    /// Starts the display loop of the devices found that are not driven yet, returns whether one was started.
+    ///
+    /// On the first scan, exits if no device is found and `--wait` is not given.
+    fn connect_devices(&mut self, first_scan: bool) -> bool {
+        let found = lookup(&self.api, &self.args, first_scan);
+        if first_scan && found.is_empty() {
+            if !self.args.wait {
+                error!("No DeepCool device found!");
+                exit(1);
+            }
+            info!("Waiting for a DeepCool device...");
+        }
+        let mut started = false;
+        for entries in &found {
+            let known = |path: &CStr| {
+                self.running.iter().any(|device| device.path.as_c_str() == path)
+                    || self.unsupported.iter().any(|p| p.as_c_str() == path)
+            };
+            if entries.iter().any(|entry| known(entry.path())) {
+                continue;
+            }
+
+            let name = entries[0].product_string().unwrap_or("unknown").to_string();
+            let product_id = entries[0].product_id();
+            let (device_args, unit) = resolve_args(
+                &self.cli_args,
+                self.cli_mode.as_deref(),
+                &self.config,
+                Some((&name, product_id)),
+            );
+            let (info, device, mut driver, lock) = match connect(&self.api, entries, &device_args, &self.definitions) {
+                Ok(connection) => connection,
+                Err(ConnectError::Interface) if self.args.device.is_some() => {
+                    // Instances started by udev for every hidraw node of a device leave the others to the display one
+                    info!("{} is not the interface of the display", entries[0].path().to_string_lossy());
+                    exit(0);
+                }
+                Err(ConnectError::Unsupported | ConnectError::Interface) => {
+                    self.unsupported.push(entries[0].path().to_owned());
+                    continue;
+                }
+                Err(ConnectError::Busy(message)) => {
+                    error!("{message}");
+                    if first_scan {
+                        exit(1);
+                    }
+                    self.unsupported.push(entries[0].path().to_owned());
+                    continue;
+                }
+                Err(ConnectError::Open(err)) => {
+                    warning!("Failed to open HID device: {err}");
+                    info!("-----");
+                    continue;
+                }
+            };
+
+            // Display loop
+            let settings = Arc::new(DeviceSettings::new(&device_args.mode, device_args.poll, unit));
+            let heartbeat = Arc::new(Heartbeat::new());
+            let options = RunOptions {
+                settings: settings.clone(),
+                heartbeat: heartbeat.clone(),
+                ..self.options.clone()
+            };
+            let handle = thread::spawn({
+                let name = name.clone();
+                move || {
+                    // The device stays locked until its loop ends
+                    let _lock = lock;
+                    if let Err(err) = devices::run(device, driver.as_mut(), &options) {
+                        options.counters.reconnects.fetch_add(1, Ordering::Relaxed);
+                        warning!(device = name; "Lost connection to {name}: {err}");
+                    }
+                }
+            });
+            self.running.push(Running {
+                path: info.path().to_owned(),
+                name,
+                product_id,
+                settings,
+                heartbeat,
+                handle,
+            });
+            started = true;
+        }
+
+        started
+    }
+
+    /// Gives up root once the devices are open, the energy counter is opened before as it is only readable by root.
+    fn drop_privileges(&self) {
+        let Some(user) = &self.args.user else {
+            return;
+        };
+        cpu::open_energy();
+        match privileges::drop_to(user) {
+            Ok(()) => info!("Running as {user}"),
+            Err(err) => {
+                error!("{err}");
+                exit(1);
+            }
+        }
+    }
+
+    /// Tells the systemd watchdog the devices are updating, unless one of them is stuck.
+    fn feed_watchdog(&self, interval: Duration) {
+        match self.running.iter().find(|device| device.heartbeat.stalled(interval)) {
+            Some(device) => warning!(device = device.name; "{} stopped updating", device.name),
+            None => systemd::notify("WATCHDOG=1"),
+        }
+    }
+}
+
+/// Returns the CPU metrics followed by the hooks, directly or through a computed metric, the other ones are read from
+/// `options.metrics`.
+///
+/// The power is only read when it is followed. Warns about the hooks of unknown metrics, they never run.
+fn hook_metrics(config: &Config, options: &RunOptions) -> Metrics {
+    let available = options.metrics.available();
+    let mut providers: Vec<Box<dyn MetricProvider>> = Vec::new();
+    for metric in CPU_METRICS {
+        let followed = config.hooks.iter().any(|hook| hook.metric == metric)
+            || config.metrics.iter().any(|source| source.reads().contains(&metric));
+        if config.hooks.is_empty() || !followed {
+            continue;
+        }
+        match metric {
+            "cpu_temp" => providers.push(Box::new(cpu::CpuTemp {
+                sensor: options.cpu_temp_sensor.clone(),
+            })),
+            "cpu_usage" => providers.push(Box::new(cpu::CpuUsage::new())),
+            _ if cpu::open_energy().is_none() => warning!(
+                "Failed to read the CPU energy counter, the power hooks will not run. {}",
+                cpu::ENERGY_HINT
+            ),
+            _ => providers.push(Box::new(cpu::CpuPower::new())),
+        }
+    }
+    for hook in &config.hooks {
+        // An unavailable built-in metric was already reported
+        let builtin = builtin_metrics().iter().any(|&(name, _)| name == hook.metric);
+        if !builtin && !available.iter().any(|(name, _)| *name == hook.metric) {
+            warning!(
+                "Unknown metric \"{}\" in the hooks, expected one of {}",
+                hook.metric,
+                metric_names(&available)
+            );
+        }
+    }
+
+    Metrics::new(providers, Vec::new(), Vec::new())
+}
+
+/// Returns the metrics defined in the settings, with the ones selected to be shown, exits if one of those is unknown.
+pub fn load_metrics(args: &Args, config: &Config) -> Metrics {
+    let mut providers = Vec::new();
+    let mut computed = Vec::new();
+    for source in &config.metrics {
+        match source.open() {
+            Ok(Definition::Provider(provider)) => providers.push(provider),
+            Ok(Definition::Computed(metric)) => computed.push(metric),
+            Err(err) => {
+                error!("Failed to open the {} metric: {err}", source.name);
+                exit(1);
+            }
+        }
+    }
+    let mut shown = shown_metrics(args, config);
+
+    let settings = builtin_settings(args, config);
+    // The built-in metrics are only read when they are used, the ones the system does not have are left out
+    let used: Vec<&str> = builtin_metrics()
+        .into_iter()
+        .map(|(name, _)| name)
+        .filter(|&name| {
+            shown.iter().any(|metric| metric == name)
+                || config.hooks.iter().any(|hook| hook.metric == name)
+                || config.metrics.iter().any(|source| source.reads().contains(&name))
+        })
+        .collect();
+    for name in used {
+        match builtin_provider(name, &settings) {
+            Ok(provider) => providers.push(provider),
+            Err(err) if shown.iter().any(|metric| metric == name) => {
+                warning!("The {name} metric is unavailable ({err}), the CPU reading is shown instead");
+                shown.retain(|metric| metric != name);
+            }
+            Err(err) => warning!("The {name} metric is unavailable ({err})"),
+        }
+    }
+
+    let metrics = Metrics::new(providers, computed, shown.clone());
+    let available = metrics.available();
+    for name in &shown {
+        if !available.iter().any(|(metric, _)| metric == name) {
+            let mut known = available.clone();
+            known.extend(
+                builtin_metrics()
+                    .into_iter()
+                    .map(|(name, unit)| (name.to_string(), unit)),
+            );
+            error!("Unknown metric \"{name}\", expected one of {}", metric_names(&known));
+            exit(1);
+        }
+    }
+
+    metrics
+}
+
+/// Lists the names of the metrics, for the error messages.
+fn metric_names(metrics: &[(String, Unit)]) -> String {
+    metrics
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
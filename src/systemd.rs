@@ -0,0 +1,44 @@
+//! Notifications to systemd for services of `Type=notify`.
+//!
+//! `READY=1` is sent once the devices are running, and with `WatchdogSec=` set, `WATCHDOG=1` is sent as long as
+//! the display loops keep updating, so systemd restarts the program when one of them hangs.
+
+use std::{
+    env,
+    os::{
+        linux::net::SocketAddrExt,
+        unix::net::{SocketAddr, UnixDatagram},
+    },
+    process,
+    sync::OnceLock,
+    time::Duration,
+};
+
+/// Sends a state to systemd, e.g. `READY=1`, does nothing when the program is not started by systemd.
+pub fn notify(state: &str) {
+    static SOCKET: OnceLock<Option<(UnixDatagram, SocketAddr)>> = OnceLock::new();
+    let socket = SOCKET.get_or_init(|| {
+        let path = env::var("NOTIFY_SOCKET").ok()?;
+        // Names starting with '@' are in the abstract namespace
+        let address = match path.strip_prefix('@') {
+            Some(name) => SocketAddr::from_abstract_name(name),
+            None => SocketAddr::from_pathname(&path),
+        };
+        Some((UnixDatagram::unbound().ok()?, address.ok()?))
+    });
+    if let Some((socket, address)) = socket {
+        let _ = socket.send_to_addr(state.as_bytes(), address);
+    }
+}
+
+/// Returns the interval the watchdog expects a notification within, if it is enabled for this process.
+pub fn watchdog_interval() -> Option<Duration> {
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+        if pid.parse() != Ok(process::id()) {
+            return None;
+        }
+    }
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+
+    Some(Duration::from_micros(usec))
+}
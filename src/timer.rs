@@ -0,0 +1,113 @@
+//! Interruptible waits, the display loops sleep until their next update unless a runtime control wakes them up.
+//!
+//! Every waiting thread polls an eventfd of its own and `wake` writes to all of them, so a signal, a control command
+//! or a reload takes effect right away instead of at the end of the update interval. `wake` only calls `write`, it
+//! can be used from a signal handler.
+
+use std::{
+    sync::atomic::{AtomicBool, AtomicI32, Ordering},
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+/// Most timers at the same time, the next ones fall back to sleeping without being woken up
+const SLOTS: usize = 32;
+
+/// Eventfd of every slot, -1 until the slot is first used. They are never closed, so `wake` cannot write to a
+/// descriptor that has been reused for something else
+static EVENTS: [AtomicI32; SLOTS] = [const { AtomicI32::new(-1) }; SLOTS];
+/// Slots held by a `Timer`
+static TAKEN: [AtomicBool; SLOTS] = [const { AtomicBool::new(false) }; SLOTS];
+
+/// Wait of a single thread, woken up early by `wake`.
+pub struct Timer {
+    slot: Option<usize>,
+}
+
+impl Timer {
+    pub fn new() -> Self {
+        let slot = (0..SLOTS).find(|&slot| {
+            TAKEN[slot]
+                .compare_exchange(false, true, Ordering::AcqRel, Ordering::Relaxed)
+                .is_ok()
+        });
+        let Some(slot) = slot else {
+            return Timer { slot: None };
+        };
+
+        match EVENTS[slot].load(Ordering::Acquire) {
+            -1 => {
+                let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+                if fd < 0 {
+                    TAKEN[slot].store(false, Ordering::Release);
+                    return Timer { slot: None };
+                }
+                EVENTS[slot].store(fd, Ordering::Release);
+            }
+            // Wake-ups meant for the previous holder of the slot
+            fd => drain(fd),
+        }
+
+        Timer { slot: Some(slot) }
+    }
+
+    /// Waits for `duration`, returns `true` if woken up before the end.
+    pub fn wait(&self, duration: Duration) -> bool {
+        let Some(slot) = self.slot else {
+            sleep(duration);
+            return false;
+        };
+        let fd = EVENTS[slot].load(Ordering::Acquire);
+
+        let deadline = Instant::now() + duration;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return false;
+            }
+            // Rounded up, so the wait is never cut short
+            let timeout = remaining.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32;
+            let mut event = libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // Otherwise timed out, or interrupted by a signal whose handler wakes the timers anyway
+            if unsafe { libc::poll(&mut event, 1, timeout) } == 1 {
+                drain(fd);
+                return true;
+            }
+        }
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        if let Some(slot) = self.slot {
+            TAKEN[slot].store(false, Ordering::Release);
+        }
+    }
+}
+
+/// Wakes up every waiting timer, and the next wait of the others.
+pub fn wake() {
+    let value: u64 = 1;
+    for event in &EVENTS {
+        let fd = event.load(Ordering::Acquire);
+        if fd >= 0 {
+            unsafe { libc::write(fd, &value as *const u64 as *const libc::c_void, 8) };
+        }
+    }
+}
+
+/// Resets the counter of an eventfd.
+fn drain(fd: i32) {
+    let mut value: u64 = 0;
+    unsafe { libc::read(fd, &mut value as *mut u64 as *mut libc::c_void, 8) };
+}
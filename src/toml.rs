@@ -0,0 +1,581 @@
+//! Minimal TOML reader.
+//!
+//! Covers the subset used by the device definition files: tables, arrays of tables, dotted and
+//! quoted keys, strings, integers (also hexadecimal, octal and binary), floats, booleans, arrays and inline tables.
+//! Dates, times and multi-line strings are not supported.
+
+use std::collections::BTreeMap;
+
+/// A table, its keys are kept in alphabetical order.
+pub type Table = BTreeMap<String, Value>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Value>),
+    Table(Table),
+}
+
+impl Value {
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_integer(&self) -> Option<i64> {
+        match self {
+            Value::Integer(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&Vec<Value>> {
+        match self {
+            Value::Array(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_table(&self) -> Option<&Table> {
+        match self {
+            Value::Table(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the type for error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Integer(_) => "integer",
+            Value::Float(_) => "float",
+            Value::Boolean(_) => "boolean",
+            Value::Array(_) => "array",
+            Value::Table(_) => "table",
+        }
+    }
+}
+
+/// Parses a TOML document and returns its root table.
+pub fn parse(input: &str) -> Result<Table, String> {
+    let mut parser = Parser {
+        chars: input.chars().collect(),
+        pos: 0,
+        line: 1,
+    };
+    parser
+        .document()
+        .map_err(|err| format!("line {}: {}", parser.line, err))
+}
+
+/// Merges `overlay` into `base`, the tables are merged key by key and the other values of `overlay` replace the ones
+/// of `base`.
+pub fn merge(base: &mut Table, overlay: Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(overlay)) => merge(base, overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Returns the string at `key`, or an error if it has another type.
+pub fn get_str<'a>(table: &'a Table, key: &str) -> Result<Option<&'a str>, String> {
+    match table.get(key) {
+        Some(value) => value
+            .as_str()
+            .map(Some)
+            .ok_or(format!("\"{key}\" must be a string, found {}", value.type_name())),
+        None => Ok(None),
+    }
+}
+
+/// Returns the integer at `key` if it is between `0` and `max`.
+pub fn get_int(table: &Table, key: &str, max: i64) -> Result<Option<i64>, String> {
+    match table.get(key) {
+        Some(value) => match value.as_integer() {
+            Some(int) if (0..=max).contains(&int) => Ok(Some(int)),
+            Some(int) => Err(format!("\"{key}\" is out of range: {int}")),
+            None => Err(format!("\"{key}\" must be an integer, found {}", value.type_name())),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Returns the boolean at `key`, or an error if it has another type.
+pub fn get_bool(table: &Table, key: &str) -> Result<Option<bool>, String> {
+    match table.get(key) {
+        Some(Value::Boolean(value)) => Ok(Some(*value)),
+        Some(value) => Err(format!("\"{key}\" must be a boolean, found {}", value.type_name())),
+        None => Ok(None),
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), String> {
+        match self.bump() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(format!("expected '{expected}', found '{c}'")),
+            None => Err(format!("expected '{expected}', found end of file")),
+        }
+    }
+
+    /// Skips spaces and tabs.
+    fn skip_spaces(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.bump();
+        }
+    }
+
+    /// Skips whitespace, newlines and comments.
+    fn skip_blank(&mut self) {
+        loop {
+            match self.peek() {
+                Some(' ' | '\t' | '\r' | '\n') => {
+                    self.bump();
+                }
+                Some('#') => self.skip_comment(),
+                _ => break,
+            }
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        while !matches!(self.peek(), None | Some('\n')) {
+            self.bump();
+        }
+    }
+
+    /// Makes sure nothing but a comment follows on the current line.
+    fn end_of_line(&mut self) -> Result<(), String> {
+        self.skip_spaces();
+        if self.peek() == Some('#') {
+            self.skip_comment();
+        }
+        match self.peek() {
+            None | Some('\n') => Ok(()),
+            Some('\r') if self.peek_at(1) == Some('\n') => Ok(()),
+            Some(c) => Err(format!("unexpected '{c}' at the end of the line")),
+        }
+    }
+
+    fn document(&mut self) -> Result<Table, String> {
+        let mut root = Table::new();
+        let mut current: Vec<String> = Vec::new();
+
+        loop {
+            self.skip_blank();
+            match self.peek() {
+                None => break,
+                Some('[') if self.peek_at(1) == Some('[') => {
+                    self.pos += 2;
+                    let path = self.key_path()?;
+                    self.expect(']')?;
+                    self.expect(']')?;
+                    let (last, parent) = path.split_last().unwrap();
+                    let table = table_at(&mut root, parent)?;
+                    match table.entry(last.clone()).or_insert_with(|| Value::Array(Vec::new())) {
+                        Value::Array(array) if array.iter().all(|v| matches!(v, Value::Table(_))) => {
+                            array.push(Value::Table(Table::new()));
+                        }
+                        _ => return Err(format!("\"{}\" is already defined", path.join("."))),
+                    }
+                    current = path;
+                }
+                Some('[') => {
+                    self.bump();
+                    let path = self.key_path()?;
+                    self.expect(']')?;
+                    table_at(&mut root, &path)?;
+                    current = path;
+                }
+                Some(_) => {
+                    let path = self.key_path()?;
+                    self.expect('=')?;
+                    self.skip_spaces();
+                    let value = self.value()?;
+                    let (last, parent) = path.split_last().unwrap();
+                    let table = table_at(table_at(&mut root, &current)?, parent)?;
+                    if table.insert(last.clone(), value).is_some() {
+                        return Err(format!("\"{}\" is defined twice", path.join(".")));
+                    }
+                }
+            }
+            self.end_of_line()?;
+        }
+
+        Ok(root)
+    }
+
+    /// Parses a dotted key like `device."AK620".mode`.
+    fn key_path(&mut self) -> Result<Vec<String>, String> {
+        let mut path = Vec::new();
+        loop {
+            self.skip_spaces();
+            path.push(self.key()?);
+            self.skip_spaces();
+            if self.peek() == Some('.') {
+                self.bump();
+            } else {
+                return Ok(path);
+            }
+        }
+    }
+
+    fn key(&mut self) -> Result<String, String> {
+        match self.peek() {
+            Some('"') => self.basic_string(),
+            Some('\'') => self.literal_string(),
+            _ => {
+                let start = self.pos;
+                while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                    self.bump();
+                }
+                if start == self.pos {
+                    return Err(match self.peek() {
+                        Some(c) => format!("expected a key, found '{c}'"),
+                        None => "expected a key, found end of file".to_string(),
+                    });
+                }
+                Ok(self.chars[start..self.pos].iter().collect())
+            }
+        }
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.peek() {
+            Some('"') => Ok(Value::String(self.basic_string()?)),
+            Some('\'') => Ok(Value::String(self.literal_string()?)),
+            Some('[') => self.array(),
+            Some('{') => self.inline_table(),
+            Some(_) => self.scalar(),
+            None => Err("expected a value, found end of file".to_string()),
+        }
+    }
+
+    fn basic_string(&mut self) -> Result<String, String> {
+        self.expect('"')?;
+        let mut value = String::new();
+        loop {
+            match self.bump() {
+                Some('"') => return Ok(value),
+                Some('\\') => match self.bump() {
+                    Some('n') => value.push('\n'),
+                    Some('t') => value.push('\t'),
+                    Some('r') => value.push('\r'),
+                    Some('"') => value.push('"'),
+                    Some('\\') => value.push('\\'),
+                    Some('u') => {
+                        let code: String = (0..4).filter_map(|_| self.bump()).collect();
+                        let c = u32::from_str_radix(&code, 16)
+                            .ok()
+                            .and_then(char::from_u32)
+                            .ok_or(format!("invalid unicode escape \"\\u{code}\""))?;
+                        value.push(c);
+                    }
+                    Some(c) => return Err(format!("invalid escape \"\\{c}\"")),
+                    None => return Err("unterminated string".to_string()),
+                },
+                Some('\n') | None => return Err("unterminated string".to_string()),
+                Some(c) => value.push(c),
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> Result<String, String> {
+        self.expect('\'')?;
+        let mut value = String::new();
+        loop {
+            match self.bump() {
+                Some('\'') => return Ok(value),
+                Some('\n') | None => return Err("unterminated string".to_string()),
+                Some(c) => value.push(c),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Value, String> {
+        self.expect('[')?;
+        let mut array = Vec::new();
+        loop {
+            self.skip_blank();
+            if self.peek() == Some(']') {
+                self.bump();
+                return Ok(Value::Array(array));
+            }
+            array.push(self.value()?);
+            self.skip_blank();
+            match self.bump() {
+                Some(',') => (),
+                Some(']') => return Ok(Value::Array(array)),
+                Some(c) => return Err(format!("expected ',' or ']' in array, found '{c}'")),
+                None => return Err("unterminated array".to_string()),
+            }
+        }
+    }
+
+    fn inline_table(&mut self) -> Result<Value, String> {
+        self.expect('{')?;
+        let mut table = Table::new();
+        self.skip_spaces();
+        if self.peek() == Some('}') {
+            self.bump();
+            return Ok(Value::Table(table));
+        }
+        loop {
+            let path = self.key_path()?;
+            self.expect('=')?;
+            self.skip_spaces();
+            let value = self.value()?;
+            let (last, parent) = path.split_last().unwrap();
+            if table_at(&mut table, parent)?.insert(last.clone(), value).is_some() {
+                return Err(format!("\"{}\" is defined twice", path.join(".")));
+            }
+            self.skip_spaces();
+            match self.bump() {
+                Some(',') => (),
+                Some('}') => return Ok(Value::Table(table)),
+                Some(c) => return Err(format!("expected ',' or '}}' in inline table, found '{c}'")),
+                None => return Err("unterminated inline table".to_string()),
+            }
+        }
+    }
+
+    /// Parses a boolean or a number.
+    fn scalar(&mut self) -> Result<Value, String> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(c) if c.is_ascii_alphanumeric() || "+-._".contains(c)) {
+            self.bump();
+        }
+        let token: String = self.chars[start..self.pos].iter().collect();
+        let digits = token.replace('_', "");
+        let (sign, unsigned) = match digits.strip_prefix('-') {
+            Some(rest) => (-1, rest),
+            None => (1, digits.strip_prefix('+').unwrap_or(&digits)),
+        };
+
+        let value = match token.as_str() {
+            "true" => Some(Value::Boolean(true)),
+            "false" => Some(Value::Boolean(false)),
+            _ if unsigned.starts_with("0x") => i64::from_str_radix(&unsigned[2..], 16)
+                .ok()
+                .map(|value| Value::Integer(sign * value)),
+            _ if unsigned.starts_with("0o") => i64::from_str_radix(&unsigned[2..], 8)
+                .ok()
+                .map(|value| Value::Integer(sign * value)),
+            _ if unsigned.starts_with("0b") => i64::from_str_radix(&unsigned[2..], 2)
+                .ok()
+                .map(|value| Value::Integer(sign * value)),
+            _ if unsigned.contains(['.', 'e', 'E']) => digits.parse::<f64>().ok().map(Value::Float),
+            _ => digits.parse::<i64>().ok().map(Value::Integer),
+        };
+        value.ok_or(if token.is_empty() {
+            format!("unexpected '{}'", self.peek().unwrap_or(' '))
+        } else {
+            format!("invalid value \"{token}\"")
+        })
+    }
+}
+
+/// Returns the table at the given path, creating the missing tables.
+/// An array of tables on the path resolves to its last element.
+fn table_at<'a>(root: &'a mut Table, path: &[String]) -> Result<&'a mut Table, String> {
+    let mut table = root;
+    for (i, key) in path.iter().enumerate() {
+        let value = table.entry(key.clone()).or_insert_with(|| Value::Table(Table::new()));
+        table = match value {
+            Value::Table(table) => table,
+            Value::Array(array) => match array.last_mut() {
+                Some(Value::Table(table)) => table,
+                _ => return Err(format!("\"{}\" is not a table", path[..=i].join("."))),
+            },
+            _ => return Err(format!("\"{}\" is not a table", path[..=i].join("."))),
+        };
+    }
+
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(entries: &[(&str, Value)]) -> Value {
+        Value::Table(
+            entries
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.clone()))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn device_definition() {
+        let root = parse(include_str!("../contrib/devices/ak620.toml")).unwrap();
+
+        assert_eq!(get_str(&root, "name").unwrap(), Some("AK620 DIGITAL"));
+        assert_eq!(get_int(&root, "product_id", u16::MAX as i64).unwrap(), Some(2));
+        assert_eq!(
+            root["init"],
+            Value::Array(vec![Value::Array(vec![Value::Integer(16), Value::Integer(170)])])
+        );
+        let fields = root["fields"].as_table().unwrap();
+        assert_eq!(
+            fields["unit"],
+            table(&[
+                ("offset", Value::Integer(1)),
+                ("values", Value::Array(vec![Value::Integer(19), Value::Integer(35)]))
+            ])
+        );
+        assert_eq!(fields["usage"].as_table().unwrap()["format"], Value::String("bar".to_string()));
+    }
+
+    #[test]
+    fn settings_file() {
+        let root = parse(include_str!("../contrib/deepcool-digital.toml")).unwrap();
+        let schedule = root["schedule"].as_array().unwrap();
+        assert_eq!(schedule.len(), 2);
+        assert_eq!(get_str(schedule[1].as_table().unwrap(), "from").unwrap(), Some("22:30"));
+        assert_eq!(
+            root["hooks"].as_array().unwrap()[0].as_table().unwrap()["command"],
+            Value::String("logger -t deepcool \"CPU at $DEEPCOOL_VALUE˚C\"".to_string())
+        );
+
+        let root = parse(
+            r#"
+            [display]
+            mode = 'temp:10,usage'   # literal string
+            fahrenheit = true
+            poll = "1.5s"
+
+            [device."CH560"]
+            mode = "usage"
+            device.'0x0002'.poll = 500
+
+            [[metrics]]
+            name = "loop_temp"
+            scale = 1e-3
+            [[metrics]]
+            name = "fan"
+            limits = [
+                0x10, 0o17, 0b11, -1_000, +2,   # trailing comma
+            ]
+            "#,
+        )
+        .unwrap();
+        let display = root["display"].as_table().unwrap();
+        assert_eq!(get_str(display, "mode").unwrap(), Some("temp:10,usage"));
+        assert_eq!(get_bool(display, "fahrenheit").unwrap(), Some(true));
+        let device = root["device"].as_table().unwrap();
+        assert_eq!(device["CH560"].as_table().unwrap()["mode"], Value::String("usage".to_string()));
+        assert_eq!(
+            device["CH560"].as_table().unwrap()["device"],
+            table(&[("0x0002", table(&[("poll", Value::Integer(500))]))])
+        );
+        let metrics = root["metrics"].as_array().unwrap();
+        assert_eq!(metrics[0].as_table().unwrap()["scale"], Value::Float(1e-3));
+        assert_eq!(
+            metrics[1].as_table().unwrap()["limits"],
+            Value::Array([16, 15, 3, -1000, 2].map(Value::Integer).to_vec())
+        );
+    }
+
+    #[test]
+    fn strings() {
+        let root = parse(r#"a = "tab\tquote\"slash\\ \u00b0C""#).unwrap();
+        assert_eq!(root["a"], Value::String("tab\tquote\"slash\\ °C".to_string()));
+        let root = parse("a = 'no \\escapes'").unwrap();
+        assert_eq!(root["a"], Value::String("no \\escapes".to_string()));
+
+        assert_eq!(parse("a = \"open").unwrap_err(), "line 1: unterminated string");
+        assert_eq!(parse("a = \"open\nb = 1").unwrap_err(), "line 2: unterminated string");
+        assert_eq!(parse("a = 'open").unwrap_err(), "line 1: unterminated string");
+        assert_eq!(parse(r#"a = "\q""#).unwrap_err(), "line 1: invalid escape \"\\q\"");
+        assert_eq!(
+            parse(r#"a = "\uzzzz""#).unwrap_err(),
+            "line 1: invalid unicode escape \"\\uzzzz\""
+        );
+    }
+
+    #[test]
+    fn arrays_and_inline_tables() {
+        assert_eq!(parse("a = [1, 2").unwrap_err(), "line 1: unterminated array");
+        assert_eq!(
+            parse("a = [1 2]").unwrap_err(),
+            "line 1: expected ',' or ']' in array, found '2'"
+        );
+        assert_eq!(parse("a = { b = 1").unwrap_err(), "line 1: unterminated inline table");
+        assert_eq!(parse("a = {}").unwrap()["a"], table(&[]));
+        assert_eq!(
+            parse("a = { b.c = 1, d = true }").unwrap()["a"],
+            table(&[("b", table(&[("c", Value::Integer(1))])), ("d", Value::Boolean(true))])
+        );
+    }
+
+    #[test]
+    fn duplicate_keys() {
+        assert_eq!(parse("a = 1\na = 2").unwrap_err(), "line 2: \"a\" is defined twice");
+        assert_eq!(parse("[t]\nb.c = 1\nb.c = 2").unwrap_err(), "line 3: \"b.c\" is defined twice");
+        assert_eq!(parse("a = { b = 1, b = 2 }").unwrap_err(), "line 1: \"b\" is defined twice");
+        assert_eq!(parse("a = 1\n[[a]]").unwrap_err(), "line 2: \"a\" is already defined");
+        assert_eq!(parse("a = 1\n[a.b]").unwrap_err(), "line 2: \"a\" is not a table");
+    }
+
+    #[test]
+    fn empty_keys() {
+        // The key path is never empty, so splitting off its last key cannot fail
+        assert_eq!(parse("[]").unwrap_err(), "line 1: expected a key, found ']'");
+        assert_eq!(parse("[[]]").unwrap_err(), "line 1: expected a key, found ']'");
+        assert_eq!(parse("= 1").unwrap_err(), "line 1: expected a key, found '='");
+        assert_eq!(parse("a. = 1").unwrap_err(), "line 1: expected a key, found '='");
+        assert_eq!(parse("a = { = 1 }").unwrap_err(), "line 1: expected a key, found '='");
+        assert_eq!(
+            parse("a = 1 b = 2").unwrap_err(),
+            "line 1: unexpected 'b' at the end of the line"
+        );
+        assert_eq!(parse("a = 0xzz").unwrap_err(), "line 1: invalid value \"0xzz\"");
+        assert_eq!(parse("a =").unwrap_err(), "line 1: expected a value, found end of file");
+    }
+
+    #[test]
+    fn merged_layers() {
+        let mut base = parse("[display]\nmode = \"temp\"\npoll = 750\n[[schedule]]\nfrom = \"07:00\"").unwrap();
+        let overlay = parse("[display]\nmode = \"usage\"\n[[schedule]]\nfrom = \"08:00\"\n[[schedule]]").unwrap();
+        merge(&mut base, overlay);
+
+        let display = base["display"].as_table().unwrap();
+        assert_eq!(get_str(display, "mode").unwrap(), Some("usage"));
+        assert_eq!(get_int(display, "poll", 1000).unwrap(), Some(750));
+        assert_eq!(base["schedule"].as_array().unwrap().len(), 2);
+    }
+}
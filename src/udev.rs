@@ -0,0 +1,46 @@
+//! Udev rules written by the `install-udev-rules` command, so the program can run without root.
+//!
+//! The rules give a group access to the hidraw nodes of the DeepCool devices and to the energy counter of the CPU,
+//! which is only readable by root by default.
+
+use std::{ffi::CString, fs, path::Path, process::Command};
+
+/// Default location of the rules
+pub const RULES_PATH: &str = "/etc/udev/rules.d/70-deepcool-digital.rules";
+
+/// Returns the rules giving `group` access to the devices of `vendor` and to the CPU energy counter.
+pub fn rules(vendor: u16, group: &str) -> String {
+    format!(
+        "# Written by deepcool-digital-linux install-udev-rules\n\
+         \n\
+         # DeepCool displays\n\
+         SUBSYSTEM==\"hidraw\", ATTRS{{idVendor}}==\"{vendor:04x}\", GROUP=\"{group}\", MODE=\"0660\"\n\
+         \n\
+         # CPU energy counter, for the power readings\n\
+         SUBSYSTEM==\"powercap\", KERNEL==\"intel-rapl:0\", \
+         RUN+=\"/bin/chgrp {group} /sys%p/energy_uj\", RUN+=\"/bin/chmod g+r /sys%p/energy_uj\"\n"
+    )
+}
+
+/// Creates the group if needed, writes the rules and applies them to the devices already plugged in.
+pub fn install(path: &Path, rules: &str, group: &str) -> Result<(), String> {
+    let name = CString::new(group).map_err(|_| format!("Invalid group name \"{group}\""))?;
+    if unsafe { libc::getgrnam(name.as_ptr()) }.is_null() {
+        run("groupadd", &["--system", group])?;
+    }
+    fs::write(path, rules).map_err(|err| format!("Failed to write {}: {err}", path.display()))?;
+    run("udevadm", &["control", "--reload-rules"])?;
+    run(
+        "udevadm",
+        &["trigger", "--subsystem-match=hidraw", "--subsystem-match=powercap"],
+    )
+}
+
+/// Runs a command with the given arguments.
+fn run(program: &str, arguments: &[&str]) -> Result<(), String> {
+    match Command::new(program).args(arguments).status() {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(format!("{program} {} failed: {status}", arguments.join(" "))),
+        Err(err) => Err(format!("Failed to run {program}: {err}")),
+    }
+}